@@ -1,6 +1,14 @@
-use crate::{consts::*, score::ScoreResource};
+use crate::{
+    consts::*,
+    lanes::Lane,
+    life::Life,
+    modifiers::{Modifiers, VisibilityModifier},
+    playfield::{Orientation, ScrollSpeed},
+    score::{reset_score, Judgement, ScoreResource},
+    settings::Settings,
+};
 use crate::{time::ControlledTime, types::*};
-use bevy::prelude::*;
+use bevy::{ecs::SystemParam, prelude::*, utils::HashMap};
 
 /// Spawns and controls arrows.
 pub struct ArrowsPlugin;
@@ -10,25 +18,78 @@ impl Plugin for ArrowsPlugin {
         app
             // Init resources
             .init_resource::<ArrowMaterialResource>()
+            .init_resource::<FadedMaterials>()
+            .init_resource::<Orientation>()
+            .init_resource::<ScrollSpeed>()
             .init_resource::<Events<CorrectArrowEvent>>()
+            .init_resource::<Events<MissArrowEvent>>()
             // Add systems
             .on_state_enter(
                 APP_STATE_STAGE,
                 AppState::Game,
                 setup_target_arrows.system(),
             )
+            .on_state_enter(APP_STATE_STAGE, AppState::Game, reset_score.system())
+            .on_state_enter(APP_STATE_STAGE, AppState::Game, sort_arrows.system())
             .on_state_update(APP_STATE_STAGE, AppState::Game, spawn_arrows.system())
             .on_state_update(APP_STATE_STAGE, AppState::Game, move_arrows.system())
-            .on_state_update(APP_STATE_STAGE, AppState::Game, despawn_arrows.system());
+            .on_state_update(APP_STATE_STAGE, AppState::Game, despawn_arrows.system())
+            .on_state_update(APP_STATE_STAGE, AppState::Game, update_holds.system())
+            .on_state_exit(APP_STATE_STAGE, AppState::Game, remove_arrows.system());
     }
 }
 
 /// Keeps the textures and materials for Arrows.
-struct ArrowMaterialResource {
+pub struct ArrowMaterialResource {
     red_texture: Handle<ColorMaterial>,
     blue_texture: Handle<ColorMaterial>,
     green_texture: Handle<ColorMaterial>,
-    border_texture: Handle<ColorMaterial>,
+    red_hold: Handle<ColorMaterial>,
+    blue_hold: Handle<ColorMaterial>,
+    green_hold: Handle<ColorMaterial>,
+    pub border_texture: Handle<ColorMaterial>,
+    pub mine_texture: Handle<ColorMaterial>,
+}
+
+impl ArrowMaterialResource {
+    /// Gets the correct material for an arrow with some speed. Slow arrows
+    /// are red, medium ones blue and fast ones green.
+    pub fn for_speed(&self, speed: Speed) -> Handle<ColorMaterial> {
+        match speed_color(speed) {
+            0 => self.red_texture.clone(),
+            1 => self.blue_texture.clone(),
+            _ => self.green_texture.clone(),
+        }
+    }
+
+    /// Like [`for_speed`](Self::for_speed), but mines get their own material.
+    pub fn for_arrow(&self, speed: Speed, mine: bool) -> Handle<ColorMaterial> {
+        if mine {
+            self.mine_texture.clone()
+        } else {
+            self.for_speed(speed)
+        }
+    }
+
+    /// Gets the material for the tail of a hold with some speed.
+    pub fn hold_for_speed(&self, speed: Speed) -> Handle<ColorMaterial> {
+        match speed_color(speed) {
+            0 => self.red_hold.clone(),
+            1 => self.blue_hold.clone(),
+            _ => self.green_hold.clone(),
+        }
+    }
+}
+
+/// Which of the three arrow colors a speed is closest to, slowest first.
+fn speed_color(speed: Speed) -> usize {
+    if speed.0 < (Speed::SLOW.0 + Speed::MEDIUM.0) / 2.0 {
+        0
+    } else if speed.0 < (Speed::MEDIUM.0 + Speed::FAST.0) / 2.0 {
+        1
+    } else {
+        2
+    }
 }
 
 impl FromResources for ArrowMaterialResource {
@@ -40,20 +101,102 @@ impl FromResources for ArrowMaterialResource {
         let green_handle = asset_server.load("images/arrow_green.png");
         let blue_handle = asset_server.load("images/arrow_blue.png");
         let border_handle = asset_server.load("images/arrow_border.png");
+        let mine_handle = asset_server.load("images/mine.png");
 
         ArrowMaterialResource {
             red_texture: materials.add(red_handle.into()),
             blue_texture: materials.add(blue_handle.into()),
             green_texture: materials.add(green_handle.into()),
+            red_hold: materials.add(Color::rgba(0.9, 0.2, 0.2, 0.5).into()),
+            blue_hold: materials.add(Color::rgba(0.2, 0.4, 0.9, 0.5).into()),
+            green_hold: materials.add(Color::rgba(0.2, 0.8, 0.3, 0.5).into()),
             border_texture: materials.add(border_handle.into()),
+            mine_texture: materials.add(mine_handle.into()),
+        }
+    }
+}
+
+/// How many steps arrows fade through for visibility modifiers.
+const FADE_STEPS: usize = 20;
+
+/// Faded versions of the arrow materials, made as they're needed and shared
+/// between every arrow.
+#[derive(Default)]
+pub struct FadedMaterials(HashMap<(Handle<ColorMaterial>, usize), Handle<ColorMaterial>>);
+
+impl FadedMaterials {
+    /// A material faded to some alpha, rounded to the nearest step.
+    fn get(
+        &mut self,
+        material: &Handle<ColorMaterial>,
+        alpha: f32,
+        materials: &mut Assets<ColorMaterial>,
+    ) -> Handle<ColorMaterial> {
+        let step = (alpha.clamp(0.0, 1.0) * FADE_STEPS as f32).round() as usize;
+        if step == FADE_STEPS {
+            return material.clone();
         }
+
+        self.0
+            .entry((material.clone(), step))
+            .or_insert_with(|| {
+                let (mut color, texture) = materials
+                    .get(material)
+                    .map_or((Color::WHITE, None), |material| {
+                        (material.color, material.texture.clone())
+                    });
+                color.set_a(step as f32 / FADE_STEPS as f32);
+                materials.add(ColorMaterial { color, texture })
+            })
+            .clone()
     }
 }
 
 /// Arrow component.
-struct Arrow {
-    speed: Speed,
-    direction: Directions,
+pub struct Arrow {
+    /// How fast the arrow moves, in pixels a second, before the song's scroll
+    /// speed changes are applied.
+    velocity: f32,
+    lane: Lane,
+    /// When the arrow should be hit, in seconds.
+    click_time: f64,
+    /// The song's scroll position when the arrow should be hit.
+    click_position: f64,
+    /// How far the arrow has dropped out of its lane after being missed.
+    fall: f32,
+    /// How far along its lane the arrow was drawn when it got missed, if it
+    /// has been.
+    missed_at: Option<f32>,
+    /// The tail that follows this arrow, if it's a hold.
+    hold: Option<Entity>,
+    mine: bool,
+    /// The arrow's material before any fading.
+    material: Handle<ColorMaterial>,
+}
+
+/// How wide hold tails are.
+const HOLD_WIDTH: f32 = 40.0;
+
+/// The tail of a hold arrow. Gets drawn from its arrow (or the target, once the
+/// arrow has been hit) back to where the hold ends.
+pub struct HoldTail {
+    /// How fast the tail moves, in pixels a second.
+    velocity: f32,
+    lane: Lane,
+    /// The song's scroll positions when the arrow should be hit and when the
+    /// hold ends.
+    click_position: f64,
+    end_position: f64,
+    /// When the hold can be let go of, in seconds.
+    end_time: f64,
+}
+
+/// Marks a hold tail whose arrow has been hit, so its key needs to stay down.
+struct Holding;
+
+/// Sorts the song's arrows by when they spawn at the player's scroll speed.
+fn sort_arrows(mut song_config: ResMut<SongConfig>, scroll: Res<ScrollSpeed>) {
+    song_config.sort_arrows(*scroll);
 }
 
 /// Spawns arrows.
@@ -61,40 +204,91 @@ fn spawn_arrows(
     commands: &mut Commands,
     mut song_config: ResMut<SongConfig>,
     materials: Res<ArrowMaterialResource>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+    mut faded: ResMut<FadedMaterials>,
+    settings: Settings,
     time: Res<ControlledTime>,
 ) {
-    // We get the current time since startup (secs) and the time since the last
-    // iteration (secs_last). This lets us check if there are any arrows that
-    // should spawn in the current window.
+    let Settings {
+        orientation,
+        scroll,
+        modifiers,
+        ..
+    } = settings;
+
+    // We get the current time since startup (secs). This lets us check if
+    // there are any arrows that should have spawned by now.
 
     // Song starts whenever we configure it to start, so subtract that value.
     let secs = time.seconds_since_startup() - SONG_START_DELAY;
-    let secs_last = secs - time.delta_seconds_f64();
+    // Arrows go by the song's scroll position instead of time, so scroll
+    // speed changes can speed them up or slow them down.
+    let position = song_config.scroll.position(secs);
 
     // Counts the number of arrows that need to be spawned and removed from the
     // list.
     let mut spawn_counter = 0;
+    let lanes = song_config.lanes;
 
     for arrow in &song_config.arrows {
         // List is sorted by spawn time, so we can just check until an item fails.
 
-        // Check if an arrow should be spawned at any point between the last frame
-        // and this frame:
-        if secs_last < arrow.spawn_time && arrow.spawn_time < secs {
+        // Check if an arrow should have spawned by now. There's no need to
+        // check it didn't spawn before the last frame too, since spawned
+        // arrows get taken off the front of the list.
+        if arrow.spawn_position(*scroll) < position {
             spawn_counter += 1;
-
-            // Get the correct material according to speed:
-            let material = match arrow.speed {
-                Speed::Slow => materials.red_texture.clone(),
-                Speed::Medium => materials.blue_texture.clone(),
-                Speed::Fast => materials.green_texture.clone(),
+            let velocity = scroll.speed(arrow.speed);
+
+            // Arrows that should have spawned a while ago (like when starting
+            // partway through a song) start partway along their path.
+            let along = along_at(arrow.click_position, position, velocity);
+
+            // Get the correct material according to speed, faded if a
+            // modifier says so.
+            let base_material = materials.for_arrow(arrow.speed, arrow.mine);
+            let material = if modifiers.visibility != VisibilityModifier::Off {
+                faded.get(
+                    &base_material,
+                    modifiers.alpha(TARGET_POSITION - along),
+                    &mut color_materials,
+                )
+            } else {
+                base_material.clone()
             };
+            let mut transform = Transform::from_translation(orientation.translation(
+                along,
+                lanes.y(arrow.lane),
+                1.0,
+            ));
 
-            let mut transform =
-                Transform::from_translation(Vec3::new(SPAWN_POSITION, arrow.direction.y(), 1.0));
-
-            // Rotate the arrow according to its direction:
-            transform.rotate(Quat::from_rotation_z(arrow.direction.rotation()));
+            // Rotate the arrow according to its direction, and shrink it to
+            // fit its lane:
+            transform.rotate(Quat::from_rotation_z(lanes.rotation(arrow.lane)));
+            transform.scale = Vec3::splat(lanes.scale());
+
+            // Holds get a tail, which gets sized and placed by `update_holds`
+            let hold = arrow.hold.and_then(|length| {
+                commands
+                    .spawn(SpriteBundle {
+                        material: materials.hold_for_speed(arrow.speed),
+                        sprite: Sprite::new(orientation.size(0.0, HOLD_WIDTH * lanes.scale())),
+                        transform: Transform::from_translation(orientation.translation(
+                            along,
+                            lanes.y(arrow.lane),
+                            0.5,
+                        )),
+                        ..Default::default()
+                    })
+                    .with(HoldTail {
+                        velocity,
+                        lane: arrow.lane,
+                        click_position: arrow.click_position,
+                        end_position: song_config.scroll.position(arrow.click_time + length),
+                        end_time: arrow.click_time + length,
+                    })
+                    .current_entity()
+            });
 
             commands
                 .spawn(SpriteBundle {
@@ -104,8 +298,15 @@ fn spawn_arrows(
                     ..Default::default()
                 })
                 .with(Arrow {
-                    speed: arrow.speed,
-                    direction: arrow.direction,
+                    velocity,
+                    lane: arrow.lane,
+                    click_time: arrow.click_time,
+                    click_position: arrow.click_position,
+                    fall: 0.0,
+                    missed_at: None,
+                    hold,
+                    mine: arrow.mine,
+                    material: base_material,
                 });
         } else {
             break;
@@ -118,41 +319,79 @@ fn spawn_arrows(
     }
 }
 
+/// How far along its path something moving at `velocity` is, when it reaches
+/// its target at scroll position `target` and the song is at `position`.
+fn along_at(target: f64, position: f64, velocity: f32) -> f32 {
+    TARGET_POSITION - (target - position) as f32 * velocity
+}
+
 /// Moves the arrows forwards.
-fn move_arrows(time: Res<ControlledTime>, mut query: Query<(&mut Transform, &Arrow)>) {
-    for (mut transform, arrow) in query.iter_mut() {
-        transform.translation.x += time.delta_seconds() * arrow.speed.value();
+fn move_arrows(
+    time: Res<ControlledTime>,
+    song_config: Res<SongConfig>,
+    orientation: Res<Orientation>,
+    modifiers: Res<Modifiers>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut faded: ResMut<FadedMaterials>,
+    mut query: Query<(&mut Transform, &mut Arrow, &mut Handle<ColorMaterial>)>,
+) {
+    let lanes = song_config.lanes;
+    let secs = time.seconds_since_startup() - SONG_START_DELAY;
+    let position = song_config.scroll.position(secs);
+
+    for (mut transform, mut arrow, mut material) in query.iter_mut() {
+        let along = along_at(arrow.click_position, position, arrow.velocity);
 
-        let distance_after_target = transform.translation.x - (TARGET_POSITION + THRESHOLD);
+        if modifiers.visibility != VisibilityModifier::Off {
+            *material = faded.get(
+                &arrow.material,
+                modifiers.alpha(TARGET_POSITION - along),
+                &mut materials,
+            );
+        }
+
+        let distance_after_target = arrow.missed_at.map_or(0.0, |missed_at| along - missed_at);
         if distance_after_target >= 0.02 {
-            // Move the arrow down if it's past its target
-            transform.translation.y -= time.delta_seconds() * distance_after_target * 2.0;
+            // Drop the arrow out of its lane once it's been missed
+            arrow.fall += time.delta_seconds() * distance_after_target * 2.0;
 
             // Change the scale according to how far away the arrow is
             let scale = ((100.0 - distance_after_target / 3.0) / 100.0).max(0.2);
-            transform.scale = Vec3::splat(scale);
+            transform.scale = Vec3::splat(scale * lanes.scale());
 
             // Rotate the arrow according to distance and speed
             transform.rotate(Quat::from_rotation_z(
-                -distance_after_target * arrow.speed.multiplier() / 2500.0,
+                -distance_after_target * arrow.velocity / BASE_SPEED * orientation.spin() / 2500.0,
             ));
         }
+
+        let place = orientation.place(along, lanes.y(arrow.lane) - arrow.fall);
+        transform.translation.x = place.x;
+        transform.translation.y = place.y;
     }
 }
 
 /// The locations that the players have to hit the arrows at.
 struct TargetArrow;
 
-/// Setup the target arrows.
-fn setup_target_arrows(commands: &mut Commands, materials: Res<ArrowMaterialResource>) {
-    use Directions::*;
-    const DIRECTIONS: [Directions; 4] = [Up, Down, Left, Right];
+/// Setup the target arrows, one for each of the song's lanes.
+fn setup_target_arrows(
+    commands: &mut Commands,
+    song_config: Res<SongConfig>,
+    materials: Res<ArrowMaterialResource>,
+    orientation: Res<Orientation>,
+) {
+    let lanes = song_config.lanes;
 
-    for direction in DIRECTIONS.iter() {
-        let mut transform =
-            Transform::from_translation(Vec3::new(TARGET_POSITION, direction.y(), 1.0));
+    for lane in lanes.iter() {
+        let mut transform = Transform::from_translation(orientation.translation(
+            TARGET_POSITION,
+            lanes.y(lane),
+            1.0,
+        ));
 
-        transform.rotate(Quat::from_rotation_z(direction.rotation()));
+        transform.rotate(Quat::from_rotation_z(lanes.rotation(lane)));
+        transform.scale = Vec3::splat(lanes.scale());
 
         commands
             .spawn(SpriteBundle {
@@ -168,41 +407,186 @@ fn setup_target_arrows(commands: &mut Commands, materials: Res<ArrowMaterialReso
 /// An event that gets sent when the user correctly hits an arrow.
 #[derive(Debug)]
 pub struct CorrectArrowEvent {
-    pub direction: Directions,
+    pub lane: Lane,
     pub points: usize,
+    /// How early (negative) or late (positive) the hit was, in seconds.
+    pub offset: f64,
+}
+
+/// An event that gets sent when the user misses an arrow, lets go of a hold
+/// too early or hits a mine.
+#[derive(Debug)]
+pub struct MissArrowEvent {
+    pub lane: Lane,
+}
+
+/// Everything that has to know when an arrow gets hit or missed.
+#[derive(SystemParam)]
+pub struct Judge<'a> {
+    score: ResMut<'a, ScoreResource>,
+    life: ResMut<'a, Life>,
+    correct_events: ResMut<'a, Events<CorrectArrowEvent>>,
+    miss_events: ResMut<'a, Events<MissArrowEvent>>,
+}
+
+impl<'a> Judge<'a> {
+    /// Scores an arrow that got hit `offset` seconds late (or early, if
+    /// negative).
+    fn hit(&mut self, arrow: &Arrow, offset: f64) {
+        let points = self
+            .score
+            .increase_correct(arrow.click_time, arrow.lane, offset);
+        self.life.hit(points);
+        self.correct_events.send(CorrectArrowEvent {
+            lane: arrow.lane,
+            points,
+            offset,
+        });
+    }
+
+    /// Scores a miss, `secs` seconds into the song.
+    fn miss(&mut self, time: f64, lane: Lane, judgement: Judgement, secs: f64) {
+        self.score.increase_fails(time, lane, judgement);
+        self.life.miss(secs);
+        self.miss_events.send(MissArrowEvent { lane });
+    }
+
+    /// Scores a mine that got hit, `secs` seconds into the song. Hitting one
+    /// hurts as much as a miss.
+    fn hit_mine(&mut self, arrow: &Arrow, secs: f64) {
+        self.score.hit_mine(arrow.click_time, arrow.lane);
+        self.life.miss(secs);
+        self.miss_events.send(MissArrowEvent { lane: arrow.lane });
+    }
+
+    /// Scores a mine that got left alone.
+    fn avoid_mine(&mut self, arrow: &Arrow) {
+        self.score.avoid_mine(arrow.click_time, arrow.lane);
+    }
 }
 
 /// Despawns the arrows when they reach the end if the correct button is clicked.
+/// Mines work the other way around, hurting when they get hit and counting as
+/// a success when they get left alone.
 fn despawn_arrows(
     commands: &mut Commands,
-    query: Query<(Entity, &Transform, &Arrow)>,
+    mut query: Query<(Entity, &mut Arrow)>,
     keyboard_input: Res<Input<KeyCode>>,
-    mut score: ResMut<ScoreResource>,
-    mut correct_arrow_events: ResMut<Events<CorrectArrowEvent>>,
+    time: Res<ControlledTime>,
+    song_config: Res<SongConfig>,
+    mut judge: Judge,
 ) {
-    for (entity, transform, arrow) in query.iter() {
-        let pos = transform.translation.x;
+    let lanes = song_config.lanes;
+    let secs = time.seconds_since_startup() - SONG_START_DELAY;
+    let position = song_config.scroll.position(secs);
+
+    for (entity, mut arrow) in query.iter_mut() {
+        // Judged by time rather than where the arrow is drawn, so scroll
+        // speed only changes how the arrows look.
+        let offset = secs - arrow.click_time;
 
-        // Check if the arrow is inside clicking threshold
-        if (TARGET_POSITION - THRESHOLD..=TARGET_POSITION + THRESHOLD).contains(&pos)
-            && arrow.direction.key_just_pressed(&keyboard_input)
-        {
+        // Check if the arrow is inside the hit window
+        if offset.abs() <= HIT_WINDOW && lanes.key_just_pressed(arrow.lane, &keyboard_input) {
             commands.despawn(entity);
 
-            let points = score.increase_correct(TARGET_POSITION - pos);
+            if arrow.mine {
+                judge.hit_mine(&arrow, secs);
+                continue;
+            }
 
-            // Send an event:
-            correct_arrow_events.send(CorrectArrowEvent {
-                direction: arrow.direction,
-                points,
-            });
+            // Holds need their key kept down until the tail is done
+            if let Some(tail) = arrow.hold {
+                commands.insert_one(tail, Holding);
+            }
+
+            judge.hit(&arrow, offset);
+            continue;
+        }
+
+        let along = along_at(arrow.click_position, position, arrow.velocity);
+
+        // Arrows that get past the window are missed
+        if offset > HIT_WINDOW && arrow.missed_at.is_none() {
+            arrow.missed_at = Some(along);
+
+            if arrow.mine {
+                judge.avoid_mine(&arrow);
+            } else {
+                // A missed hold can't be held anymore
+                if let Some(tail) = arrow.hold {
+                    commands.despawn(tail);
+                }
+
+                judge.miss(arrow.click_time, arrow.lane, Judgement::Miss, secs);
+            }
         }
 
         // Despawn arrows after they leave the screen
-        if pos >= 2.0 * TARGET_POSITION {
+        if along >= 2.0 * TARGET_POSITION {
             commands.despawn(entity);
+        }
+    }
+}
 
-            score.increase_fails();
+/// Stretches hold tails between their arrow and their end, and checks that
+/// holds that have been hit are kept down until they're done. Letting go early
+/// counts as a miss.
+fn update_holds(
+    commands: &mut Commands,
+    time: Res<ControlledTime>,
+    keyboard_input: Res<Input<KeyCode>>,
+    song_config: Res<SongConfig>,
+    orientation: Res<Orientation>,
+    mut judge: Judge,
+    mut query: Query<(
+        Entity,
+        &HoldTail,
+        &mut Transform,
+        &mut Sprite,
+        Option<&Holding>,
+    )>,
+) {
+    let secs = time.seconds_since_startup() - SONG_START_DELAY;
+    let position = song_config.scroll.position(secs);
+
+    for (entity, tail, mut transform, mut sprite, holding) in query.iter_mut() {
+        if holding.is_some() {
+            if !song_config.lanes.key_pressed(tail.lane, &keyboard_input) {
+                commands.despawn(entity);
+                judge.miss(secs, tail.lane, Judgement::HoldDropped, secs);
+                continue;
+            }
+
+            if secs >= tail.end_time {
+                commands.despawn(entity);
+                continue;
+            }
         }
+
+        // Held tails stay stuck to the target and get eaten up as time passes
+        let front = if holding.is_some() {
+            TARGET_POSITION
+        } else {
+            along_at(tail.click_position, position, tail.velocity)
+        };
+        let back = along_at(tail.end_position, position, tail.velocity).min(front);
+
+        let lanes = song_config.lanes;
+        sprite.size = orientation.size(front - back, HOLD_WIDTH * lanes.scale());
+        let center = orientation.place((front + back) / 2.0, lanes.y(tail.lane));
+        transform.translation.x = center.x;
+        transform.translation.y = center.y;
+    }
+}
+
+/// Removes all arrows, hold tails and target arrows when leaving a game.
+fn remove_arrows(
+    commands: &mut Commands,
+    arrows: Query<Entity, With<Arrow>>,
+    tails: Query<Entity, With<HoldTail>>,
+    targets: Query<Entity, With<TargetArrow>>,
+) {
+    for entity in arrows.iter().chain(tails.iter()).chain(targets.iter()) {
+        commands.despawn(entity);
     }
 }
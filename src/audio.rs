@@ -1,21 +1,196 @@
-use crate::{consts::*, time::ControlledTime, types::SongConfig};
-use bevy::prelude::*;
+use crate::{
+    consts::*,
+    time::{ControlledTime, PlaybackRate},
+    types::SongConfig,
+};
+use bevy::{prelude::*, utils::Duration};
+use rodio::{OutputStream, OutputStreamHandle, Sink, Source};
+use std::{
+    io::Cursor,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
 
 /// Handles all of the game's audio.
 pub struct AudioPlugin;
 
 impl Plugin for AudioPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.on_state_update(APP_STATE_STAGE, AppState::Game, start_song.system());
+        app.init_resource::<SongPlayer>()
+            .on_state_update(APP_STATE_STAGE, AppState::Game, start_song.system())
+            .on_state_exit(APP_STATE_STAGE, AppState::Game, stop_song.system());
     }
 }
 
-fn start_song(audio: Res<Audio>, time: Res<ControlledTime>, config: Res<SongConfig>) {
-    // Song starts whenever the first arrow spawns.
-    let secs = time.seconds_since_startup();
-    let secs_last = secs - time.delta_seconds_f64();
+/// Plays songs straight through rodio instead of through Bevy's [`Audio`], so
+/// that they can be started from any point and stopped again.
+pub struct SongPlayer {
+    stream_handle: Option<OutputStreamHandle>,
+    sink: Option<Sink>,
+    rate: f64,
+}
+
+impl Default for SongPlayer {
+    fn default() -> Self {
+        // The output stream can't be sent between threads, so it lives on its
+        // own thread for as long as the game runs.
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || match OutputStream::try_default() {
+            Ok((_stream, handle)) => {
+                let _ = sender.send(Some(handle));
+                loop {
+                    thread::park();
+                }
+            }
+            Err(err) => {
+                warn!("Couldn't open an audio output stream: {}", err);
+                let _ = sender.send(None);
+            }
+        });
+
+        Self {
+            stream_handle: receiver.recv().ok().flatten(),
+            sink: None,
+            rate: 1.0,
+        }
+    }
+}
+
+impl SongPlayer {
+    /// Starts playing a song from some position, in seconds. Stops whatever was
+    /// playing before.
+    pub fn play(&mut self, source: &AudioSource, position: f64) {
+        self.stop();
+
+        let stream_handle = match &self.stream_handle {
+            Some(stream_handle) => stream_handle,
+            None => return,
+        };
+
+        let decoder = match rodio::Decoder::new(Cursor::new(source.bytes.clone())) {
+            Ok(decoder) => decoder,
+            Err(err) => {
+                warn!("Couldn't decode song: {}", err);
+                return;
+            }
+        };
+
+        match Sink::try_new(stream_handle) {
+            Ok(sink) => {
+                sink.append(
+                    decoder
+                        .skip_duration(Duration::from_secs_f64(position.max(0.0)))
+                        .speed(self.rate as f32),
+                );
+                self.sink = Some(sink);
+            }
+            Err(err) => warn!("Couldn't play song: {}", err),
+        }
+    }
+
+    /// Changes how fast songs play. Takes effect the next time a song is
+    /// started or seeked.
+    pub fn set_rate(&mut self, rate: f64) {
+        self.rate = rate;
+    }
+
+    /// Jumps to some position in the current song, in seconds. Stays paused if
+    /// the song was paused.
+    pub fn seek(&mut self, source: &AudioSource, position: f64) {
+        let paused = self.is_paused();
+        self.play(source, position);
+        if paused {
+            self.pause();
+        }
+    }
+
+    /// Pauses the current song.
+    pub fn pause(&self) {
+        if let Some(sink) = &self.sink {
+            sink.pause();
+        }
+    }
+
+    /// Resumes the current song if it's paused.
+    pub fn resume(&self) {
+        if let Some(sink) = &self.sink {
+            sink.play();
+        }
+    }
+
+    /// Returns true if the current song is paused.
+    pub fn is_paused(&self) -> bool {
+        self.sink.as_ref().is_some_and(|sink| sink.is_paused())
+    }
+
+    /// Stops playing the current song.
+    pub fn stop(&mut self) {
+        if let Some(sink) = self.sink.take() {
+            sink.stop();
+        }
+    }
+
+    /// Returns true if a song has been started and not stopped since.
+    pub fn is_playing(&self) -> bool {
+        self.sink.is_some()
+    }
+}
+
+/// Works out how long a song is. This means decoding the whole thing, so it
+/// happens on a background thread.
+#[derive(Debug, Default)]
+pub struct SongLength {
+    length: Arc<Mutex<Option<f64>>>,
+    measuring: bool,
+}
 
-    if secs_last <= SONG_START_DELAY && SONG_START_DELAY <= secs {
-        audio.play(config.song_audio.clone());
+impl SongLength {
+    /// Starts measuring a song, if it isn't already being measured.
+    pub fn measure(&mut self, source: &AudioSource) {
+        if self.measuring {
+            return;
+        }
+        self.measuring = true;
+
+        let bytes = source.bytes.clone();
+        let length = self.length.clone();
+        thread::spawn(move || {
+            if let Ok(decoder) = rodio::Decoder::new(Cursor::new(bytes)) {
+                let samples_per_second = decoder.channels() as f64 * decoder.sample_rate() as f64;
+                let samples = decoder.count() as f64;
+                *length.lock().unwrap() = Some(samples / samples_per_second);
+            }
+        });
     }
+
+    /// The song's length in seconds, once it's been measured.
+    pub fn get(&self) -> Option<f64> {
+        *self.length.lock().unwrap()
+    }
+}
+
+/// Starts the song as soon as it's time for it to play and its audio has
+/// loaded.
+fn start_song(
+    mut player: ResMut<SongPlayer>,
+    time: Res<ControlledTime>,
+    config: Res<SongConfig>,
+    audio_sources: Res<Assets<AudioSource>>,
+    rate: Res<PlaybackRate>,
+) {
+    let secs = time.seconds_since_startup() - SONG_START_DELAY;
+
+    if secs < 0.0 || player.is_playing() {
+        return;
+    }
+
+    if let Some(source) = audio_sources.get(&config.song_audio) {
+        player.set_rate(rate.0);
+        player.play(source, secs);
+    }
+}
+
+/// Stops the song.
+pub fn stop_song(mut player: ResMut<SongPlayer>) {
+    player.stop();
 }
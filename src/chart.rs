@@ -0,0 +1,167 @@
+use crate::{
+    consts::*,
+    import::{parse_chart, CHART_EXTENSIONS},
+    modifiers::Modifiers,
+    playfield::ScrollSpeed,
+    time::ControlledTime,
+    types::SongConfig,
+};
+use bevy::{
+    asset::{AssetLoader, AssetPath, LoadContext, LoadedAsset},
+    ecs::SystemParam,
+    prelude::*,
+    utils::BoxedFuture,
+};
+use std::{path::PathBuf, str};
+
+/// Loads charts through the asset server, and reloads the one being played
+/// whenever its file changes.
+pub struct ChartPlugin;
+
+impl Plugin for ChartPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_asset::<SongConfig>()
+            .init_asset_loader::<ChartLoader>()
+            .init_resource::<ChartHandle>()
+            .add_startup_system(watch_for_changes.system())
+            .on_state_update(APP_STATE_STAGE, AppState::Game, reload_chart.system());
+    }
+}
+
+/// Turns chart files into a [`SongConfig`] for each difficulty. The song's
+/// audio is loaded as a dependency, relative to the chart.
+#[derive(Default)]
+pub struct ChartLoader;
+
+impl AssetLoader for ChartLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let path = load_context.path();
+            let ext = path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .unwrap_or_default();
+            let chart = parse_chart(str::from_utf8(bytes)?, ext)?;
+
+            let audio_path = AssetPath::new(
+                path.parent()
+                    .map_or_else(PathBuf::new, |parent| parent.to_path_buf())
+                    .join(&chart.filename),
+                None,
+            );
+            let song_audio: Handle<AudioSource> = load_context.get_handle(audio_path.clone());
+            let file = path
+                .strip_prefix("songs")
+                .unwrap_or(path)
+                .to_string_lossy()
+                .replace('\\', "/");
+
+            // Each difficulty gets labeled with its index, like
+            // `songs/song.toml#1`. The first one is the default.
+            for (i, difficulty) in chart.difficulties.iter().enumerate() {
+                let mut song_config =
+                    SongConfig::with_audio(&chart, difficulty, song_audio.clone());
+                song_config.file = Some(file.clone());
+
+                if i == 0 {
+                    load_context.set_default_asset(
+                        LoadedAsset::new(song_config.clone()).with_dependency(audio_path.clone()),
+                    );
+                }
+                load_context.set_labeled_asset(
+                    &i.to_string(),
+                    LoadedAsset::new(song_config).with_dependency(audio_path.clone()),
+                );
+            }
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &CHART_EXTENSIONS
+    }
+}
+
+/// The chart file being played. Playtests play charts straight out of the
+/// editor, so they don't have one.
+#[derive(Debug, Default)]
+pub struct ChartHandle(pub Option<Handle<SongConfig>>);
+
+/// The song being played, along with the chart file it came from.
+#[derive(SystemParam)]
+pub struct PlayingChart<'a> {
+    pub song_config: ResMut<'a, SongConfig>,
+    charts: Res<'a, Assets<SongConfig>>,
+    handle: Res<'a, ChartHandle>,
+    pub scroll: Res<'a, ScrollSpeed>,
+    modifiers: Res<'a, Modifiers>,
+}
+
+impl<'a> PlayingChart<'a> {
+    /// Returns true if a chart file is the one being played.
+    pub fn is(&self, handle: &Handle<SongConfig>) -> bool {
+        self.handle.0.as_ref() == Some(handle)
+    }
+
+    /// Sets the song back up from the latest version of its chart file, with
+    /// modifiers applied and arrows sorted. Returns false if it isn't being
+    /// played from a file, or the file isn't loaded.
+    pub fn reset(&mut self) -> bool {
+        let chart = match self
+            .handle
+            .0
+            .as_ref()
+            .and_then(|handle| self.charts.get(handle))
+        {
+            Some(chart) => chart,
+            None => return false,
+        };
+
+        *self.song_config = chart.clone();
+        self.modifiers.apply(&mut self.song_config);
+        self.song_config.sort_arrows(*self.scroll);
+        true
+    }
+}
+
+/// Lets chart files get reloaded when they're saved.
+fn watch_for_changes(asset_server: Res<AssetServer>) {
+    if let Err(err) = asset_server.watch_for_changes() {
+        warn!("Couldn't watch assets for changes: {:?}", err);
+    }
+}
+
+/// Swaps in the new version of a chart when its file changes partway through a
+/// song. Arrows that are already on screen are left alone.
+fn reload_chart(
+    mut event_reader: Local<EventReader<AssetEvent<SongConfig>>>,
+    events: Res<Events<AssetEvent<SongConfig>>>,
+    time: Res<ControlledTime>,
+    mut chart: PlayingChart,
+) {
+    for event in event_reader.iter(&events) {
+        let handle = match event {
+            AssetEvent::Modified { handle } => handle,
+            _ => continue,
+        };
+        if !chart.is(handle) {
+            continue;
+        }
+
+        if chart.reset() {
+            let secs = time.seconds_since_startup() - SONG_START_DELAY;
+            let scroll = *chart.scroll;
+            let song_config = &mut chart.song_config;
+            let position = song_config.scroll.position(secs);
+            song_config
+                .arrows
+                .retain(|arrow| arrow.spawn_position(scroll) >= position);
+
+            info!("Reloaded chart {}", song_config.name);
+        }
+    }
+}
@@ -7,8 +7,14 @@ pub const SPAWN_POSITION: f32 = -400.0;
 /// X coordinate value where the arrows should be clicked.
 pub const TARGET_POSITION: f32 = 200.0;
 
-/// Margin of error for clicking an arrow.
-pub const THRESHOLD: f32 = 20.0;
+/// How early or late an arrow can be hit and still count, in seconds.
+pub const HIT_WINDOW: f64 = 0.1;
+
+/// How early or late hits can be to get each grade, in seconds. Anything else
+/// inside the [`HIT_WINDOW`] is bad.
+pub const PERFECT_WINDOW: f64 = 0.01;
+pub const GREAT_WINDOW: f64 = 0.03;
+pub const GOOD_WINDOW: f64 = 0.06;
 
 /// Total distance travelled by an arrow, from spawn to target.
 pub const DISTANCE: f32 = TARGET_POSITION - SPAWN_POSITION;
@@ -17,12 +23,32 @@ pub const DISTANCE: f32 = TARGET_POSITION - SPAWN_POSITION;
 pub const APP_STATE_STAGE: &str = "app_state_stage";
 
 /// Possible app states
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum AppState {
     Menu,
     Game,
     MakeMap,
+    EditChart,
+    Results,
 }
 
 /// How long to wait before starting a song after entering a game, in seconds.
 pub const SONG_START_DELAY: f64 = 5.0;
+
+/// How long to wait after the last arrow before showing the results, in
+/// seconds.
+pub const SONG_END_DELAY: f64 = 3.0;
+
+/// X coordinate value of the chart editor's playhead.
+pub const EDITOR_PLAYHEAD_POSITION: f32 = -200.0;
+
+/// How many pixels one second of a song takes up in the chart editor.
+pub const EDITOR_PIXELS_PER_SECOND: f32 = 300.0;
+
+/// How long to wait before reaching the starting point of a playtest, in
+/// seconds.
+pub const PLAYTEST_LEAD_IN: f64 = 2.0;
+
+/// How long to wait before reaching the start of a practice loop each time it
+/// comes back around, in seconds.
+pub const PRACTICE_LEAD_IN: f64 = 2.0;
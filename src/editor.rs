@@ -0,0 +1,1149 @@
+use crate::{
+    arrows::ArrowMaterialResource,
+    consts::*,
+    history::{EditCommand, History},
+    import::{export_chart, EXPORT_EXTENSIONS},
+    lanes::{Lane, Lanes},
+    playtest::start_playtest,
+    timing::{Timing, DEFAULT_BPM},
+    types::{ArrowTimeToml, DifficultyToml, SongConfig, SongConfigToml, Speed, DEFAULT_DIFFICULTY},
+};
+use bevy::{
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    prelude::*,
+};
+use std::path::Path;
+
+/// A timeline chart editor. Lays a chart's arrows out over a beat grid, and
+/// lets them be added, removed, moved and copied around with the mouse and
+/// keyboard.
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<EditorMaterials>()
+            .init_resource::<EditorState>()
+            .on_state_enter(APP_STATE_STAGE, AppState::EditChart, setup_editor.system())
+            .on_state_update(APP_STATE_STAGE, AppState::EditChart, scroll_editor.system())
+            .on_state_update(APP_STATE_STAGE, AppState::EditChart, editor_mouse.system())
+            .on_state_update(
+                APP_STATE_STAGE,
+                AppState::EditChart,
+                editor_keyboard.system(),
+            )
+            .on_state_update(
+                APP_STATE_STAGE,
+                AppState::EditChart,
+                playtest_from_editor.system(),
+            )
+            .on_state_update(
+                APP_STATE_STAGE,
+                AppState::EditChart,
+                rebuild_editor_arrows.system(),
+            )
+            .on_state_update(
+                APP_STATE_STAGE,
+                AppState::EditChart,
+                position_editor_arrows.system(),
+            )
+            .on_state_update(
+                APP_STATE_STAGE,
+                AppState::EditChart,
+                position_grid_lines.system(),
+            )
+            .on_state_update(
+                APP_STATE_STAGE,
+                AppState::EditChart,
+                update_selection_box.system(),
+            )
+            .on_state_update(
+                APP_STATE_STAGE,
+                AppState::EditChart,
+                update_editor_text.system(),
+            )
+            .on_state_exit(
+                APP_STATE_STAGE,
+                AppState::EditChart,
+                despawn_editor.system(),
+            );
+    }
+}
+
+/// Beat subdivisions that arrows can snap to.
+const SNAPS: [u32; 8] = [1, 2, 3, 4, 6, 8, 12, 16];
+
+/// How many grid lines get spawned. Should be enough to fill the screen.
+const GRID_LINES: usize = 96;
+
+/// Size of an arrow in the editor, with four lanes.
+const EDITOR_ARROW_SIZE: f32 = 80.0;
+
+/// How much 9 and 0 change the speed of the selected arrows by.
+const SPEED_STEP: f64 = 0.05;
+
+/// Names given to new difficulties, in order.
+const DIFFICULTY_NAMES: [&str; 4] = ["Easy", DEFAULT_DIFFICULTY, "Hard", "Expert"];
+
+/// What the mouse is currently dragging.
+#[derive(Debug, Clone, Copy)]
+enum Drag {
+    /// Moving the selected arrows around.
+    Move { start: Vec2 },
+    /// Drawing a box to select arrows with.
+    Select { start: Vec2 },
+}
+
+/// Everything about the chart currently being edited.
+pub struct EditorState {
+    /// File the chart was opened from, relative to `assets/songs`.
+    pub path: String,
+    pub chart: SongConfigToml,
+    /// Index of the difficulty being edited.
+    pub difficulty: usize,
+    pub timing: Timing,
+    /// Arrows that are currently selected.
+    selection: Vec<ArrowTimeToml>,
+    /// Copied arrows, with times relative to the first copied arrow.
+    clipboard: Vec<ArrowTimeToml>,
+    /// Song time at the playhead, in seconds.
+    pub view_time: f64,
+    /// Index into [`SNAPS`].
+    snap: usize,
+    drag: Option<Drag>,
+    /// Where the mouse is, in world coordinates.
+    cursor: Vec2,
+    /// Edits that can be undone and redone.
+    history: History,
+    /// Bumped whenever the arrows need to be redrawn.
+    generation: usize,
+}
+
+impl Default for EditorState {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            chart: SongConfigToml {
+                difficulties: vec![DifficultyToml {
+                    name: DEFAULT_DIFFICULTY.to_string(),
+                    level: 0,
+                    lanes: Lanes::default(),
+                    arrows: Vec::new(),
+                    timing: None,
+                }],
+                ..Default::default()
+            },
+            difficulty: 0,
+            timing: Timing::constant(DEFAULT_BPM, 0.0),
+            selection: Vec::new(),
+            clipboard: Vec::new(),
+            view_time: 0.0,
+            snap: 3,
+            drag: None,
+            cursor: Vec2::zero(),
+            history: History::default(),
+            generation: 0,
+        }
+    }
+}
+
+impl EditorState {
+    /// Opens a chart from `assets/songs` for editing.
+    pub fn open(path: &str) -> std::io::Result<Self> {
+        let mut chart = SongConfigToml::load(path)?;
+        if chart.difficulties.is_empty() {
+            chart.difficulties = Self::default().chart.difficulties;
+        }
+
+        let mut state = Self {
+            path: path.to_string(),
+            chart,
+            ..Default::default()
+        };
+        state.timing = state.difficulty_timing();
+        Ok(state)
+    }
+
+    /// Timing of the difficulty being edited, as it was last stored in the
+    /// chart.
+    fn difficulty_timing(&self) -> Timing {
+        self.chart
+            .timing_for(self.current_difficulty())
+            .cloned()
+            .unwrap_or_else(|| Timing::constant(DEFAULT_BPM, 0.0))
+    }
+
+    /// The chart, with the timing being edited put back where it came from:
+    /// the difficulty's own if it has any, or else the song's.
+    fn edited_chart(&self) -> SongConfigToml {
+        let mut chart = self.chart.clone();
+        match chart.difficulties[self.difficulty].timing.as_mut() {
+            Some(timing) => *timing = self.timing.clone(),
+            None => chart.timing = Some(self.timing.clone()),
+        }
+        chart
+    }
+
+    /// Writes the chart back to the file it was opened from.
+    fn save(&mut self) {
+        self.chart = self.edited_chart();
+        for difficulty in self.chart.difficulties.iter_mut() {
+            difficulty
+                .arrows
+                .sort_by(|a, b| a.click_time.partial_cmp(&b.click_time).unwrap());
+        }
+
+        match self.chart.save(&self.path) {
+            Ok(()) => info!("Saved chart to {}", self.path),
+            Err(err) => error!("Couldn't save chart to {}: {}", self.path, err),
+        }
+    }
+
+    /// Writes the chart out to `exports/` in every other format it can be
+    /// played in.
+    fn export(&self) {
+        let chart = self.edited_chart();
+        let stem = Path::new(&self.path)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("chart");
+
+        for ext in EXPORT_EXTENSIONS.iter() {
+            let path = format!("exports/{}.{}", stem, ext);
+            match export_chart(&chart, &path) {
+                Ok(paths) => {
+                    for path in paths {
+                        info!("Exported chart to {}", path);
+                    }
+                }
+                Err(err) => error!("Couldn't export chart to {}: {}", path, err),
+            }
+        }
+    }
+
+    /// Arrows in the difficulty being edited.
+    fn arrows(&self) -> &[ArrowTimeToml] {
+        &self.chart.difficulties[self.difficulty].arrows
+    }
+
+    /// The difficulty being edited.
+    fn current_difficulty(&self) -> &DifficultyToml {
+        &self.chart.difficulties[self.difficulty]
+    }
+
+    /// Lanes of the difficulty being edited.
+    fn lanes(&self) -> Lanes {
+        self.current_difficulty().lanes
+    }
+
+    /// How big arrows get drawn, so they fit in their lanes.
+    fn arrow_size(&self) -> f32 {
+        EDITOR_ARROW_SIZE * self.lanes().scale()
+    }
+
+    /// Switches the difficulty being edited to the next lane count that all of
+    /// its arrows fit in.
+    fn change_lanes(&mut self) {
+        let mut lanes = self.lanes().next();
+        while self
+            .arrows()
+            .iter()
+            .any(|arrow| arrow.lane >= lanes.count())
+        {
+            lanes = lanes.next();
+        }
+        if lanes == self.lanes() {
+            info!("No other lane count fits every arrow");
+            return;
+        }
+
+        let difficulty = self.difficulty;
+        self.chart.difficulties[difficulty].lanes = lanes;
+        // Undoing could bring back arrows that don't fit anymore.
+        self.history = History::default();
+        self.mark_changed();
+    }
+
+    /// Switches to editing another difficulty. Edits made to the last one can't
+    /// be undone anymore.
+    fn switch_difficulty(&mut self, difficulty: usize) {
+        if difficulty >= self.chart.difficulties.len() || difficulty == self.difficulty {
+            return;
+        }
+
+        self.chart = self.edited_chart();
+        self.difficulty = difficulty;
+        self.timing = self.difficulty_timing();
+        self.selection.clear();
+        self.history = History::default();
+        self.mark_changed();
+    }
+
+    /// Adds an empty difficulty, one level above the hardest one so far, and
+    /// starts editing it.
+    fn add_difficulty(&mut self) {
+        let difficulties = &self.chart.difficulties;
+        let name = DIFFICULTY_NAMES
+            .iter()
+            .find(|name| {
+                difficulties
+                    .iter()
+                    .all(|difficulty| difficulty.name != **name)
+            })
+            .map_or_else(
+                || format!("Edit {}", difficulties.len() + 1),
+                |name| name.to_string(),
+            );
+        let level = difficulties
+            .iter()
+            .map(|difficulty| difficulty.level)
+            .max()
+            .map_or(1, |level| level + 1);
+
+        let lanes = self.lanes();
+        self.chart.difficulties.push(DifficultyToml {
+            name,
+            level,
+            lanes,
+            arrows: Vec::new(),
+            timing: None,
+        });
+        self.switch_difficulty(self.chart.difficulties.len() - 1);
+    }
+
+    /// Makes sure the arrows get redrawn.
+    fn mark_changed(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    /// Number of beat subdivisions arrows snap to.
+    fn snap(&self) -> u32 {
+        SNAPS[self.snap]
+    }
+
+    /// Converts a song time into an x coordinate on screen.
+    fn time_to_x(&self, time: f64) -> f32 {
+        EDITOR_PLAYHEAD_POSITION + (time - self.view_time) as f32 * EDITOR_PIXELS_PER_SECOND
+    }
+
+    /// Converts an x coordinate on screen into a song time.
+    fn x_to_time(&self, x: f32) -> f64 {
+        self.view_time + ((x - EDITOR_PLAYHEAD_POSITION) / EDITOR_PIXELS_PER_SECOND) as f64
+    }
+
+    /// Rounds a beat to the closest snapping point.
+    fn snap_beat(&self, beat: f64) -> f64 {
+        let snap = self.snap() as f64;
+        (beat * snap).round() / snap
+    }
+
+    /// Rounds a song time to the closest snapping point.
+    fn snap_time(&self, time: f64) -> f64 {
+        let beat = self.snap_beat(self.timing.time_to_beat(time));
+        self.timing.beat_to_time(beat)
+    }
+
+    /// Finds the arrow closest to some point on the screen, if any are close
+    /// enough.
+    fn arrow_at(&self, pos: Vec2) -> Option<ArrowTimeToml> {
+        let lanes = self.lanes();
+
+        self.arrows()
+            .iter()
+            .map(|arrow| {
+                let arrow_pos = Vec2::new(self.time_to_x(arrow.click_time), lanes.y(arrow.lane));
+                (arrow, (arrow_pos - pos).length())
+            })
+            .filter(|(_, distance)| *distance <= self.arrow_size() / 2.0)
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+            .map(|(arrow, _)| *arrow)
+    }
+
+    fn is_selected(&self, arrow: &ArrowTimeToml) -> bool {
+        self.selection.contains(arrow)
+    }
+
+    fn select_only(&mut self, arrow: ArrowTimeToml) {
+        self.selection = vec![arrow];
+        self.mark_changed();
+    }
+
+    fn toggle_selected(&mut self, arrow: ArrowTimeToml) {
+        if self.is_selected(&arrow) {
+            self.selection.retain(|selected| *selected != arrow);
+        } else {
+            self.selection.push(arrow);
+        }
+        self.mark_changed();
+    }
+
+    fn clear_selection(&mut self) {
+        if !self.selection.is_empty() {
+            self.selection.clear();
+            self.mark_changed();
+        }
+    }
+
+    /// Adds every arrow inside the box between two points to the selection.
+    fn select_in_box(&mut self, a: Vec2, b: Vec2) {
+        let min = a.min(b);
+        let max = a.max(b);
+
+        for arrow in self.chart.difficulties[self.difficulty].arrows.iter() {
+            let x = self.time_to_x(arrow.click_time);
+            let y = self.lanes().y(arrow.lane);
+
+            if (min.x..=max.x).contains(&x)
+                && (min.y..=max.y).contains(&y)
+                && !self.selection.contains(arrow)
+            {
+                self.selection.push(*arrow);
+            }
+        }
+        self.mark_changed();
+    }
+
+    /// Runs an edit on the chart, remembering it so it can be undone. Arrows
+    /// that the edit adds end up selected.
+    fn edit(&mut self, command: EditCommand) {
+        self.selection
+            .retain(|arrow| !command.removed().contains(arrow));
+        self.selection.extend_from_slice(command.added());
+
+        self.history.apply(
+            command,
+            &mut self.chart.difficulties[self.difficulty].arrows,
+        );
+        self.mark_changed();
+    }
+
+    /// Undoes the last edit, selecting whatever it changed.
+    fn undo(&mut self) {
+        if let Some(command) = self
+            .history
+            .undo(&mut self.chart.difficulties[self.difficulty].arrows)
+        {
+            self.selection = command.removed().to_vec();
+            self.mark_changed();
+        }
+    }
+
+    /// Redoes the last undone edit, selecting whatever it changed.
+    fn redo(&mut self) {
+        if let Some(command) = self
+            .history
+            .redo(&mut self.chart.difficulties[self.difficulty].arrows)
+        {
+            self.selection = command.added().to_vec();
+            self.mark_changed();
+        }
+    }
+
+    /// Adds some arrows to the chart, skipping any that would end up on top of
+    /// another arrow.
+    fn add_arrows(&mut self, arrows: &[ArrowTimeToml]) {
+        self.edit(EditCommand::add_arrows(self.arrows(), arrows));
+    }
+
+    /// Removes some arrows from the chart.
+    fn remove_arrows(&mut self, arrows: &[ArrowTimeToml]) {
+        let removed = arrows
+            .iter()
+            .filter(|arrow| self.arrows().contains(arrow))
+            .copied()
+            .collect();
+
+        self.edit(EditCommand::Remove(removed));
+    }
+
+    /// How far the selection has been dragged, as a number of beats and a
+    /// number of lanes.
+    fn drag_offset(&self, start: Vec2, end: Vec2) -> (f64, i32) {
+        let start_beat = self.timing.time_to_beat(self.x_to_time(start.x));
+        let end_beat = self.timing.time_to_beat(self.x_to_time(end.x));
+        let lanes = ((start.y - end.y) / self.lanes().spacing()).round() as i32;
+
+        (self.snap_beat(end_beat - start_beat), lanes)
+    }
+
+    /// Moves an arrow by some amount of beats and lanes.
+    fn offset_arrow(&self, arrow: &ArrowTimeToml, beats: f64, lanes: i32) -> ArrowTimeToml {
+        let beat = self.timing.time_to_beat(arrow.click_time) + beats;
+        let last_lane = self.lanes().count() as i32 - 1;
+
+        ArrowTimeToml {
+            click_time: self.timing.beat_to_time(beat),
+            lane: (arrow.lane as i32 + lanes).clamp(0, last_lane) as Lane,
+            ..*arrow
+        }
+    }
+
+    /// Moves every selected arrow by some amount of beats and lanes, as long
+    /// as none of them end up on top of another arrow.
+    fn move_selection(&mut self, beats: f64, lanes: i32) {
+        let before = self.selection.clone();
+        let after = before
+            .iter()
+            .map(|arrow| self.offset_arrow(arrow, beats, lanes))
+            .collect();
+
+        match EditCommand::move_arrows(self.arrows(), before, after) {
+            Some(command) => self.edit(command),
+            None => info!("Can't move arrows on top of other arrows"),
+        }
+    }
+
+    /// Changes the speed of every selected arrow.
+    fn set_selection_speed(&mut self, speed: Speed) {
+        self.change_selection_speed(|_| speed);
+    }
+
+    /// Changes the speed of every selected arrow based on what it was before.
+    fn change_selection_speed(&mut self, change: impl Fn(Speed) -> Speed) {
+        let before = self.selection.clone();
+        let after = before
+            .iter()
+            .map(|arrow| ArrowTimeToml {
+                speed: change(arrow.speed),
+                ..*arrow
+            })
+            .collect();
+
+        self.edit(EditCommand::ChangeSpeed { before, after });
+    }
+
+    /// Copies the selected arrows into the clipboard.
+    fn copy_selection(&mut self) {
+        let first = self
+            .selection
+            .iter()
+            .map(|arrow| arrow.click_time)
+            .fold(f64::INFINITY, f64::min);
+
+        self.clipboard = self
+            .selection
+            .iter()
+            .map(|arrow| ArrowTimeToml {
+                click_time: arrow.click_time - first,
+                ..*arrow
+            })
+            .collect();
+    }
+
+    /// Pastes the clipboard at the playhead, and selects the pasted arrows.
+    /// Arrows copied from a difficulty with more lanes get left out if they
+    /// don't fit.
+    fn paste(&mut self) {
+        let start = self.snap_time(self.view_time);
+        let lanes = self.lanes().count();
+        let pasted = self
+            .clipboard
+            .iter()
+            .filter(|arrow| arrow.lane < lanes)
+            .map(|arrow| ArrowTimeToml {
+                click_time: start + arrow.click_time,
+                ..*arrow
+            })
+            .collect::<Vec<_>>();
+
+        self.selection.clear();
+        self.add_arrows(&pasted);
+    }
+}
+
+/// Materials for everything in the editor other than the arrows.
+struct EditorMaterials {
+    none: Handle<ColorMaterial>,
+    lane: Handle<ColorMaterial>,
+    beat_line: Handle<ColorMaterial>,
+    measure_line: Handle<ColorMaterial>,
+    playhead: Handle<ColorMaterial>,
+    selected: Handle<ColorMaterial>,
+    selection_box: Handle<ColorMaterial>,
+    font: Handle<Font>,
+}
+
+impl FromResources for EditorMaterials {
+    fn from_resources(resources: &Resources) -> Self {
+        let mut materials = resources.get_mut::<Assets<ColorMaterial>>().unwrap();
+        let asset_server = resources.get::<AssetServer>().unwrap();
+
+        Self {
+            none: materials.add(Color::NONE.into()),
+            lane: materials.add(Color::rgba(1.0, 1.0, 1.0, 0.05).into()),
+            beat_line: materials.add(Color::rgba(1.0, 1.0, 1.0, 0.15).into()),
+            measure_line: materials.add(Color::rgba(1.0, 1.0, 1.0, 0.5).into()),
+            playhead: materials.add(Color::rgb(0.9, 0.3, 0.3).into()),
+            selected: materials.add(ColorMaterial::modulated_texture(
+                asset_server.load("images/arrow_border.png"),
+                Color::rgb(1.0, 0.85, 0.2),
+            )),
+            selection_box: materials.add(Color::rgba(0.4, 0.6, 1.0, 0.25).into()),
+            font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+        }
+    }
+}
+
+/// Marks everything that should be removed when leaving the editor.
+struct EditorEntity;
+
+/// An arrow drawn on the editor's timeline.
+struct EditorArrow(ArrowTimeToml);
+
+/// The background of one of the lanes.
+struct LaneStrip;
+
+/// A vertical line showing where a beat is.
+struct GridLine(usize);
+
+/// The box drawn while selecting arrows.
+struct SelectionBox;
+
+/// The editor's status text.
+struct EditorText;
+
+/// Spawns everything the editor needs other than the arrows.
+fn setup_editor(
+    commands: &mut Commands,
+    mut state: ResMut<EditorState>,
+    materials: Res<EditorMaterials>,
+) {
+    // Lanes and arrows get spawned by `rebuild_editor_arrows`.
+    state.mark_changed();
+    let height = state.lanes().height();
+
+    for i in 0..GRID_LINES {
+        commands
+            .spawn(SpriteBundle {
+                material: materials.beat_line.clone(),
+                sprite: Sprite::new(Vec2::new(2.0, height)),
+                ..Default::default()
+            })
+            .with(GridLine(i))
+            .with(EditorEntity);
+    }
+
+    commands
+        .spawn(SpriteBundle {
+            material: materials.playhead.clone(),
+            sprite: Sprite::new(Vec2::new(3.0, height + 20.0)),
+            transform: Transform::from_translation(Vec3::new(EDITOR_PLAYHEAD_POSITION, 0.0, 0.3)),
+            ..Default::default()
+        })
+        .with(EditorEntity)
+        .spawn(SpriteBundle {
+            material: materials.selection_box.clone(),
+            visible: Visible {
+                is_visible: false,
+                is_transparent: true,
+            },
+            ..Default::default()
+        })
+        .with(SelectionBox)
+        .with(EditorEntity)
+        // Status text node
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.0),
+                    top: Val::Px(10.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            material: materials.none.clone(),
+            ..Default::default()
+        })
+        .with(EditorEntity)
+        .with_children(|parent| {
+            parent
+                .spawn(TextBundle {
+                    text: Text {
+                        value: String::new(),
+                        font: materials.font.clone(),
+                        style: TextStyle {
+                            font_size: 20.0,
+                            color: Color::rgb(0.9, 0.9, 0.9),
+                            ..Default::default()
+                        },
+                    },
+                    ..Default::default()
+                })
+                .with(EditorText);
+        });
+}
+
+/// Removes everything the editor spawned.
+fn despawn_editor(commands: &mut Commands, query: Query<Entity, With<EditorEntity>>) {
+    for entity in query.iter() {
+        commands.despawn_recursive(entity);
+    }
+}
+
+/// Scrolls the timeline with the mouse wheel and some keys.
+fn scroll_editor(
+    mut state: ResMut<EditorState>,
+    mut event_reader: Local<EventReader<MouseWheel>>,
+    events: Res<Events<MouseWheel>>,
+    keyboard_input: Res<Input<KeyCode>>,
+) {
+    for event in event_reader.iter(&events) {
+        let seconds = match event.unit {
+            MouseScrollUnit::Line => event.y as f64 * 0.25,
+            MouseScrollUnit::Pixel => (event.y / EDITOR_PIXELS_PER_SECOND) as f64,
+        };
+        state.view_time = (state.view_time - seconds).max(0.0);
+    }
+
+    // Step by one snapping point at a time. Shift + arrow keys moves the
+    // selection instead.
+    if keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift) {
+        return;
+    }
+    let step = 1.0 / state.snap() as f64;
+    let beat = state.timing.time_to_beat(state.view_time);
+    if keyboard_input.just_pressed(KeyCode::Right) {
+        state.view_time = state.timing.beat_to_time(state.snap_beat(beat) + step);
+    }
+    if keyboard_input.just_pressed(KeyCode::Left) {
+        let time = state.timing.beat_to_time(state.snap_beat(beat) - step);
+        state.view_time = time.max(0.0);
+    }
+    if keyboard_input.just_pressed(KeyCode::Home) {
+        state.view_time = 0.0;
+    }
+}
+
+/// Handles clicking and dragging on the timeline.
+fn editor_mouse(
+    mut state: ResMut<EditorState>,
+    windows: Res<Windows>,
+    mouse_input: Res<Input<MouseButton>>,
+    keyboard_input: Res<Input<KeyCode>>,
+) {
+    // Convert the cursor position into world coordinates
+    let cursor = match windows.get_primary().and_then(|window| {
+        let size = Vec2::new(window.width(), window.height());
+        window.cursor_position().map(|pos| pos - size / 2.0)
+    }) {
+        Some(cursor) => cursor,
+        None => return,
+    };
+    state.cursor = cursor;
+
+    let shift = keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift);
+
+    if mouse_input.just_pressed(MouseButton::Left) {
+        match state.arrow_at(cursor) {
+            Some(arrow) => {
+                if shift {
+                    state.toggle_selected(arrow);
+                } else if !state.is_selected(&arrow) {
+                    state.select_only(arrow);
+                }
+                state.drag = Some(Drag::Move { start: cursor });
+            }
+
+            None if shift => state.drag = Some(Drag::Select { start: cursor }),
+
+            None => {
+                state.clear_selection();
+
+                if let Some(lane) = state.lanes().lane_at(cursor.y) {
+                    let click_time = state.snap_time(state.x_to_time(cursor.x));
+                    if click_time >= 0.0 {
+                        state.add_arrows(&[ArrowTimeToml {
+                            click_time,
+                            speed: Speed::SLOW,
+                            lane,
+                            hold: None,
+                            mine: false,
+                        }]);
+                    }
+                }
+            }
+        }
+    }
+
+    if mouse_input.just_released(MouseButton::Left) {
+        match state.drag.take() {
+            Some(Drag::Move { start }) => {
+                let (beats, lanes) = state.drag_offset(start, cursor);
+                if beats != 0.0 || lanes != 0 {
+                    state.move_selection(beats, lanes);
+                }
+            }
+            Some(Drag::Select { start }) => state.select_in_box(start, cursor),
+            None => {}
+        }
+    }
+
+    if mouse_input.just_pressed(MouseButton::Right) {
+        if let Some(arrow) = state.arrow_at(cursor) {
+            state.remove_arrows(&[arrow]);
+        }
+    }
+}
+
+/// Handles keyboard shortcuts.
+fn editor_keyboard(mut state: ResMut<EditorState>, keyboard_input: Res<Input<KeyCode>>) {
+    let ctrl =
+        keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl);
+    let shift = keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift);
+
+    if ctrl {
+        if keyboard_input.just_pressed(KeyCode::C) {
+            state.copy_selection();
+        }
+        if keyboard_input.just_pressed(KeyCode::V) {
+            state.paste();
+        }
+        if keyboard_input.just_pressed(KeyCode::A) {
+            state.selection = state.arrows().to_vec();
+            state.mark_changed();
+        }
+        if keyboard_input.just_pressed(KeyCode::S) {
+            state.save();
+        }
+        if keyboard_input.just_pressed(KeyCode::E) {
+            state.export();
+        }
+        if keyboard_input.just_pressed(KeyCode::Z) {
+            if shift {
+                state.redo();
+            } else {
+                state.undo();
+            }
+        }
+        if keyboard_input.just_pressed(KeyCode::Y) {
+            state.redo();
+        }
+        if keyboard_input.just_pressed(KeyCode::N) {
+            state.add_difficulty();
+        }
+        if keyboard_input.just_pressed(KeyCode::K) {
+            state.change_lanes();
+        }
+
+        // Change the level of the difficulty being edited
+        let difficulty = state.difficulty;
+        let level = &mut state.chart.difficulties[difficulty].level;
+        if keyboard_input.just_pressed(KeyCode::Up) {
+            *level += 1;
+        }
+        if keyboard_input.just_pressed(KeyCode::Down) && *level > 0 {
+            *level -= 1;
+        }
+        return;
+    }
+
+    // Switch between difficulties
+    if keyboard_input.just_pressed(KeyCode::PageUp) && state.difficulty > 0 {
+        let difficulty = state.difficulty - 1;
+        state.switch_difficulty(difficulty);
+    }
+    if keyboard_input.just_pressed(KeyCode::PageDown) {
+        let difficulty = state.difficulty + 1;
+        state.switch_difficulty(difficulty);
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Delete) || keyboard_input.just_pressed(KeyCode::Back) {
+        let selection = state.selection.clone();
+        state.remove_arrows(&selection);
+    }
+
+    // Change speed of the selected arrows
+    if keyboard_input.just_pressed(KeyCode::Key1) {
+        state.set_selection_speed(Speed::SLOW);
+    }
+    if keyboard_input.just_pressed(KeyCode::Key2) {
+        state.set_selection_speed(Speed::MEDIUM);
+    }
+    if keyboard_input.just_pressed(KeyCode::Key3) {
+        state.set_selection_speed(Speed::FAST);
+    }
+    // Nudge it up or down, for speeds in between
+    for (key, step) in [(KeyCode::Key9, -SPEED_STEP), (KeyCode::Key0, SPEED_STEP)].iter() {
+        if keyboard_input.just_pressed(*key) {
+            state.change_selection_speed(|speed| {
+                // Rounded so steps don't pile up floating point errors
+                let nudged = ((speed.0 + step) / SPEED_STEP).round() * SPEED_STEP;
+                Speed(nudged.max(SPEED_STEP))
+            });
+        }
+    }
+
+    // Move the selected arrows a lane or a snapping point at a time
+    if shift && !state.selection.is_empty() {
+        let step = 1.0 / state.snap() as f64;
+        for (key, beats, lanes) in [
+            (KeyCode::Up, 0.0, -1),
+            (KeyCode::Down, 0.0, 1),
+            (KeyCode::Left, -step, 0),
+            (KeyCode::Right, step, 0),
+        ]
+        .iter()
+        {
+            if keyboard_input.just_pressed(*key) {
+                state.move_selection(*beats, *lanes);
+            }
+        }
+    }
+
+    // Change how finely arrows snap to the grid
+    if keyboard_input.just_pressed(KeyCode::LBracket) && state.snap > 0 {
+        state.snap -= 1;
+    }
+    if keyboard_input.just_pressed(KeyCode::RBracket) && state.snap < SNAPS.len() - 1 {
+        state.snap += 1;
+    }
+
+    // Adjust the starting tempo and offset. Holding shift makes smaller steps.
+    let bpm_step = if shift { 0.1 } else { 1.0 };
+    let offset_step = if shift { 0.001 } else { 0.01 };
+    let mut timing_changed = false;
+    if let Some(first) = state.timing.bpms.first_mut() {
+        if keyboard_input.just_pressed(KeyCode::Equals) {
+            first.bpm += bpm_step;
+            timing_changed = true;
+        }
+        if keyboard_input.just_pressed(KeyCode::Minus) && first.bpm > bpm_step {
+            first.bpm -= bpm_step;
+            timing_changed = true;
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Period) {
+        state.timing.offset += offset_step;
+        timing_changed = true;
+    }
+    if keyboard_input.just_pressed(KeyCode::Comma) {
+        state.timing.offset -= offset_step;
+        timing_changed = true;
+    }
+    if timing_changed {
+        state.mark_changed();
+    }
+}
+
+/// Plays the chart from the playhead when F5 is pressed.
+fn playtest_from_editor(
+    commands: &mut Commands,
+    state: Res<EditorState>,
+    keyboard_input: Res<Input<KeyCode>>,
+    asset_server: Res<AssetServer>,
+    mut app_state: ResMut<State<AppState>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::F5) {
+        return;
+    }
+
+    let chart = state.edited_chart();
+
+    let song_config = SongConfig::new(
+        &chart,
+        &chart.difficulties[state.difficulty],
+        &state.path,
+        &asset_server,
+    );
+    start_playtest(
+        commands,
+        song_config,
+        state.view_time,
+        AppState::EditChart,
+        &mut app_state,
+    );
+}
+
+/// Respawns the lanes and arrows on the timeline whenever the chart changes.
+fn rebuild_editor_arrows(
+    commands: &mut Commands,
+    state: Res<EditorState>,
+    mut last_generation: Local<Option<usize>>,
+    arrow_materials: Res<ArrowMaterialResource>,
+    materials: Res<EditorMaterials>,
+    arrows: Query<Entity, With<EditorArrow>>,
+    strips: Query<Entity, With<LaneStrip>>,
+) {
+    if *last_generation == Some(state.generation) {
+        return;
+    }
+    *last_generation = Some(state.generation);
+
+    for entity in arrows.iter().chain(strips.iter()) {
+        commands.despawn_recursive(entity);
+    }
+
+    let lanes = state.lanes();
+    for lane in lanes.iter() {
+        commands
+            .spawn(SpriteBundle {
+                material: materials.lane.clone(),
+                sprite: Sprite::new(Vec2::new(4000.0, lanes.spacing() - 4.0)),
+                transform: Transform::from_translation(Vec3::new(0.0, lanes.y(lane), 0.1)),
+                ..Default::default()
+            })
+            .with(LaneStrip)
+            .with(EditorEntity);
+    }
+
+    let size = state.arrow_size();
+    for arrow in state.arrows().iter() {
+        let mut transform = Transform::from_translation(Vec3::new(0.0, lanes.y(arrow.lane), 1.0));
+        transform.rotate(Quat::from_rotation_z(lanes.rotation(arrow.lane)));
+
+        commands
+            .spawn(SpriteBundle {
+                material: arrow_materials.for_arrow(arrow.speed, arrow.mine),
+                sprite: Sprite::new(Vec2::new(size, size)),
+                transform,
+                ..Default::default()
+            })
+            .with(EditorArrow(*arrow))
+            .with(EditorEntity);
+
+        if state.is_selected(arrow) {
+            commands.with_children(|parent| {
+                parent.spawn(SpriteBundle {
+                    material: materials.selected.clone(),
+                    sprite: Sprite::new(Vec2::new(size, size)),
+                    transform: Transform::from_translation(Vec3::new(0.0, 0.0, 0.1)),
+                    ..Default::default()
+                });
+            });
+        }
+    }
+}
+
+/// Moves the arrows on the timeline according to the playhead and any
+/// dragging that's happening.
+fn position_editor_arrows(
+    state: Res<EditorState>,
+    mut query: Query<(&EditorArrow, &mut Transform)>,
+) {
+    let drag = match state.drag {
+        Some(Drag::Move { start }) => Some(state.drag_offset(start, state.cursor)),
+        _ => None,
+    };
+
+    for (arrow, mut transform) in query.iter_mut() {
+        let arrow = match drag {
+            Some((beats, lanes)) if state.is_selected(&arrow.0) => {
+                state.offset_arrow(&arrow.0, beats, lanes)
+            }
+            _ => arrow.0,
+        };
+
+        transform.translation.x = state.time_to_x(arrow.click_time);
+        transform.translation.y = state.lanes().y(arrow.lane);
+        transform.rotation = Quat::from_rotation_z(state.lanes().rotation(arrow.lane));
+    }
+}
+
+/// Lines the grid up with the beats that are currently on screen.
+fn position_grid_lines(
+    state: Res<EditorState>,
+    windows: Res<Windows>,
+    materials: Res<EditorMaterials>,
+    mut query: Query<(
+        &GridLine,
+        &mut Transform,
+        &mut Visible,
+        &mut Handle<ColorMaterial>,
+    )>,
+) {
+    let half_width = windows
+        .get_primary()
+        .map_or(400.0, |window| window.width() / 2.0);
+    let first_beat = state
+        .timing
+        .time_to_beat(state.x_to_time(-half_width))
+        .ceil()
+        .max(0.0);
+
+    for (line, mut transform, mut visible, mut material) in query.iter_mut() {
+        let beat = first_beat + line.0 as f64;
+        let x = state.time_to_x(state.timing.beat_to_time(beat));
+
+        visible.is_visible = x <= half_width;
+        transform.translation = Vec3::new(x, 0.0, 0.2);
+        *material = if beat as i64 % 4 == 0 {
+            materials.measure_line.clone()
+        } else {
+            materials.beat_line.clone()
+        };
+    }
+}
+
+/// Shows the selection box while it's being dragged out.
+fn update_selection_box(
+    state: Res<EditorState>,
+    mut query: Query<(&mut Sprite, &mut Transform, &mut Visible), With<SelectionBox>>,
+) {
+    for (mut sprite, mut transform, mut visible) in query.iter_mut() {
+        match state.drag {
+            Some(Drag::Select { start }) => {
+                visible.is_visible = true;
+                sprite.size = (state.cursor - start).abs();
+                let center = (state.cursor + start) / 2.0;
+                transform.translation = Vec3::new(center.x, center.y, 2.0);
+            }
+            _ => visible.is_visible = false,
+        }
+    }
+}
+
+/// Shows what's being edited, along with a list of controls.
+fn update_editor_text(state: Res<EditorState>, mut query: Query<&mut Text, With<EditorText>>) {
+    for mut text in query.iter_mut() {
+        text.value = format!(
+            "{} [{} {} {}]  |  Time: {:.2}  |  BPM: {:.1}  |  Offset: {:.3}  |  Snap: 1/{}  |  Selected: {}\n\
+             Click: add/select  Right click: remove  Shift: multi-select  Drag: move\n\
+             1/2/3: speed  9/0: speed -/+  Shift+arrows: move  [ ]: snap  -/=: BPM  ,/.: offset\n\
+             Ctrl+C/V: copy/paste  Ctrl+Z/Y: undo/redo  Ctrl+S: save  Ctrl+E: export\n\
+             PgUp/PgDn: difficulty  Ctrl+N: new difficulty  Ctrl+Up/Down: level  Ctrl+K: lanes\n\
+             F5: playtest (Tab to return)",
+            state.chart.name,
+            state.current_difficulty().name,
+            state.current_difficulty().level,
+            state.lanes().label(),
+            state.view_time,
+            state.timing.initial_bpm(),
+            state.timing.offset,
+            state.snap(),
+            state.selection.len(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arrow(click_time: f64, lane: Lane) -> ArrowTimeToml {
+        ArrowTimeToml {
+            click_time,
+            speed: Speed::SLOW,
+            lane,
+            hold: None,
+            mine: false,
+        }
+    }
+
+    #[test]
+    fn arrows_dont_get_added_on_top_of_others() {
+        let mut state = EditorState::default();
+        state.add_arrows(&[arrow(1.0, 0), arrow(1.0004, 0), arrow(1.0, 1)]);
+        assert_eq!(state.arrows(), vec![arrow(1.0, 0), arrow(1.0, 1)]);
+
+        // Pasting over the same arrows only adds the ones with room
+        state.selection = vec![arrow(1.0, 0), arrow(1.5, 2)];
+        state.copy_selection();
+        state.view_time = 1.0;
+        state.paste();
+        assert_eq!(
+            state.arrows(),
+            vec![arrow(1.0, 0), arrow(1.0, 1), arrow(1.5, 2)]
+        );
+        assert_eq!(state.selection, vec![arrow(1.5, 2)]);
+    }
+}
@@ -0,0 +1,319 @@
+use crate::types::ArrowTimeToml;
+
+/// How close together two arrows in the same lane can be before they count as
+/// being in the same place, in seconds.
+const SAME_PLACE: f64 = 0.001;
+
+/// A reversible edit to a chart's arrows.
+///
+/// Every command knows which arrows it takes out of a chart and which ones it
+/// puts in, so undoing one is just doing the opposite.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EditCommand {
+    /// Adds some new arrows.
+    Add(Vec<ArrowTimeToml>),
+    /// Removes some arrows.
+    Remove(Vec<ArrowTimeToml>),
+    /// Moves some arrows to different times or lanes.
+    Move {
+        before: Vec<ArrowTimeToml>,
+        after: Vec<ArrowTimeToml>,
+    },
+    /// Changes the speed of some arrows.
+    ChangeSpeed {
+        before: Vec<ArrowTimeToml>,
+        after: Vec<ArrowTimeToml>,
+    },
+}
+
+impl EditCommand {
+    /// Adds some arrows to a chart, leaving out any that would end up in the
+    /// same place as an arrow that's already there or another new one.
+    pub fn add_arrows(arrows: &[ArrowTimeToml], new: &[ArrowTimeToml]) -> Self {
+        let mut added: Vec<ArrowTimeToml> = Vec::new();
+        for arrow in new {
+            let taken = arrows
+                .iter()
+                .chain(added.iter())
+                .any(|other| same_place(arrow, other));
+            if !taken {
+                added.push(*arrow);
+            }
+        }
+
+        Self::Add(added)
+    }
+
+    /// Moves some of a chart's arrows, unless that would leave two arrows in
+    /// the same place. Arrows get told apart by their values, so they can't be
+    /// allowed to pile up on each other.
+    pub fn move_arrows(
+        arrows: &[ArrowTimeToml],
+        before: Vec<ArrowTimeToml>,
+        after: Vec<ArrowTimeToml>,
+    ) -> Option<Self> {
+        let mut moved = arrows.to_vec();
+        replace(&mut moved, &before, &after);
+
+        let overlaps = after.iter().any(|arrow| {
+            moved
+                .iter()
+                .filter(|other| same_place(arrow, other))
+                .count()
+                > 1
+        });
+        if overlaps {
+            None
+        } else {
+            Some(Self::Move { before, after })
+        }
+    }
+
+    /// Arrows that this command puts into a chart.
+    pub fn added(&self) -> &[ArrowTimeToml] {
+        match self {
+            Self::Add(arrows) => arrows,
+            Self::Remove(_) => &[],
+            Self::Move { after, .. } | Self::ChangeSpeed { after, .. } => after,
+        }
+    }
+
+    /// Arrows that this command takes out of a chart.
+    pub fn removed(&self) -> &[ArrowTimeToml] {
+        match self {
+            Self::Add(_) => &[],
+            Self::Remove(arrows) => arrows,
+            Self::Move { before, .. } | Self::ChangeSpeed { before, .. } => before,
+        }
+    }
+
+    /// Returns true if running this command wouldn't change anything.
+    pub fn is_empty(&self) -> bool {
+        self.added() == self.removed()
+    }
+
+    /// Runs this command on a list of arrows.
+    pub fn apply(&self, arrows: &mut Vec<ArrowTimeToml>) {
+        replace(arrows, self.removed(), self.added());
+    }
+
+    /// Undoes this command on a list of arrows.
+    pub fn revert(&self, arrows: &mut Vec<ArrowTimeToml>) {
+        replace(arrows, self.added(), self.removed());
+    }
+}
+
+/// Returns true if two arrows are in the same lane at the same time.
+fn same_place(a: &ArrowTimeToml, b: &ArrowTimeToml) -> bool {
+    a.lane == b.lane && (a.click_time - b.click_time).abs() < SAME_PLACE
+}
+
+/// Takes one copy of each arrow in `remove` out of `arrows`, then adds
+/// everything in `add`.
+fn replace(arrows: &mut Vec<ArrowTimeToml>, remove: &[ArrowTimeToml], add: &[ArrowTimeToml]) {
+    for arrow in remove {
+        if let Some(index) = arrows.iter().position(|a| a == arrow) {
+            arrows.remove(index);
+        }
+    }
+
+    arrows.extend_from_slice(add);
+}
+
+/// Keeps track of the commands run on a chart so they can be undone and redone.
+#[derive(Debug, Default)]
+pub struct History {
+    undo: Vec<EditCommand>,
+    redo: Vec<EditCommand>,
+}
+
+impl History {
+    /// Runs a command and remembers it. Anything that was undone can't be
+    /// redone anymore.
+    pub fn apply(&mut self, command: EditCommand, arrows: &mut Vec<ArrowTimeToml>) {
+        if command.is_empty() {
+            return;
+        }
+
+        command.apply(arrows);
+        self.undo.push(command);
+        self.redo.clear();
+    }
+
+    /// Undoes the last command, returning it if there was one.
+    pub fn undo(&mut self, arrows: &mut Vec<ArrowTimeToml>) -> Option<&EditCommand> {
+        let command = self.undo.pop()?;
+        command.revert(arrows);
+        self.redo.push(command);
+        self.redo.last()
+    }
+
+    /// Redoes the last undone command, returning it if there was one.
+    pub fn redo(&mut self, arrows: &mut Vec<ArrowTimeToml>) -> Option<&EditCommand> {
+        let command = self.redo.pop()?;
+        command.apply(arrows);
+        self.undo.push(command);
+        self.undo.last()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Speed;
+
+    fn arrow(click_time: f64, lane: usize) -> ArrowTimeToml {
+        ArrowTimeToml {
+            click_time,
+            speed: Speed::SLOW,
+            lane,
+            hold: None,
+            mine: false,
+        }
+    }
+
+    fn chart() -> Vec<ArrowTimeToml> {
+        vec![
+            arrow(0.5, 0),
+            arrow(1.0, 1),
+            arrow(1.5, 2),
+            arrow(2.0, 3),
+            arrow(2.5, 1),
+        ]
+    }
+
+    /// Checks that two lists have the same arrows, in any order.
+    fn assert_same_arrows(a: &[ArrowTimeToml], b: &[ArrowTimeToml]) {
+        let sorted = |arrows: &[ArrowTimeToml]| {
+            let mut arrows = arrows.to_vec();
+            arrows.sort_by(|a, b| {
+                a.click_time
+                    .partial_cmp(&b.click_time)
+                    .unwrap()
+                    .then(a.lane.cmp(&b.lane))
+            });
+            arrows
+        };
+        assert_eq!(sorted(a), sorted(b));
+    }
+
+    /// One of each kind of command that can be run on [`chart`], one after
+    /// the other. Moving an arrow to another lane is how its direction gets
+    /// changed.
+    fn commands() -> Vec<EditCommand> {
+        vec![
+            EditCommand::Add(vec![arrow(3.0, 1), arrow(3.0, 2)]),
+            EditCommand::Remove(vec![arrow(1.0, 1)]),
+            EditCommand::Move {
+                before: vec![arrow(0.5, 0), arrow(1.5, 2)],
+                after: vec![arrow(0.75, 0), arrow(1.75, 2)],
+            },
+            EditCommand::Move {
+                before: vec![arrow(2.0, 3)],
+                after: vec![arrow(2.0, 0)],
+            },
+            EditCommand::ChangeSpeed {
+                before: vec![arrow(2.5, 1)],
+                after: vec![ArrowTimeToml {
+                    speed: Speed::FAST,
+                    ..arrow(2.5, 1)
+                }],
+            },
+        ]
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        for command in commands() {
+            let original = chart();
+            let mut arrows = original.clone();
+            let mut history = History::default();
+
+            history.apply(command.clone(), &mut arrows);
+            let applied = arrows.clone();
+            assert_ne!(applied, original, "{:?} didn't change anything", command);
+
+            assert_eq!(history.undo(&mut arrows), Some(&command));
+            assert_same_arrows(&arrows, &original);
+            assert_eq!(history.undo(&mut arrows), None);
+
+            assert_eq!(history.redo(&mut arrows), Some(&command));
+            assert_same_arrows(&arrows, &applied);
+            assert_eq!(history.redo(&mut arrows), None);
+        }
+    }
+
+    #[test]
+    fn undo_everything() {
+        let original = chart();
+        let mut arrows = original.clone();
+        let mut history = History::default();
+
+        for command in commands() {
+            history.apply(command, &mut arrows);
+        }
+        while history.undo(&mut arrows).is_some() {}
+
+        assert_same_arrows(&arrows, &original);
+    }
+
+    #[test]
+    fn new_edit_clears_redo() {
+        let mut arrows = chart();
+        let mut history = History::default();
+
+        history.apply(EditCommand::Remove(vec![arrow(0.5, 0)]), &mut arrows);
+        history.undo(&mut arrows);
+        history.apply(EditCommand::Add(vec![arrow(4.0, 0)]), &mut arrows);
+
+        assert_eq!(history.redo(&mut arrows), None);
+        assert!(arrows.contains(&arrow(0.5, 0)));
+        assert!(arrows.contains(&arrow(4.0, 0)));
+    }
+
+    #[test]
+    fn moves_onto_other_arrows_are_refused() {
+        let arrows = chart();
+
+        // Onto an arrow that isn't being moved
+        assert_eq!(
+            EditCommand::move_arrows(&arrows, vec![arrow(0.5, 0)], vec![arrow(1.0, 1)]),
+            None
+        );
+        // Two arrows onto the same place
+        assert_eq!(
+            EditCommand::move_arrows(
+                &arrows,
+                vec![arrow(0.5, 0), arrow(1.5, 2)],
+                vec![arrow(3.0, 0), arrow(3.0, 0)]
+            ),
+            None
+        );
+        // Into a place another moved arrow is leaving
+        assert!(EditCommand::move_arrows(
+            &arrows,
+            vec![arrow(0.5, 0), arrow(1.0, 1)],
+            vec![arrow(1.0, 1), arrow(1.5, 1)]
+        )
+        .is_some());
+    }
+
+    #[test]
+    fn adds_onto_other_arrows_are_skipped() {
+        let arrows = chart();
+
+        assert_eq!(
+            EditCommand::add_arrows(
+                &arrows,
+                &[
+                    arrow(1.0, 1),
+                    arrow(1.0004, 1),
+                    arrow(3.0, 0),
+                    arrow(3.0005, 0),
+                    arrow(3.0, 1),
+                ]
+            ),
+            EditCommand::Add(vec![arrow(3.0, 0), arrow(3.0, 1)])
+        );
+    }
+}
@@ -0,0 +1,667 @@
+use crate::types::SongConfigToml;
+use std::{error::Error, fmt, fs, io, path::Path};
+
+mod osu;
+mod stepmania;
+
+pub use osu::{write_beatmap, Beatmap};
+pub use stepmania::{write_simfile, write_ssc, Simfile};
+
+/// Something that went wrong while reading a chart.
+#[derive(Debug)]
+pub enum ImportError {
+    /// The file couldn't be read.
+    Io(io::Error),
+    /// The file wasn't a valid TOML chart.
+    Toml(toml::de::Error),
+    /// The file was malformed in some other way.
+    Parse(String),
+    /// The file was valid, but has something in it that we can't play.
+    Unsupported(String),
+    /// The file didn't have any charts that we can play.
+    NoCharts,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "couldn't read file: {}", err),
+            Self::Toml(err) => write!(f, "couldn't parse TOML: {}", err),
+            Self::Parse(msg) => write!(f, "couldn't parse file: {}", msg),
+            Self::Unsupported(msg) => write!(f, "unsupported chart: {}", msg),
+            Self::NoCharts => write!(f, "no playable charts found"),
+        }
+    }
+}
+
+impl Error for ImportError {}
+
+impl From<io::Error> for ImportError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for ImportError {
+    fn from(err: toml::de::Error) -> Self {
+        Self::Toml(err)
+    }
+}
+
+/// Turns empty strings into `None`, for formats that leave things blank
+/// instead of leaving them out.
+fn non_empty(value: &str) -> Option<String> {
+    Some(value.trim())
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+/// File extensions of charts that can be loaded.
+pub const CHART_EXTENSIONS: [&str; 4] = ["toml", "sm", "ssc", "osu"];
+
+/// Returns true if a path looks like a chart that can be loaded.
+pub fn is_chart_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            CHART_EXTENSIONS
+                .iter()
+                .any(|chart_ext| ext.eq_ignore_ascii_case(chart_ext))
+        })
+}
+
+/// Parses a chart, converting it from whatever format its file extension says
+/// it's in.
+pub fn parse_chart(text: &str, ext: &str) -> Result<SongConfigToml, ImportError> {
+    let chart = match ext.to_lowercase().as_str() {
+        "sm" | "ssc" => Simfile::parse(text)?.to_config(),
+        "osu" => Beatmap::parse(text)?.to_config(),
+        _ => SongConfigToml::from_toml(text)?,
+    };
+
+    if chart.difficulties.is_empty() {
+        return Err(ImportError::NoCharts);
+    }
+
+    Ok(chart)
+}
+
+/// Reads a chart from a file in `assets/songs`. Charts that get played are
+/// loaded through the asset server instead, this is for looking at what's in
+/// them.
+pub fn load_chart(path: &str) -> Result<SongConfigToml, ImportError> {
+    let text = fs::read_to_string(format!("assets/songs/{}", path))?;
+    let ext = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default();
+
+    parse_chart(&text, ext)
+}
+
+/// File extensions that charts can be exported to.
+pub const EXPORT_EXTENSIONS: [&str; 3] = ["sm", "ssc", "osu"];
+
+/// Writes a chart out in the format that matches a path's extension, returning
+/// the paths of the files that got written. Beatmaps only hold one difficulty
+/// each, so songs with more than one get a beatmap per difficulty, with the
+/// difficulty's name added to the file name.
+pub fn export_chart(config: &SongConfigToml, path: &str) -> io::Result<Vec<String>> {
+    let path = Path::new(path);
+
+    let files = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("sm") => vec![(path.to_path_buf(), write_simfile(config))],
+        Some("ssc") => vec![(path.to_path_buf(), write_ssc(config))],
+        Some("osu") if config.difficulties.len() == 1 => vec![(
+            path.to_path_buf(),
+            write_beatmap(config, &config.difficulties[0]),
+        )],
+        Some("osu") => config
+            .difficulties
+            .iter()
+            .map(|difficulty| {
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                (
+                    path.with_file_name(format!("{} [{}].osu", stem, difficulty.name)),
+                    write_beatmap(config, difficulty),
+                )
+            })
+            .collect(),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "charts can only be exported to .sm, .ssc or .osu",
+            ))
+        }
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    files
+        .into_iter()
+        .map(|(path, text)| {
+            fs::write(&path, text)?;
+            Ok(path.to_string_lossy().to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lanes::Lanes,
+        timing::Timing,
+        types::{ArrowTimeToml, DifficultyToml, SongConfig, Speed, DEFAULT_DIFFICULTY},
+    };
+    use std::convert::TryFrom;
+
+    /// How far apart notes can end up after a round trip, in seconds.
+    const TOLERANCE: f64 = 0.001;
+
+    const SIMFILE: &str = "\
+#TITLE:Round Trip;
+#ARTIST:Someone;
+#CREDIT:Someone Else;
+#GENRE:Test;
+#DISPLAYBPM:150:200;
+#MUSIC:song.ogg;
+#SAMPLESTART:12.500;
+#SAMPLELENGTH:15.000;
+#BACKGROUND:bg.png;
+#BANNER:banner.png;
+#OFFSET:-0.120;
+#BPMS:0.000=150.000,8.000=200.000;
+#STOPS:12.000=0.500;
+#NOTES:
+     dance-single:
+     :
+     Challenge:
+     9:
+     0,0,0,0,0:
+1000
+0100
+0010
+0001
+,
+2000
+0000
+3000
+0000
+0000
+0000
+0000
+0000
+0000
+0000
+0100
+0000
+,
+0010
+0000
+0001
+0000
+,
+1001
+0100
+0010
+0000
+;
+";
+
+    /// An `.ssc` file where the hard chart has timing of its own.
+    const SSC: &str = "\
+#VERSION:0.83;
+#TITLE:Own Timing;
+#MUSIC:song.ogg;
+#OFFSET:0.000;
+#BPMS:0.000=120.000;
+
+#NOTEDATA:;
+#STEPSTYPE:dance-single;
+#DIFFICULTY:Easy;
+#METER:2;
+#NOTES:
+1000
+0000
+0000
+0000
+,
+0100
+0000
+0000
+0000
+;
+
+#NOTEDATA:;
+#STEPSTYPE:dance-single;
+#DIFFICULTY:Hard;
+#METER:8;
+#OFFSET:-0.050;
+#BPMS:0.000=240.000;
+#STOPS:2.000=0.250;
+#SCROLLS:4.000=2.000;
+#NOTES:
+1000
+0010
+0000
+0000
+,
+0100
+0000
+0001
+0000
+;
+";
+
+    const BEATMAP: &str = "\
+osu file format v14
+
+[General]
+AudioFilename: audio.mp3
+Mode: 3
+
+[Metadata]
+Title:Round Trip
+Version:Hard
+
+[Difficulty]
+CircleSize:4
+
+[TimingPoints]
+250,500,4,2,0,50,1,0
+8250,-50,4,2,0,50,0,0
+10250,375,4,2,0,50,1,0
+
+[HitObjects]
+64,192,100,1,0,0:0:0:0:
+64,192,250,1,0,0:0:0:0:
+192,192,500,1,0,0:0:0:0:
+320,192,1000,128,0,1500:0:0:0:0:
+448,192,1333,1,0,0:0:0:0:
+64,192,10625,1,0,0:0:0:0:
+448,192,11000,128,0,11750:0:0:0:0:
+";
+
+    /// A chart recorded in the map maker, with no timing and notes that don't
+    /// sit on any beat.
+    fn recorded_chart() -> SongConfigToml {
+        let arrow = |click_time, lane, hold| ArrowTimeToml {
+            click_time,
+            speed: Speed::SLOW,
+            lane,
+            hold,
+            mine: false,
+        };
+
+        SongConfigToml {
+            name: "Recorded".to_string(),
+            filename: "song.mp3".to_string(),
+            difficulties: vec![
+                DifficultyToml {
+                    name: "Easy".to_string(),
+                    level: 2,
+                    lanes: Lanes::default(),
+                    arrows: vec![arrow(0.4137, 0, None), arrow(2.9182, 3, None)],
+                    timing: None,
+                },
+                DifficultyToml {
+                    name: "Hard".to_string(),
+                    level: 6,
+                    lanes: Lanes::default(),
+                    arrows: vec![
+                        arrow(0.4137, 0, None),
+                        arrow(1.0021, 1, None),
+                        arrow(1.5, 2, Some(0.7391)),
+                        arrow(2.9182, 3, None),
+                        arrow(3.0003, 0, Some(1.2345)),
+                        arrow(7.77, 2, None),
+                    ],
+                    timing: None,
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn from_simfile(text: &str) -> SongConfigToml {
+        Simfile::parse(text).unwrap().to_config()
+    }
+
+    fn from_beatmap(text: &str) -> SongConfigToml {
+        Beatmap::parse(text).unwrap().to_config()
+    }
+
+    /// Writes each of a chart's difficulties out as a beatmap, and reads them
+    /// back in as one song.
+    fn through_beatmaps(chart: &SongConfigToml) -> SongConfigToml {
+        let mut song = chart.clone();
+        song.difficulties = chart
+            .difficulties
+            .iter()
+            .flat_map(|difficulty| from_beatmap(&write_beatmap(chart, difficulty)).difficulties)
+            .collect();
+        song
+    }
+
+    /// Checks that two charts have the same difficulties, with the same notes
+    /// at (almost) the same times.
+    fn assert_same_notes(a: &SongConfigToml, b: &SongConfigToml) {
+        assert_eq!(a.difficulties.len(), b.difficulties.len());
+        for (a, b) in a.difficulties.iter().zip(&b.difficulties) {
+            assert_same_difficulty(a, b);
+        }
+    }
+
+    fn assert_same_difficulty(a: &DifficultyToml, b: &DifficultyToml) {
+        let notes = |difficulty: &DifficultyToml| {
+            let mut arrows = difficulty.arrows.clone();
+            arrows.sort_by(|a, b| {
+                a.click_time
+                    .partial_cmp(&b.click_time)
+                    .unwrap()
+                    .then(a.lane.cmp(&b.lane))
+            });
+            arrows
+        };
+        assert_eq!(a.lanes, b.lanes);
+        let (a, b) = (notes(a), notes(b));
+
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(&b) {
+            assert_eq!(a.lane, b.lane);
+            assert!(
+                (a.click_time - b.click_time).abs() <= TOLERANCE,
+                "{} and {} are too far apart",
+                a.click_time,
+                b.click_time
+            );
+
+            match (a.hold, b.hold) {
+                (Some(a_hold), Some(b_hold)) => {
+                    let (a_end, b_end) = (a.click_time + a_hold, b.click_time + b_hold);
+                    assert!(
+                        (a_end - b_end).abs() <= TOLERANCE,
+                        "holds ending at {} and {} are too far apart",
+                        a_end,
+                        b_end
+                    );
+                }
+                (None, None) => {}
+                _ => panic!("{:?} and {:?} aren't both holds", a, b),
+            }
+        }
+    }
+
+    #[test]
+    fn simfile_round_trip() {
+        let chart = from_simfile(SIMFILE);
+
+        assert_same_notes(&chart, &from_simfile(&write_simfile(&chart)));
+        assert_same_notes(&chart, &through_beatmaps(&chart));
+    }
+
+    #[test]
+    fn sharps_in_values() {
+        let chart = from_simfile(&SIMFILE.replace("Round Trip", "Prelude in C# Minor"));
+
+        assert_eq!(chart.name, "Prelude in C# Minor");
+        assert_eq!(chart.artist.as_deref(), Some("Someone"));
+        assert_same_notes(&chart, &from_simfile(SIMFILE));
+        assert_eq!(from_simfile(&write_simfile(&chart)).name, chart.name);
+    }
+
+    #[test]
+    fn missing_semicolons() {
+        let chart = from_simfile(&SIMFILE.replace("#TITLE:Round Trip;", "#TITLE:Round Trip"));
+
+        assert_eq!(chart.name, "Round Trip");
+        assert_eq!(chart.artist.as_deref(), Some("Someone"));
+        assert_same_notes(&chart, &from_simfile(SIMFILE));
+    }
+
+    #[test]
+    fn simfile_holds() {
+        let chart = from_simfile(SIMFILE);
+        let holds = chart.difficulties[0]
+            .arrows
+            .iter()
+            .filter(|arrow| arrow.hold.is_some())
+            .collect::<Vec<_>>();
+
+        // Two twelfths of a measure, or two thirds of a beat at 150 BPM.
+        assert_eq!(holds.len(), 1);
+        assert_eq!(holds[0].lane, 2);
+        assert!((holds[0].hold.unwrap() - 0.4 * 2.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn beatmap_notes() {
+        let chart = from_beatmap(BEATMAP);
+        let timing = chart.timing.unwrap();
+        let hard = &chart.difficulties[0];
+
+        assert_eq!(
+            (chart.name.as_str(), hard.name.as_str()),
+            ("Round Trip", "Hard")
+        );
+        assert_eq!(timing.offset, 0.25);
+        // The inherited timing point only changes scroll speed.
+        assert_eq!(timing.bpms.len(), 2);
+        assert_eq!((timing.bpms[1].beat, timing.bpms[1].bpm), (20.0, 160.0));
+
+        assert_eq!(hard.arrows.len(), 7);
+        assert_eq!(hard.arrows[3].lane, 0);
+        assert_eq!(hard.arrows[3].hold, Some(0.5));
+        assert_eq!(hard.arrows[6].lane, 3);
+        assert_eq!(hard.arrows[6].hold, Some(0.75));
+    }
+
+    #[test]
+    fn beatmap_round_trip() {
+        let chart = from_beatmap(BEATMAP);
+
+        assert_same_notes(&chart, &through_beatmaps(&chart));
+        assert_same_notes(&chart, &from_simfile(&write_simfile(&chart)));
+    }
+
+    #[test]
+    fn recorded_chart_round_trip() {
+        let chart = recorded_chart();
+
+        assert_same_notes(&chart, &from_simfile(&write_simfile(&chart)));
+        assert_same_notes(&chart, &through_beatmaps(&chart));
+    }
+
+    #[test]
+    fn more_lanes_round_trip() {
+        let mut chart = recorded_chart();
+        for (difficulty, count) in chart.difficulties.iter_mut().zip(&[6, 8]) {
+            difficulty.lanes = Lanes::try_from(*count).unwrap();
+            for (i, arrow) in difficulty.arrows.iter_mut().enumerate() {
+                arrow.lane = (arrow.lane + i) % count;
+            }
+        }
+
+        assert_same_notes(&chart, &from_simfile(&write_simfile(&chart)));
+        assert_same_notes(&chart, &through_beatmaps(&chart));
+    }
+
+    #[test]
+    fn mines_are_kept() {
+        let mut chart = recorded_chart();
+        chart.difficulties[1].arrows[1].mine = true;
+        chart.difficulties[1].arrows[5].mine = true;
+
+        let exported = from_simfile(&write_simfile(&chart));
+        assert_same_notes(&chart, &exported);
+        let mines = |chart: &SongConfigToml| {
+            let mut mines = chart.difficulties[1]
+                .arrows
+                .iter()
+                .filter(|arrow| arrow.mine)
+                .map(|arrow| arrow.lane)
+                .collect::<Vec<_>>();
+            mines.sort();
+            mines
+        };
+        assert_eq!(mines(&chart), mines(&exported));
+    }
+
+    #[test]
+    fn timing_is_kept() {
+        let chart = from_simfile(SIMFILE);
+        let exported = from_simfile(&write_simfile(&chart));
+
+        assert_eq!(chart.timing, exported.timing);
+    }
+
+    #[test]
+    fn charts_keep_their_own_timing() {
+        let chart = from_simfile(SSC);
+        let bpm = |timing: Option<&Timing>| timing.unwrap().initial_bpm();
+
+        assert_eq!(bpm(chart.timing.as_ref()), 120.0);
+        assert_eq!(chart.difficulties[0].timing, None);
+        let hard = &chart.difficulties[1];
+        assert_eq!(bpm(hard.timing.as_ref()), 240.0);
+        assert_eq!(hard.timing.as_ref().unwrap().scrolls.len(), 1);
+
+        // Beat 4 is two seconds in at the song's tempo, but one second (and a
+        // bit for the offset and stop) at the hard chart's.
+        assert_eq!(chart.difficulties[0].arrows[1].click_time, 2.0);
+        assert!((hard.arrows[2].click_time - 1.3).abs() < 1e-9);
+
+        // Only the hard chart speeds up after beat 4 when it gets played
+        let position = |difficulty| {
+            SongConfig::with_audio(&chart, difficulty, Default::default())
+                .scroll
+                .position(3.3)
+        };
+        assert!((position(&chart.difficulties[0]) - 3.3).abs() < 1e-9);
+        assert!((position(hard) - 5.3).abs() < 1e-9);
+
+        let exported = from_simfile(&write_ssc(&chart));
+        assert_eq!(chart.timing, exported.timing);
+        assert_eq!(
+            chart.difficulties[0].timing,
+            exported.difficulties[0].timing
+        );
+        assert_eq!(hard.timing, exported.difficulties[1].timing);
+        assert_same_notes(&chart, &exported);
+
+        // The other formats put the notes in the right places, even where
+        // they can't keep the chart's own timing.
+        assert_same_notes(&chart, &from_simfile(&write_simfile(&chart)));
+        assert_same_notes(&chart, &through_beatmaps(&chart));
+    }
+
+    #[test]
+    fn charts_get_parsed_by_extension() {
+        assert_same_notes(&parse_chart(SIMFILE, "SM").unwrap(), &from_simfile(SIMFILE));
+        assert_same_notes(
+            &parse_chart(BEATMAP, "osu").unwrap(),
+            &from_beatmap(BEATMAP),
+        );
+        assert!(parse_chart(SIMFILE, "toml").is_err());
+    }
+
+    #[test]
+    fn scroll_changes_are_kept() {
+        let chart = from_beatmap(BEATMAP);
+        let scrolls = |chart: &SongConfigToml| {
+            chart
+                .timing
+                .as_ref()
+                .unwrap()
+                .scrolls
+                .iter()
+                .map(|change| (change.beat, change.scroll))
+                .collect::<Vec<_>>()
+        };
+        // Doubled from beat 16, and back to normal at the tempo change on
+        // beat 20.
+        assert_eq!(scrolls(&chart), vec![(16.0, 2.0), (20.0, 1.0)]);
+
+        let exported = from_beatmap(&write_beatmap(&chart, &chart.difficulties[0]));
+        for (a, b) in scrolls(&chart).iter().zip(&scrolls(&exported)) {
+            assert!((a.0 - b.0).abs() <= TOLERANCE);
+            assert!((a.1 - b.1).abs() <= TOLERANCE);
+        }
+    }
+
+    #[test]
+    fn metadata_is_kept() {
+        let chart = from_simfile(SIMFILE);
+        assert_eq!(chart.artist.as_deref(), Some("Someone"));
+        assert_eq!(chart.bpm_display.as_deref(), Some("150-200"));
+        assert_eq!(chart.jacket.as_deref(), Some("banner.png"));
+
+        let exported = from_simfile(&write_simfile(&chart));
+        assert_eq!(chart.artist, exported.artist);
+        assert_eq!(chart.charter, exported.charter);
+        assert_eq!(chart.genre, exported.genre);
+        assert_eq!(chart.bpm_display, exported.bpm_display);
+        assert_eq!(chart.preview_start, exported.preview_start);
+        assert_eq!(chart.preview_length, exported.preview_length);
+        assert_eq!(chart.background_image, exported.background_image);
+        assert_eq!(chart.jacket, exported.jacket);
+
+        // Beatmaps don't have anywhere to put the rest.
+        let exported = from_beatmap(&write_beatmap(&chart, &chart.difficulties[0]));
+        assert_eq!(chart.artist, exported.artist);
+        assert_eq!(chart.charter, exported.charter);
+        assert_eq!(chart.preview_start, exported.preview_start);
+        assert_eq!(chart.background_image, exported.background_image);
+    }
+
+    #[test]
+    fn old_charts_get_a_difficulty() {
+        let chart = parse_chart(
+            "name = \"Old\"\n\
+             filename = \"song.mp3\"\n\
+             \n\
+             [[arrows]]\n\
+             click_time = 1.5\n\
+             speed = \"Slow\"\n\
+             direction = \"Up\"\n",
+            "toml",
+        )
+        .unwrap();
+
+        assert!(chart.arrows.is_empty());
+        assert_eq!(chart.difficulties.len(), 1);
+        assert_eq!(chart.difficulties[0].name, DEFAULT_DIFFICULTY);
+        assert_eq!(chart.difficulties[0].arrows.len(), 1);
+        // Up used to be the top lane.
+        assert_eq!(chart.difficulties[0].arrows[0].lane, 0);
+    }
+
+    #[test]
+    fn speeds_can_be_any_number() {
+        let chart = |speed: &str| {
+            parse_chart(
+                &format!(
+                    "name = \"Speeds\"\n\
+                     filename = \"song.mp3\"\n\
+                     \n\
+                     [[arrows]]\n\
+                     click_time = 1.5\n\
+                     speed = {}\n\
+                     lane = 0\n",
+                    speed
+                ),
+                "toml",
+            )
+        };
+
+        let speed = |speed| chart(speed).unwrap().difficulties[0].arrows[0].speed;
+        assert_eq!(speed("1.35"), Speed(1.35));
+        assert_eq!(speed("2"), Speed(2.0));
+        assert_eq!(speed("\"Medium\""), Speed::MEDIUM);
+        assert!(chart("0").is_err());
+        assert!(chart("\"Ludicrous\"").is_err());
+    }
+}
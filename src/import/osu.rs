@@ -0,0 +1,460 @@
+//! Reads and writes osu!mania `.osu` beatmaps.
+
+use super::{non_empty, ImportError};
+use crate::{
+    lanes::Lanes,
+    timing::{BpmChange, ScrollChange, Timing, DEFAULT_BPM},
+    types::{ArrowTimeToml, DifficultyToml, SongConfigToml, Speed},
+};
+use std::convert::TryFrom;
+
+/// osu!'s id for the mania game mode.
+const MANIA_MODE: u32 = 3;
+
+/// Hit objects are placed in columns by splitting this width up evenly.
+const PLAYFIELD_WIDTH: f64 = 512.0;
+
+/// The hit object type bit for mania hold notes.
+const HOLD_TYPE: u32 = 128;
+
+/// An osu!mania beatmap. Each `.osu` file has exactly one difficulty in it.
+#[derive(Debug, Clone)]
+pub struct Beatmap {
+    pub title: String,
+    pub artist: String,
+    /// Who made the beatmap.
+    pub creator: String,
+    /// Difficulty name, like "Hard" or "Insane".
+    pub version: String,
+    /// Audio file, relative to the beatmap.
+    pub audio: String,
+    /// Where the song select preview starts, in seconds.
+    pub preview_time: Option<f64>,
+    /// Background image, relative to the beatmap.
+    pub background: String,
+    pub lanes: Lanes,
+    pub timing: Option<Timing>,
+    pub arrows: Vec<ArrowTimeToml>,
+}
+
+impl Beatmap {
+    /// Parses the contents of a `.osu` file.
+    pub fn parse(text: &str) -> Result<Self, ImportError> {
+        let mut title = String::new();
+        let mut artist = String::new();
+        let mut creator = String::new();
+        let mut version = String::new();
+        let mut audio = String::new();
+        let mut preview_time = None;
+        let mut background = String::new();
+        let mut mode = 0;
+        let mut keys = None;
+        let mut timing_points = Vec::new();
+        let mut hit_objects = Vec::new();
+
+        let mut section = "";
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                section = &line[1..line.len() - 1];
+                continue;
+            }
+
+            match section {
+                "General" | "Metadata" | "Difficulty" => {
+                    let (key, value) = match line.find(':') {
+                        Some(colon) => (line[..colon].trim(), line[colon + 1..].trim()),
+                        None => continue,
+                    };
+
+                    match key {
+                        "AudioFilename" => audio = value.to_string(),
+                        // -1 means there's no preview point.
+                        "PreviewTime" => {
+                            preview_time = value
+                                .parse::<f64>()
+                                .ok()
+                                .filter(|time| *time >= 0.0)
+                                .map(|time| time / 1000.0)
+                        }
+                        "Mode" => {
+                            mode = value
+                                .parse()
+                                .map_err(|_| ImportError::Parse(format!("bad Mode: {}", value)))?
+                        }
+                        "Title" => title = value.to_string(),
+                        "Artist" => artist = value.to_string(),
+                        "Creator" => creator = value.to_string(),
+                        "Version" => version = value.to_string(),
+                        // Mania uses the circle size as the key count.
+                        "CircleSize" => {
+                            keys = Some(value.parse::<f64>().map_err(|_| {
+                                ImportError::Parse(format!("bad CircleSize: {}", value))
+                            })?)
+                        }
+                        _ => {}
+                    }
+                }
+                // Backgrounds look like `0,0,"bg.jpg",0,0`.
+                "Events" => {
+                    let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+                    if fields.len() >= 3 && fields[0] == "0" && fields[1] == "0" {
+                        background = fields[2].trim_matches('"').to_string();
+                    }
+                }
+                "TimingPoints" => timing_points.push(line),
+                "HitObjects" => hit_objects.push(line),
+                _ => {}
+            }
+        }
+
+        if mode != MANIA_MODE {
+            return Err(ImportError::Unsupported(format!(
+                "only osu!mania beatmaps can be played, this one is for mode {}",
+                mode
+            )));
+        }
+
+        let lanes = match keys {
+            Some(keys) if keys.fract() == 0.0 && keys >= 0.0 => Lanes::try_from(keys as usize)
+                .map_err(|_| {
+                    ImportError::Unsupported(format!(
+                        "only 4K, 6K and 8K beatmaps can be played, this one is {}K",
+                        keys
+                    ))
+                })?,
+            Some(keys) => return Err(ImportError::Parse(format!("bad CircleSize: {}", keys))),
+            None => return Err(ImportError::Parse("missing CircleSize".to_string())),
+        };
+
+        let timing = parse_timing(&timing_points)?;
+
+        let arrows = hit_objects
+            .into_iter()
+            .map(|line| parse_hit_object(line, lanes))
+            .collect::<Result<Vec<_>, _>>()?;
+        if arrows.is_empty() {
+            return Err(ImportError::NoCharts);
+        }
+
+        Ok(Self {
+            title,
+            artist,
+            creator,
+            version,
+            audio,
+            preview_time,
+            background,
+            lanes,
+            timing,
+            arrows,
+        })
+    }
+
+    /// Converts the beatmap into our own format, as a song with one
+    /// difficulty.
+    pub fn to_config(&self) -> SongConfigToml {
+        SongConfigToml {
+            name: self.title.clone(),
+            filename: self.audio.clone(),
+            artist: non_empty(&self.artist),
+            charter: non_empty(&self.creator),
+            preview_start: self.preview_time,
+            background_image: non_empty(&self.background),
+            arrows: Vec::new(),
+            difficulties: vec![DifficultyToml {
+                name: self.version.clone(),
+                // Beatmaps don't rate themselves
+                level: 0,
+                lanes: self.lanes,
+                arrows: self.arrows.clone(),
+                timing: None,
+            }],
+            timing: self.timing.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Splits a comma separated line up, checking it has at least `count` fields.
+fn fields<'a>(line: &'a str, count: usize, what: &str) -> Result<Vec<&'a str>, ImportError> {
+    let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+    if fields.len() < count {
+        return Err(ImportError::Parse(format!("bad {}: {}", what, line)));
+    }
+
+    Ok(fields)
+}
+
+/// Parses a number out of a field.
+fn number(field: &str, line: &str, what: &str) -> Result<f64, ImportError> {
+    field
+        .parse()
+        .map_err(|_| ImportError::Parse(format!("bad {}: {}", what, line)))
+}
+
+/// Builds timing information out of the timing points. Uninherited ones
+/// change the tempo, and inherited ones change the scroll speed.
+fn parse_timing(lines: &[&str]) -> Result<Option<Timing>, ImportError> {
+    // (time in seconds, bpm)
+    let mut points = Vec::new();
+    // (time in seconds, scroll speed)
+    let mut scroll_points = Vec::new();
+
+    for line in lines {
+        let fields = fields(line, 2, "timing point")?;
+        let time = number(fields[0], line, "timing point")? / 1000.0;
+        let beat_length = number(fields[1], line, "timing point")?;
+
+        // Old beatmaps don't have the uninherited field, and mark inherited
+        // points with a negative beat length instead.
+        let uninherited = match fields.get(6) {
+            Some(field) => *field == "1",
+            None => beat_length > 0.0,
+        };
+        if !uninherited {
+            // Inherited points store their scroll speed as a negative
+            // percentage of the beat length.
+            if beat_length < 0.0 {
+                scroll_points.push((time, -100.0 / beat_length));
+            }
+            continue;
+        }
+
+        if beat_length <= 0.0 {
+            return Err(ImportError::Unsupported(
+                "negative or zero beat lengths aren't supported".to_string(),
+            ));
+        }
+
+        points.push((time, 60_000.0 / beat_length));
+        // Every uninherited point puts the scroll speed back to normal.
+        scroll_points.push((time, 1.0));
+    }
+
+    points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    // Inherited points go after uninherited ones at the same time, which the
+    // sort keeps since it's stable.
+    scroll_points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    let offset = match points.first() {
+        Some(&(time, _)) => time,
+        None => return Ok(None),
+    };
+
+    let mut bpms: Vec<BpmChange> = Vec::new();
+    let mut last_time = offset;
+    for (time, bpm) in points {
+        let beat = bpms
+            .last()
+            .map_or(0.0, |last| last.beat + (time - last_time) * last.bpm / 60.0);
+
+        bpms.push(BpmChange { beat, bpm });
+        last_time = time;
+    }
+
+    let mut timing = Timing {
+        offset,
+        bpms,
+        stops: Vec::new(),
+        scrolls: Vec::new(),
+    };
+
+    let mut scroll = 1.0;
+    for (time, next) in scroll_points {
+        if next != scroll {
+            timing.scrolls.push(ScrollChange {
+                beat: timing.time_to_beat(time),
+                scroll: next,
+            });
+            scroll = next;
+        }
+    }
+
+    Ok(Some(timing))
+}
+
+/// Turns a hit object into an arrow. Hold notes keep their end time in the
+/// extras field, before the first `:`.
+fn parse_hit_object(line: &str, lanes: Lanes) -> Result<ArrowTimeToml, ImportError> {
+    let fields = fields(line, 4, "hit object")?;
+
+    let x = number(fields[0], line, "hit object")?;
+    let column =
+        ((x * lanes.count() as f64 / PLAYFIELD_WIDTH).floor() as usize).min(lanes.count() - 1);
+    let click_time = number(fields[2], line, "hit object")? / 1000.0;
+    let kind = number(fields[3], line, "hit object")? as u32;
+
+    let hold = if kind & HOLD_TYPE != 0 {
+        let end = fields
+            .get(5)
+            .and_then(|extras| extras.split(':').next())
+            .ok_or_else(|| ImportError::Parse(format!("hold note has no end: {}", line)))?;
+
+        Some(number(end, line, "hold note end")? / 1000.0 - click_time)
+    } else {
+        None
+    };
+
+    Ok(ArrowTimeToml {
+        click_time,
+        speed: Speed::SLOW,
+        lane: lanes.lane_for_column(column),
+        hold,
+        mine: false,
+    })
+}
+
+/// Writes one of a chart's difficulties out as an osu!mania beatmap, with the
+/// difficulty's own timing if it has any. Charts without any timing get
+/// written at a constant tempo.
+pub fn write_beatmap(config: &SongConfigToml, difficulty: &DifficultyToml) -> String {
+    let timing = config
+        .timing_for(difficulty)
+        .cloned()
+        .unwrap_or_else(|| Timing::constant(DEFAULT_BPM, 0.0));
+
+    // (time in seconds, bpm)
+    let mut points = timing
+        .bpms
+        .iter()
+        .map(|change| (timing.beat_to_time(change.beat), change.bpm))
+        .collect::<Vec<_>>();
+    // osu! doesn't have stops, so the beat gets restarted once each one is over.
+    points.extend(timing.stops.iter().map(|stop| {
+        (
+            timing.beat_to_time(stop.beat) + stop.duration,
+            timing.bpm_at(stop.beat),
+        )
+    }));
+    points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    // (time in seconds, scroll speed)
+    let mut scrolls = timing
+        .scrolls
+        .iter()
+        .map(|change| (timing.beat_to_time(change.beat), change.scroll))
+        .collect::<Vec<_>>();
+    // Uninherited points put the scroll speed back to normal, so it gets set
+    // again after each one.
+    for (time, _) in points.iter() {
+        let scroll = timing
+            .scrolls
+            .iter()
+            .take_while(|change| timing.beat_to_time(change.beat) <= *time)
+            .last()
+            .map_or(1.0, |change| change.scroll);
+        if scroll != 1.0 {
+            scrolls.push((*time, scroll));
+        }
+    }
+    scrolls.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    scrolls.dedup();
+
+    // (time in seconds, timing point). Inherited points come after
+    // uninherited ones at the same time, which the sort keeps since it's
+    // stable.
+    let mut lines = points
+        .into_iter()
+        .map(|(time, bpm)| {
+            (
+                time,
+                format!("{},{},4,0,0,100,1,0", time * 1000.0, 60_000.0 / bpm),
+            )
+        })
+        .collect::<Vec<_>>();
+    // osu! can't go slower than a hundredth of the normal speed.
+    lines.extend(scrolls.into_iter().map(|(time, scroll)| {
+        (
+            time,
+            format!(
+                "{},{},4,0,0,100,0,0",
+                time * 1000.0,
+                -100.0 / scroll.max(0.01)
+            ),
+        )
+    }));
+    lines.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    let timing_points = lines
+        .into_iter()
+        .map(|(_, line)| line)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    // osu!mania doesn't have mines, so they get left out.
+    let mut arrows = difficulty
+        .arrows
+        .iter()
+        .filter(|arrow| !arrow.mine)
+        .copied()
+        .collect::<Vec<_>>();
+    arrows.sort_by(|a, b| a.click_time.partial_cmp(&b.click_time).unwrap());
+
+    let ms = |time: f64| (time * 1000.0).round() as i64;
+    let hit_objects = arrows
+        .iter()
+        .map(|arrow| {
+            let lanes = difficulty.lanes;
+            let column = lanes.column_for_lane(arrow.lane);
+            // The middle of the column
+            let x = ((column as f64 + 0.5) * PLAYFIELD_WIDTH / lanes.count() as f64) as i64;
+
+            match arrow.hold {
+                Some(hold) => format!(
+                    "{},192,{},{},0,{}:0:0:0:0:",
+                    x,
+                    ms(arrow.click_time),
+                    HOLD_TYPE,
+                    ms(arrow.click_time + hold)
+                ),
+                None => format!("{},192,{},1,0,0:0:0:0:", x, ms(arrow.click_time)),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!(
+        "osu file format v14\n\
+         \n\
+         [General]\n\
+         AudioFilename: {}\n\
+         PreviewTime: {}\n\
+         Mode: {}\n\
+         \n\
+         [Metadata]\n\
+         Title:{}\n\
+         Artist:{}\n\
+         Creator:{}\n\
+         Version:{}\n\
+         \n\
+         [Difficulty]\n\
+         CircleSize:{}\n\
+         \n\
+         [Events]\n\
+         {}\n\
+         [TimingPoints]\n\
+         {}\n\
+         \n\
+         [HitObjects]\n\
+         {}\n",
+        config.filename,
+        config.preview_start.map_or(-1, ms),
+        MANIA_MODE,
+        config.name,
+        config.artist.as_deref().unwrap_or_default(),
+        config.charter.as_deref().unwrap_or_default(),
+        difficulty.name,
+        difficulty.lanes.count(),
+        config
+            .background_image
+            .as_ref()
+            .map_or_else(String::new, |image| format!("0,0,\"{}\",0,0\n", image)),
+        timing_points,
+        hit_objects,
+    )
+}
@@ -0,0 +1,811 @@
+//! Reads and writes StepMania `.sm` and `.ssc` simfiles.
+
+use super::{non_empty, ImportError};
+use crate::{
+    lanes::Lanes,
+    timing::{BpmChange, ScrollChange, Stop, Timing, DEFAULT_BPM},
+    types::{ArrowTimeToml, DifficultyToml, SongConfigToml, Speed},
+};
+use std::convert::TryFrom;
+
+/// Kinds of charts we can play, and how many panels each has.
+const STEPS_TYPES: [(&str, usize); 3] =
+    [("dance-single", 4), ("dance-solo", 6), ("dance-double", 8)];
+
+/// How far off an exported note is allowed to be from where it should be, in
+/// seconds.
+const EXPORT_TOLERANCE: f64 = 0.0005;
+
+/// Row counts that get tried for each exported measure, coarsest first.
+/// StepMania itself rounds everything to 192nds, so the finer ones are only
+/// for notes that don't sit on the beat grid at all.
+const MEASURE_ROWS: [usize; 16] = [
+    4, 8, 12, 16, 24, 32, 48, 64, 96, 192, 384, 768, 1536, 3072, 6144, 12288,
+];
+
+/// A single chart from a simfile.
+#[derive(Debug, Clone)]
+pub struct SimfileChart {
+    /// Difficulty name, like "Hard" or "Challenge".
+    pub difficulty: String,
+    /// Numeric difficulty rating.
+    pub meter: u32,
+    pub lanes: Lanes,
+    /// The chart's own timing if it has any, or else the song's.
+    pub timing: Timing,
+    pub arrows: Vec<ArrowTimeToml>,
+}
+
+/// A StepMania simfile. Only holds the charts with a steps type in
+/// [`STEPS_TYPES`], since those are the only ones that map onto our lanes.
+#[derive(Debug, Clone)]
+pub struct Simfile {
+    pub title: String,
+    pub artist: String,
+    /// Who made the simfile.
+    pub credit: String,
+    pub genre: String,
+    pub display_bpm: String,
+    /// Audio file, relative to the simfile.
+    pub music: String,
+    /// Where the song select preview starts and how long it is, in seconds.
+    pub sample_start: Option<f64>,
+    pub sample_length: Option<f64>,
+    /// Image files, relative to the simfile.
+    pub background: String,
+    pub jacket: String,
+    /// The song's timing. `.ssc` files can leave it out if every chart has
+    /// its own.
+    pub timing: Option<Timing>,
+    pub charts: Vec<SimfileChart>,
+}
+
+/// A chart's raw tags, before being turned into a [`SimfileChart`].
+#[derive(Debug, Default)]
+struct RawChart {
+    steps_type: String,
+    difficulty: String,
+    meter: String,
+    notes: String,
+    // `.ssc` files can give each chart its own timing.
+    offset: Option<String>,
+    bpms: Option<String>,
+    stops: Option<String>,
+    scrolls: Option<String>,
+}
+
+impl Simfile {
+    /// Parses the contents of a `.sm` or `.ssc` file.
+    pub fn parse(text: &str) -> Result<Self, ImportError> {
+        let mut title = String::new();
+        let mut artist = String::new();
+        let mut credit = String::new();
+        let mut genre = String::new();
+        let mut display_bpm = String::new();
+        let mut music = String::new();
+        let mut sample_start = None;
+        let mut sample_length = None;
+        let mut background = String::new();
+        let mut banner = String::new();
+        let mut jacket = String::new();
+        let mut offset = None;
+        let mut bpms = None;
+        let mut stops = None;
+        let mut scrolls = None;
+
+        let mut raw_charts: Vec<RawChart> = Vec::new();
+        // Set while reading the tags of an `.ssc` chart.
+        let mut current: Option<RawChart> = None;
+
+        for (tag, value) in tags(text) {
+            // Starts a new chart in an `.ssc` file.
+            if tag == "NOTEDATA" {
+                raw_charts.extend(current.replace(RawChart::default()));
+                continue;
+            }
+
+            match (tag.as_str(), current.as_mut()) {
+                ("STEPSTYPE", Some(chart)) => chart.steps_type = value.trim().to_string(),
+                ("DIFFICULTY", Some(chart)) => chart.difficulty = value.trim().to_string(),
+                ("METER", Some(chart)) => chart.meter = value.trim().to_string(),
+                ("OFFSET", Some(chart)) => chart.offset = Some(value),
+                ("BPMS", Some(chart)) => chart.bpms = Some(value),
+                ("STOPS", Some(chart)) | ("FREEZES", Some(chart)) => chart.stops = Some(value),
+                ("SCROLLS", Some(chart)) => chart.scrolls = Some(value),
+                ("NOTES", Some(chart)) => chart.notes = value,
+
+                // `.sm` files put everything about a chart into one tag:
+                // type, author, difficulty, meter, groove radar and notes.
+                ("NOTES", None) => {
+                    let fields = value.splitn(6, ':').collect::<Vec<_>>();
+                    if fields.len() < 6 {
+                        return Err(ImportError::Parse(
+                            "#NOTES needs six fields separated by ':'".to_string(),
+                        ));
+                    }
+
+                    raw_charts.push(RawChart {
+                        steps_type: fields[0].trim().to_string(),
+                        difficulty: fields[2].trim().to_string(),
+                        meter: fields[3].trim().to_string(),
+                        notes: fields[5].to_string(),
+                        ..Default::default()
+                    });
+                }
+
+                ("TITLE", None) => title = value.trim().to_string(),
+                ("ARTIST", None) => artist = value.trim().to_string(),
+                ("CREDIT", None) => credit = value.trim().to_string(),
+                ("GENRE", None) => genre = value.trim().to_string(),
+                // Tempo ranges are written like `120:240`.
+                ("DISPLAYBPM", None) => display_bpm = value.trim().replace(':', "-"),
+                ("MUSIC", None) => music = value.trim().to_string(),
+                ("SAMPLESTART", None) => sample_start = value.trim().parse().ok(),
+                ("SAMPLELENGTH", None) => sample_length = value.trim().parse().ok(),
+                ("BACKGROUND", None) => background = value.trim().to_string(),
+                ("BANNER", None) => banner = value.trim().to_string(),
+                ("JACKET", None) => jacket = value.trim().to_string(),
+                ("OFFSET", None) => offset = Some(value),
+                ("BPMS", None) => bpms = Some(value),
+                ("STOPS", None) | ("FREEZES", None) => stops = Some(value),
+                ("SCROLLS", None) => scrolls = Some(value),
+
+                _ => {}
+            }
+        }
+        raw_charts.extend(current);
+
+        let timing = bpms
+            .as_ref()
+            .map(|_| {
+                parse_timing(
+                    offset.as_deref(),
+                    bpms.as_deref(),
+                    stops.as_deref(),
+                    scrolls.as_deref(),
+                )
+            })
+            .transpose()?;
+
+        let mut charts = Vec::new();
+        for raw in raw_charts {
+            let lanes = match STEPS_TYPES
+                .iter()
+                .find(|(steps_type, _)| raw.steps_type.eq_ignore_ascii_case(steps_type))
+            {
+                Some((_, count)) => Lanes::try_from(*count).unwrap(),
+                None => continue,
+            };
+
+            let timing = parse_timing(
+                raw.offset.as_deref().or(offset.as_deref()),
+                raw.bpms.as_deref().or(bpms.as_deref()),
+                raw.stops.as_deref().or(stops.as_deref()),
+                raw.scrolls.as_deref().or(scrolls.as_deref()),
+            )?;
+            let arrows = parse_notes(&raw.notes, lanes, &timing)?;
+
+            charts.push(SimfileChart {
+                difficulty: raw.difficulty,
+                meter: raw.meter.parse().unwrap_or(0),
+                lanes,
+                timing,
+                arrows,
+            });
+        }
+
+        if charts.is_empty() {
+            return Err(ImportError::NoCharts);
+        }
+
+        // Banners are the closest thing older simfiles have to a jacket.
+        if jacket.is_empty() {
+            jacket = banner;
+        }
+
+        Ok(Self {
+            title,
+            artist,
+            credit,
+            genre,
+            display_bpm,
+            music,
+            sample_start,
+            sample_length,
+            background,
+            jacket,
+            timing,
+            charts,
+        })
+    }
+
+    /// Converts the simfile into our own format, with a difficulty for each
+    /// chart. `.ssc` charts that have their own timing keep it. If the song
+    /// doesn't have any, the hardest chart's gets used for it.
+    pub fn to_config(&self) -> SongConfigToml {
+        let mut charts = self.charts.iter().collect::<Vec<_>>();
+        charts.sort_by_key(|chart| chart.meter);
+        let timing = self
+            .timing
+            .clone()
+            .or_else(|| charts.last().map(|chart| chart.timing.clone()));
+
+        SongConfigToml {
+            name: self.title.clone(),
+            filename: self.music.clone(),
+            artist: non_empty(&self.artist),
+            charter: non_empty(&self.credit),
+            genre: non_empty(&self.genre),
+            // `*` means the BPM is a secret.
+            bpm_display: non_empty(&self.display_bpm).map(|bpm| bpm.replace('*', "???")),
+            preview_start: self.sample_start,
+            preview_length: self.sample_length,
+            background_image: non_empty(&self.background),
+            jacket: non_empty(&self.jacket),
+            difficulties: charts
+                .iter()
+                .map(|chart| DifficultyToml {
+                    name: chart.difficulty.clone(),
+                    level: chart.meter,
+                    lanes: chart.lanes,
+                    arrows: chart.arrows.clone(),
+                    timing: Some(chart.timing.clone()).filter(|own| Some(own) != timing.as_ref()),
+                })
+                .collect(),
+            timing,
+            ..Default::default()
+        }
+    }
+}
+
+/// Splits a simfile up into `#TAG:value;` pairs. Tag names get uppercased.
+/// Only a `;` ends a value, so values can have a `#` in them, like a song
+/// called "Prelude in C#". Tags start with a `#` at the start of a line, or
+/// right after the last tag's `;`.
+fn tags(text: &str) -> Vec<(String, String)> {
+    let mut tags = Vec::new();
+    // The tag being read, from its name up to where it's got to
+    let mut current: Option<String> = None;
+
+    for line in text.lines() {
+        // Strip out comments
+        let mut line = line.find("//").map_or(line, |i| &line[..i]);
+
+        loop {
+            if let Some(start) = line.trim_start().strip_prefix('#') {
+                // Some simfiles forget the semicolon, so a new tag ends the
+                // value too.
+                if let Some(tag) = current.take() {
+                    push_tag(&mut tags, &tag);
+                }
+                current = Some(String::new());
+                line = start;
+            }
+
+            let tag = match current.as_mut() {
+                Some(tag) => tag,
+                None => break,
+            };
+            match line.find(';') {
+                Some(end) => {
+                    tag.push_str(&line[..end]);
+                    push_tag(&mut tags, tag);
+                    current = None;
+                    line = &line[end + 1..];
+                }
+                None => {
+                    tag.push_str(line);
+                    tag.push('\n');
+                    break;
+                }
+            }
+        }
+    }
+
+    if let Some(tag) = current {
+        push_tag(&mut tags, &tag);
+    }
+
+    tags
+}
+
+/// Adds a `TAG:value` pair to `tags`. Anything without a colon gets skipped.
+fn push_tag(tags: &mut Vec<(String, String)>, tag: &str) {
+    if let Some(colon) = tag.find(':') {
+        tags.push((
+            tag[..colon].trim().to_uppercase(),
+            tag[colon + 1..].to_string(),
+        ));
+    }
+}
+
+/// Parses a list of `beat=value` pairs, like the ones in `#BPMS` and `#STOPS`.
+fn parse_pairs(value: &str, tag: &str) -> Result<Vec<(f64, f64)>, ImportError> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut parts = pair.split('=');
+            let mut next = || {
+                parts
+                    .next()
+                    .and_then(|part| part.trim().parse::<f64>().ok())
+                    .ok_or_else(|| ImportError::Parse(format!("bad #{} entry: {}", tag, pair)))
+            };
+
+            Ok((next()?, next()?))
+        })
+        .collect()
+}
+
+/// Builds timing information out of the `#OFFSET`, `#BPMS` and `#STOPS` tags,
+/// and the `#SCROLLS` tag `.ssc` files can have.
+fn parse_timing(
+    offset: Option<&str>,
+    bpms: Option<&str>,
+    stops: Option<&str>,
+    scrolls: Option<&str>,
+) -> Result<Timing, ImportError> {
+    let offset = match offset.map(str::trim).filter(|offset| !offset.is_empty()) {
+        Some(offset) => offset
+            .parse::<f64>()
+            .map_err(|_| ImportError::Parse(format!("bad #OFFSET: {}", offset)))?,
+        None => 0.0,
+    };
+
+    let mut bpms = parse_pairs(bpms.unwrap_or_default(), "BPMS")?
+        .into_iter()
+        .map(|(beat, bpm)| BpmChange { beat, bpm })
+        .collect::<Vec<_>>();
+    if bpms.is_empty() {
+        return Err(ImportError::Parse("missing #BPMS".to_string()));
+    }
+    if bpms.iter().any(|change| change.bpm <= 0.0) {
+        return Err(ImportError::Unsupported(
+            "negative or zero BPMs aren't supported".to_string(),
+        ));
+    }
+    bpms.sort_by(|a, b| a.beat.partial_cmp(&b.beat).unwrap());
+
+    let mut stops = parse_pairs(stops.unwrap_or_default(), "STOPS")?
+        .into_iter()
+        .map(|(beat, duration)| Stop { beat, duration })
+        .filter(|stop| stop.duration != 0.0)
+        .collect::<Vec<_>>();
+    if stops.iter().any(|stop| stop.duration < 0.0) {
+        return Err(ImportError::Unsupported(
+            "negative stops aren't supported".to_string(),
+        ));
+    }
+    stops.sort_by(|a, b| a.beat.partial_cmp(&b.beat).unwrap());
+
+    let mut scrolls = parse_pairs(scrolls.unwrap_or_default(), "SCROLLS")?
+        .into_iter()
+        .map(|(beat, scroll)| ScrollChange { beat, scroll })
+        .collect::<Vec<_>>();
+    if scrolls.iter().any(|change| change.scroll < 0.0) {
+        return Err(ImportError::Unsupported(
+            "negative scroll speeds aren't supported".to_string(),
+        ));
+    }
+    scrolls.sort_by(|a, b| a.beat.partial_cmp(&b.beat).unwrap());
+
+    Ok(Timing {
+        // StepMania's offset is how far into the audio beat 0 is, negated.
+        offset: -offset,
+        bpms,
+        stops,
+        scrolls,
+    })
+}
+
+/// Turns a chart's note data into arrows. Measures are separated by commas,
+/// each with some number of evenly spaced rows, and each row has one character
+/// per column.
+fn parse_notes(
+    notes: &str,
+    lanes: Lanes,
+    timing: &Timing,
+) -> Result<Vec<ArrowTimeToml>, ImportError> {
+    let mut arrows: Vec<ArrowTimeToml> = Vec::new();
+    // Hold and roll heads that haven't been ended yet, by column.
+    let mut holds: Vec<Option<usize>> = vec![None; lanes.count()];
+
+    for (measure, rows) in notes.split(',').enumerate() {
+        let rows = rows.split_whitespace().collect::<Vec<_>>();
+
+        for (i, row) in rows.iter().enumerate() {
+            if row.chars().count() != lanes.count() {
+                return Err(ImportError::Parse(format!(
+                    "row '{}' in measure {} doesn't have {} columns",
+                    row,
+                    measure,
+                    lanes.count()
+                )));
+            }
+
+            let beat = measure as f64 * 4.0 + i as f64 * 4.0 / rows.len() as f64;
+            let click_time = timing.beat_to_time(beat);
+
+            for (column, note) in row.chars().enumerate() {
+                match note {
+                    // Taps and lifts both get hit like a tap.
+                    '1' | 'L' | '2' | '4' | 'M' => {
+                        // Rolls get played like holds.
+                        if let '2' | '4' = note {
+                            holds[column] = Some(arrows.len());
+                        }
+
+                        arrows.push(ArrowTimeToml {
+                            click_time,
+                            speed: Speed::SLOW,
+                            lane: lanes.lane_for_column(column),
+                            hold: None,
+                            mine: note == 'M',
+                        });
+                    }
+                    '3' => {
+                        if let Some(head) = holds[column].take() {
+                            let arrow = &mut arrows[head];
+                            arrow.hold = Some(click_time - arrow.click_time);
+                        }
+                    }
+                    // Fakes don't get hit at all.
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    Ok(arrows)
+}
+
+/// A note (or the end of a hold) that's being exported, along with where it
+/// should go.
+struct Step {
+    beat: f64,
+    time: f64,
+    column: usize,
+    note: char,
+    /// For hold ends, which step the hold started at.
+    head: Option<usize>,
+}
+
+/// Beat that a row in some measure lands on.
+fn row_beat(measure: usize, row: usize, rows: usize) -> f64 {
+    measure as f64 * 4.0 + row as f64 * 4.0 / rows as f64
+}
+
+/// Row of a measure that's closest to some beat. Might be one past the end.
+fn closest_row(beat: f64, measure: usize, rows: usize) -> usize {
+    ((beat - measure as f64 * 4.0) * rows as f64 / 4.0)
+        .round()
+        .max(0.0) as usize
+}
+
+/// Picks the fewest rows a measure can be split into while keeping all of its
+/// steps close enough to their times.
+fn measure_rows(steps: &[&Step], measure: usize, timing: &Timing) -> usize {
+    let fits = |rows: usize| {
+        steps.iter().all(|step| {
+            let row = closest_row(step.beat, measure, rows);
+            row < rows
+                && (timing.beat_to_time(row_beat(measure, row, rows)) - step.time).abs()
+                    <= EXPORT_TOLERANCE
+        })
+    };
+
+    MEASURE_ROWS
+        .iter()
+        .copied()
+        .find(|&rows| fits(rows))
+        .unwrap_or(MEASURE_ROWS[MEASURE_ROWS.len() - 1])
+}
+
+/// StepMania only knows about a few difficulty names, so ours get matched up
+/// with the closest one.
+fn simfile_difficulty(name: &str) -> &'static str {
+    match name.to_lowercase().as_str() {
+        "beginner" => "Beginner",
+        "easy" | "light" => "Easy",
+        "medium" | "normal" | "standard" => "Medium",
+        "hard" | "heavy" => "Hard",
+        "challenge" | "expert" | "oni" => "Challenge",
+        _ => "Edit",
+    }
+}
+
+/// Lays a difficulty's arrows out into measures and rows.
+fn write_notes(arrows: &[ArrowTimeToml], lanes: Lanes, timing: &Timing) -> String {
+    let mut steps = Vec::new();
+    for arrow in arrows {
+        let column = lanes.column_for_lane(arrow.lane);
+        let head = steps.len();
+
+        steps.push(Step {
+            beat: timing.time_to_beat(arrow.click_time),
+            time: arrow.click_time,
+            column,
+            note: if arrow.mine {
+                'M'
+            } else if arrow.hold.is_some() {
+                '2'
+            } else {
+                '1'
+            },
+            head: None,
+        });
+
+        if let Some(hold) = arrow.hold {
+            let time = arrow.click_time + hold;
+            steps.push(Step {
+                beat: timing.time_to_beat(time),
+                time,
+                column,
+                note: '3',
+                head: Some(head),
+            });
+        }
+    }
+
+    // Steps a hair before a barline (thanks to rounding) belong after it.
+    let measure_of = |step: &Step| ((step.beat + 1e-6) / 4.0).floor().max(0.0) as usize;
+    let measures = steps
+        .iter()
+        .map(measure_of)
+        .max()
+        .map_or(1, |last| last + 1);
+
+    let rows = (0..measures)
+        .map(|measure| {
+            let in_measure = steps
+                .iter()
+                .filter(|step| measure_of(step) == measure)
+                .collect::<Vec<_>>();
+            measure_rows(&in_measure, measure, timing)
+        })
+        .collect::<Vec<_>>();
+
+    // Where each step ends up: (measure, row).
+    let places = steps
+        .iter()
+        .map(|step| {
+            let measure = measure_of(step);
+            let row = closest_row(step.beat, measure, rows[measure]).min(rows[measure] - 1);
+            (measure, row)
+        })
+        .collect::<Vec<_>>();
+    let placed_beat = |i: usize| row_beat(places[i].0, places[i].1, rows[places[i].0]);
+
+    // Holds that got squashed down to nothing turn into taps.
+    for i in 0..steps.len() {
+        if let Some(head) = steps[i].head {
+            if placed_beat(i) <= placed_beat(head) {
+                steps[head].note = '1';
+                steps[i].note = '0';
+            }
+        }
+    }
+
+    let mut grid = rows
+        .iter()
+        .map(|&rows| vec![vec!['0'; lanes.count()]; rows])
+        .collect::<Vec<_>>();
+    // Hold ends go in first, so nothing that starts on the same row gets lost.
+    let (ends, starts): (Vec<_>, Vec<_>) = steps
+        .iter()
+        .zip(&places)
+        .partition(|(step, _)| step.note == '3');
+    for (step, &(measure, row)) in ends.into_iter().chain(starts) {
+        if step.note != '0' {
+            grid[measure][row][step.column] = step.note;
+        }
+    }
+
+    grid.iter()
+        .map(|measure| {
+            measure
+                .iter()
+                .map(|row| row.iter().collect::<String>())
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .collect::<Vec<_>>()
+        .join("\n,\n")
+}
+
+/// Tags for whichever bits of a chart's metadata it has.
+fn optional_tags(config: &SongConfigToml) -> Vec<(&'static str, String)> {
+    let mut tags = Vec::new();
+    let mut tag =
+        |name: &'static str, value: Option<String>| tags.extend(value.map(|value| (name, value)));
+
+    tag("ARTIST", config.artist.clone());
+    tag("CREDIT", config.charter.clone());
+    tag("GENRE", config.genre.clone());
+    tag(
+        "DISPLAYBPM",
+        config.bpm_display.as_ref().map(|bpm| bpm.replace('-', ":")),
+    );
+    tag(
+        "SAMPLESTART",
+        config.preview_start.map(|start| format!("{:.6}", start)),
+    );
+    tag(
+        "SAMPLELENGTH",
+        config.preview_length.map(|length| format!("{:.6}", length)),
+    );
+    tag("BACKGROUND", config.background_image.clone());
+    tag("JACKET", config.jacket.clone());
+
+    tags
+}
+
+/// Takes out anything that would end a tag early.
+fn clean(value: &str) -> String {
+    value.replace(';', "")
+}
+
+/// Timing to export some arrows with, going by the chart's own if it has any.
+/// Simfiles can't have notes before beat 0, so it gets moved back by whole
+/// measures until they fit.
+fn export_timing<'a>(
+    timing: Option<&Timing>,
+    arrows: impl Iterator<Item = &'a ArrowTimeToml>,
+) -> Timing {
+    let timing = timing
+        .cloned()
+        .unwrap_or_else(|| Timing::constant(DEFAULT_BPM, 0.0));
+
+    let first_beat = arrows
+        .map(|arrow| timing.time_to_beat(arrow.click_time))
+        .fold(0.0, f64::min);
+    if first_beat < 0.0 {
+        timing.shifted((-first_beat / 4.0).ceil() * 4.0)
+    } else {
+        timing
+    }
+}
+
+/// `#OFFSET`, `#BPMS` and `#STOPS` tags for some timing, and `#SCROLLS` if
+/// `scrolls` is true.
+fn timing_tags(timing: &Timing, scrolls: bool) -> String {
+    let pairs = |tag: &str, pairs: Vec<(f64, f64)>| {
+        format!(
+            "#{}:{};\n",
+            tag,
+            pairs
+                .into_iter()
+                .map(|(beat, value)| format!("{:.6}={:.6}", beat, value))
+                .collect::<Vec<_>>()
+                .join(",")
+        )
+    };
+
+    let mut text = format!("#OFFSET:{:.6};\n", -timing.offset);
+    text += &pairs(
+        "BPMS",
+        timing
+            .bpms
+            .iter()
+            .map(|change| (change.beat, change.bpm))
+            .collect(),
+    );
+    text += &pairs(
+        "STOPS",
+        timing
+            .stops
+            .iter()
+            .map(|stop| (stop.beat, stop.duration))
+            .collect(),
+    );
+    if scrolls {
+        text += &pairs(
+            "SCROLLS",
+            timing
+                .scrolls
+                .iter()
+                .map(|change| (change.beat, change.scroll))
+                .collect(),
+        );
+    }
+
+    text
+}
+
+/// Tags for the song itself, up to and including its timing.
+fn song_tags(config: &SongConfigToml, timing: &Timing, scrolls: bool) -> String {
+    format!(
+        "#TITLE:{};\n{}#MUSIC:{};\n{}",
+        clean(&config.name),
+        optional_tags(config)
+            .into_iter()
+            .map(|(tag, value)| format!("#{}:{};\n", tag, clean(&value)))
+            .collect::<String>(),
+        clean(&config.filename),
+        timing_tags(timing, scrolls),
+    )
+}
+
+/// The steps type a difficulty gets saved as.
+fn steps_type(difficulty: &DifficultyToml) -> &'static str {
+    STEPS_TYPES
+        .iter()
+        .find(|(_, count)| *count == difficulty.lanes.count())
+        .map(|(steps_type, _)| *steps_type)
+        .unwrap()
+}
+
+/// Writes a chart out as a `.sm` simfile, with a `#NOTES` tag for each
+/// difficulty. Charts without any timing get written at a constant tempo.
+/// `.sm` files only have the one timing and can't change scroll speed, so
+/// difficulties with their own timing get laid out on the song's, and scroll
+/// changes get left out.
+pub fn write_simfile(config: &SongConfigToml) -> String {
+    let timing = export_timing(
+        config.timing.as_ref(),
+        config
+            .difficulties
+            .iter()
+            .flat_map(|difficulty| difficulty.arrows.iter()),
+    );
+
+    let mut text = song_tags(config, &timing, false);
+
+    for difficulty in &config.difficulties {
+        text += &format!(
+            "\n#NOTES:\n     {}:\n     {}:\n     {}:\n     {}:\n     0,0,0,0,0:\n{}\n;\n",
+            steps_type(difficulty),
+            // The description field, which is where edits keep their names.
+            clean(&difficulty.name).replace(':', ""),
+            simfile_difficulty(&difficulty.name),
+            difficulty.level.max(1),
+            write_notes(&difficulty.arrows, difficulty.lanes, &timing),
+        );
+    }
+
+    text
+}
+
+/// Writes a chart out as a `.ssc` simfile, with a `#NOTEDATA` section for each
+/// difficulty. Difficulties with their own timing get it written into their
+/// section. Charts without any timing get written at a constant tempo.
+pub fn write_ssc(config: &SongConfigToml) -> String {
+    let timing = export_timing(
+        config.timing.as_ref(),
+        config
+            .difficulties
+            .iter()
+            .filter(|difficulty| difficulty.timing.is_none())
+            .flat_map(|difficulty| difficulty.arrows.iter()),
+    );
+
+    let mut text = format!("#VERSION:0.83;\n{}", song_tags(config, &timing, true));
+
+    for difficulty in &config.difficulties {
+        let own_timing = difficulty
+            .timing
+            .as_ref()
+            .map(|own| export_timing(Some(own), difficulty.arrows.iter()));
+
+        text += &format!(
+            "\n#NOTEDATA:;\n#STEPSTYPE:{};\n#DESCRIPTION:{};\n#DIFFICULTY:{};\n#METER:{};\n{}#NOTES:\n{}\n;\n",
+            steps_type(difficulty),
+            clean(&difficulty.name),
+            simfile_difficulty(&difficulty.name),
+            difficulty.level.max(1),
+            own_timing
+                .as_ref()
+                .map(|own| timing_tags(own, true))
+                .unwrap_or_default(),
+            write_notes(
+                &difficulty.arrows,
+                difficulty.lanes,
+                own_timing.as_ref().unwrap_or(&timing),
+            ),
+        );
+    }
+
+    text
+}
@@ -0,0 +1,192 @@
+use crate::types::Directions::{self, *};
+use bevy::input::{keyboard::KeyCode, Input};
+use serde::{de::Error, Deserializer};
+use serde_derive::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// Index of a lane, counting from the top of the screen.
+pub type Lane = usize;
+
+/// Lane counts that charts can be made for.
+pub const LANE_COUNTS: [usize; 3] = [4, 6, 8];
+
+/// How tall all the lanes are together, in pixels.
+const LANES_HEIGHT: f32 = 400.0;
+
+/// How many lanes (or keys) a chart is played with. Lane layout, bindings and
+/// arrow directions all come from this.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "usize", into = "usize")]
+pub struct Lanes(usize);
+
+impl Default for Lanes {
+    fn default() -> Self {
+        Self(4)
+    }
+}
+
+impl TryFrom<usize> for Lanes {
+    type Error = String;
+
+    fn try_from(count: usize) -> Result<Self, Self::Error> {
+        if LANE_COUNTS.contains(&count) {
+            Ok(Self(count))
+        } else {
+            Err(format!("charts can't have {} lanes", count))
+        }
+    }
+}
+
+impl From<Lanes> for usize {
+    fn from(lanes: Lanes) -> Self {
+        lanes.0
+    }
+}
+
+impl Lanes {
+    pub fn count(self) -> usize {
+        self.0
+    }
+
+    /// Every lane, from top to bottom.
+    pub fn iter(self) -> impl Iterator<Item = Lane> {
+        0..self.0
+    }
+
+    /// The next lane count along, wrapping back around to the first.
+    pub fn next(self) -> Self {
+        let index = LANE_COUNTS.iter().position(|count| *count == self.0);
+        Self(LANE_COUNTS[index.map_or(0, |index| (index + 1) % LANE_COUNTS.len())])
+    }
+
+    /// Short name for the lane count, like "6K".
+    pub fn label(self) -> String {
+        format!("{}K", self.0)
+    }
+
+    /// Distance between the centers of two lanes.
+    pub fn spacing(self) -> f32 {
+        LANES_HEIGHT / self.0 as f32
+    }
+
+    /// How tall all the lanes are together.
+    pub fn height(self) -> f32 {
+        LANES_HEIGHT
+    }
+
+    /// How much arrows need to be scaled by to fit in their lanes. Arrows are
+    /// full size with four lanes.
+    pub fn scale(self) -> f32 {
+        self.spacing() / 100.0
+    }
+
+    /// The y coordinate of the middle of a lane.
+    pub fn y(self, lane: Lane) -> f32 {
+        self.spacing() * ((self.0 - 1) as f32 / 2.0 - lane as f32)
+    }
+
+    /// Finds which lane a y coordinate is in.
+    pub fn lane_at(self, y: f32) -> Option<Lane> {
+        self.iter()
+            .find(|lane| (self.y(*lane) - y).abs() <= self.spacing() / 2.0)
+    }
+
+    /// Which way the arrows in a lane point.
+    pub fn direction(self, lane: Lane) -> Directions {
+        match self.0 {
+            6 => [Left, UpLeft, Down, Up, UpRight, Right][lane],
+            8 => [Up, Down, Left, Right][lane % 4],
+            _ => [Up, Down, Left, Right][lane],
+        }
+    }
+
+    /// The rotation of arrows in a lane.
+    pub fn rotation(self, lane: Lane) -> f32 {
+        self.direction(lane).rotation()
+    }
+
+    fn keys(self, lane: Lane) -> &'static [KeyCode] {
+        const SIX_KEYS: [KeyCode; 6] = [
+            KeyCode::S,
+            KeyCode::D,
+            KeyCode::F,
+            KeyCode::J,
+            KeyCode::K,
+            KeyCode::L,
+        ];
+        const EIGHT_KEYS: [KeyCode; 8] = [
+            KeyCode::A,
+            KeyCode::S,
+            KeyCode::D,
+            KeyCode::F,
+            KeyCode::J,
+            KeyCode::K,
+            KeyCode::L,
+            KeyCode::Semicolon,
+        ];
+        const FOUR_KEYS: [[KeyCode; 2]; 4] = [
+            [KeyCode::Up, KeyCode::W],
+            [KeyCode::Down, KeyCode::S],
+            [KeyCode::Left, KeyCode::A],
+            [KeyCode::Right, KeyCode::D],
+        ];
+
+        match self.0 {
+            6 => std::slice::from_ref(&SIX_KEYS[lane]),
+            8 => std::slice::from_ref(&EIGHT_KEYS[lane]),
+            _ => &FOUR_KEYS[lane],
+        }
+    }
+
+    /// Checks if a key for this lane has *just* been pressed.
+    pub fn key_just_pressed(self, lane: Lane, input: &Input<KeyCode>) -> bool {
+        self.keys(lane).iter().any(|code| input.just_pressed(*code))
+    }
+
+    /// Checks if a key for this lane is *currently* being pressed.
+    pub fn key_pressed(self, lane: Lane, input: &Input<KeyCode>) -> bool {
+        self.keys(lane).iter().any(|code| input.pressed(*code))
+    }
+
+    /// Which lane a column of a StepMania or osu!mania chart goes in. Four key
+    /// charts there go left, down, up, right, but our lanes go up, down, left,
+    /// right.
+    pub fn lane_for_column(self, column: usize) -> Lane {
+        match self.0 {
+            4 => [2, 1, 0, 3][column],
+            _ => column,
+        }
+    }
+
+    /// Which column of a StepMania or osu!mania chart a lane goes in.
+    pub fn column_for_lane(self, lane: Lane) -> usize {
+        // Swapping the lanes around is its own inverse
+        self.lane_for_column(lane)
+    }
+}
+
+/// Lanes that arrows in four lane charts went in back when they were stored
+/// as directions.
+const DIRECTION_NAMES: [&str; 4] = ["Up", "Down", "Left", "Right"];
+
+/// Reads an arrow's lane. Older charts stored directions instead, which still
+/// get read as their four lane equivalents.
+pub fn deserialize_lane<'de, D>(deserializer: D) -> Result<Lane, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum LaneToml {
+        Lane(Lane),
+        Direction(String),
+    }
+
+    match <LaneToml as serde::Deserialize>::deserialize(deserializer)? {
+        LaneToml::Lane(lane) => Ok(lane),
+        LaneToml::Direction(name) => DIRECTION_NAMES
+            .iter()
+            .position(|direction| *direction == name)
+            .ok_or_else(|| D::Error::custom(format!("unknown direction {}", name))),
+    }
+}
@@ -0,0 +1,312 @@
+use crate::consts::*;
+use bevy::prelude::*;
+
+/// Keeps track of the player's life during a game. Misses drain it, good hits
+/// fill it back up, and running out fails the song.
+pub struct LifePlugin;
+
+impl Plugin for LifePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<LifeGauge>()
+            .init_resource::<NoFail>()
+            .init_resource::<Life>()
+            .init_resource::<LifeBarMaterials>()
+            .on_state_enter(APP_STATE_STAGE, AppState::Game, reset_life.system())
+            .on_state_enter(APP_STATE_STAGE, AppState::Game, setup_life_bar.system())
+            .on_state_update(APP_STATE_STAGE, AppState::Game, update_life_bar.system())
+            .on_state_exit(APP_STATE_STAGE, AppState::Game, despawn_life_bar.system());
+    }
+}
+
+/// How life goes up and down over a song.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LifeCurve {
+    /// Life at the start of a song, from 0 to 1.
+    pub start: f32,
+    /// Life gained for a perfect hit. Worse hits get less.
+    pub hit: f32,
+    /// Life lost for each miss.
+    pub miss: f32,
+}
+
+/// Which life curve gets used, picked by the player.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum LifeGauge {
+    Easy,
+    #[default]
+    Normal,
+    /// Starts full, but barely refills and drains fast.
+    Hard,
+}
+
+impl LifeGauge {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Easy => "Easy",
+            Self::Normal => "Normal",
+            Self::Hard => "Hard",
+        }
+    }
+
+    /// The gauge after this one, going back to the first after the last.
+    pub fn next(&self) -> Self {
+        match self {
+            Self::Easy => Self::Normal,
+            Self::Normal => Self::Hard,
+            Self::Hard => Self::Easy,
+        }
+    }
+
+    pub fn curve(&self) -> LifeCurve {
+        match self {
+            Self::Easy => LifeCurve {
+                start: 0.5,
+                hit: 0.03,
+                miss: 0.04,
+            },
+            Self::Normal => LifeCurve {
+                start: 0.5,
+                hit: 0.02,
+                miss: 0.06,
+            },
+            Self::Hard => LifeCurve {
+                start: 1.0,
+                hit: 0.005,
+                miss: 0.1,
+            },
+        }
+    }
+}
+
+/// Keeps the song going when life runs out. Runs still remember that they
+/// would have failed.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct NoFail(pub bool);
+
+/// The player's life in the current game.
+#[derive(Debug, Clone, Copy)]
+pub struct Life {
+    value: f32,
+    curve: LifeCurve,
+    /// When life ran out, in seconds into the song.
+    failed_at: Option<f64>,
+}
+
+impl Default for Life {
+    fn default() -> Self {
+        Self::new(LifeGauge::default().curve())
+    }
+}
+
+impl Life {
+    pub fn new(curve: LifeCurve) -> Self {
+        Self {
+            value: curve.start,
+            curve,
+            failed_at: None,
+        }
+    }
+
+    /// How much life is left, from 0 to 1.
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    /// Adds life for hitting an arrow worth some points, out of 100.
+    pub fn hit(&mut self, points: usize) {
+        self.value = (self.value + self.curve.hit * points as f32 / 100.0).min(1.0);
+    }
+
+    /// Takes life away for a miss at some point in the song, in seconds. With
+    /// no fail on, life can fill back up after running out, but the run still
+    /// counts as failed.
+    pub fn miss(&mut self, secs: f64) {
+        self.value = (self.value - self.curve.miss).max(0.0);
+        if self.value <= 0.0 && self.failed_at.is_none() {
+            self.failed_at = Some(secs);
+        }
+    }
+
+    /// When life ran out, in seconds into the song, if it has.
+    pub fn failed_at(&self) -> Option<f64> {
+        self.failed_at
+    }
+
+    /// Returns true if the song should end because life ran out. That never
+    /// happens with no fail on.
+    pub fn has_failed(&self, no_fail: NoFail) -> bool {
+        self.failed_at.is_some() && !no_fail.0
+    }
+}
+
+/// Fills up the player's life at the start of a game.
+fn reset_life(mut life: ResMut<Life>, gauge: Res<LifeGauge>) {
+    *life = Life::new(gauge.curve());
+}
+
+/// Colors for the life bar.
+struct LifeBarMaterials {
+    background: Handle<ColorMaterial>,
+    fill: Handle<ColorMaterial>,
+    /// Used when life is running low.
+    low: Handle<ColorMaterial>,
+}
+
+impl FromResources for LifeBarMaterials {
+    fn from_resources(resources: &Resources) -> Self {
+        let mut materials = resources.get_mut::<Assets<ColorMaterial>>().unwrap();
+        LifeBarMaterials {
+            background: materials.add(Color::rgba(0.2, 0.2, 0.2, 0.8).into()),
+            fill: materials.add(Color::rgb(0.2, 0.8, 0.3).into()),
+            low: materials.add(Color::rgb(0.9, 0.2, 0.2).into()),
+        }
+    }
+}
+
+/// Life below this shows up in red.
+const LOW_LIFE: f32 = 0.25;
+
+/// The background of the life bar.
+struct LifeBar;
+
+/// The part of the life bar that shows how much life is left.
+struct LifeBarFill;
+
+fn setup_life_bar(commands: &mut Commands, materials: Res<LifeBarMaterials>) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    right: Val::Px(10.0),
+                    top: Val::Px(20.0),
+                    ..Default::default()
+                },
+                size: Size::new(Val::Px(200.0), Val::Px(20.0)),
+                ..Default::default()
+            },
+            material: materials.background.clone(),
+            ..Default::default()
+        })
+        .with(LifeBar)
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Percent(0.0), Val::Percent(100.0)),
+                        ..Default::default()
+                    },
+                    material: materials.fill.clone(),
+                    ..Default::default()
+                })
+                .with(LifeBarFill);
+        });
+}
+
+/// Sizes the life bar to match how much life is left.
+fn update_life_bar(
+    life: Res<Life>,
+    materials: Res<LifeBarMaterials>,
+    mut query: Query<(&mut Style, &mut Handle<ColorMaterial>), With<LifeBarFill>>,
+) {
+    for (mut style, mut material) in query.iter_mut() {
+        style.size.width = Val::Percent(life.value() * 100.0);
+        *material = if life.value() < LOW_LIFE {
+            materials.low.clone()
+        } else {
+            materials.fill.clone()
+        };
+    }
+}
+
+/// Removes the life bar when leaving a game.
+fn despawn_life_bar(commands: &mut Commands, query: Query<Entity, With<LifeBar>>) {
+    for entity in query.iter() {
+        commands.despawn_recursive(entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAUGES: [LifeGauge; 3] = [LifeGauge::Easy, LifeGauge::Normal, LifeGauge::Hard];
+
+    #[test]
+    fn hits_and_misses_follow_the_gauge() {
+        for gauge in GAUGES.iter() {
+            let curve = gauge.curve();
+            // Starting at half life leaves room to go both ways
+            let mut life = Life::new(LifeCurve {
+                start: 0.5,
+                ..curve
+            });
+
+            life.hit(100);
+            assert!((life.value() - (0.5 + curve.hit)).abs() < 1e-6);
+            life.hit(50);
+            assert!((life.value() - (0.5 + curve.hit * 1.5)).abs() < 1e-6);
+
+            // Mines that get hit drain life through a miss too
+            life.miss(1.0);
+            assert!((life.value() - (0.5 + curve.hit * 1.5 - curve.miss)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn harder_gauges_drain_faster() {
+        let misses = GAUGES
+            .iter()
+            .map(|gauge| gauge.curve().miss)
+            .collect::<Vec<_>>();
+        assert!(misses[0] < misses[1] && misses[1] < misses[2]);
+    }
+
+    #[test]
+    fn life_stays_between_0_and_1() {
+        for gauge in GAUGES.iter() {
+            let mut life = Life::new(gauge.curve());
+            for _ in 0..1000 {
+                life.hit(100);
+            }
+            assert_eq!(life.value(), 1.0);
+
+            for i in 0..100 {
+                life.miss(i as f64);
+            }
+            assert_eq!(life.value(), 0.0);
+        }
+    }
+
+    #[test]
+    fn fails_once_life_runs_out() {
+        let mut life = Life::new(LifeCurve {
+            start: 0.5,
+            hit: 0.25,
+            miss: 0.25,
+        });
+        life.miss(1.0);
+        assert_eq!(life.failed_at(), None);
+        assert!(!life.has_failed(NoFail(false)));
+
+        life.miss(2.0);
+        life.miss(3.0);
+        // Filling back up doesn't take it back
+        life.hit(100);
+        assert_eq!(life.failed_at(), Some(2.0));
+        assert!(life.has_failed(NoFail(false)));
+    }
+
+    #[test]
+    fn no_fail_never_fails() {
+        for gauge in GAUGES.iter() {
+            let mut life = Life::new(gauge.curve());
+            for i in 0..100 {
+                life.miss(i as f64);
+                assert!(!life.has_failed(NoFail(true)));
+            }
+            // The run still remembers it would have failed
+            assert!(life.failed_at().is_some());
+        }
+    }
+}
@@ -2,21 +2,45 @@ use bevy::{input::system::exit_on_esc_system, prelude::*, render::pass::ClearCol
 
 mod arrows;
 mod audio;
+mod chart;
 mod consts;
+mod editor;
+mod history;
+mod import;
+mod lanes;
+mod life;
 mod map_maker;
 mod menu;
+mod modifiers;
+mod playfield;
+mod playtest;
+mod popups;
+mod practice;
+mod results;
 mod score;
+mod session;
+mod settings;
 mod shaders;
 mod time;
+mod timing;
 mod types;
 mod ui;
 
 use arrows::ArrowsPlugin;
 use audio::AudioPlugin;
+use chart::ChartPlugin;
 use consts::*;
+use editor::EditorPlugin;
+use life::LifePlugin;
 use map_maker::MapMakerPlugin;
 use menu::MenuPlugin;
+use modifiers::ModifiersPlugin;
+use playtest::PlaytestPlugin;
+use popups::PopupsPlugin;
+use practice::PracticePlugin;
+use results::ResultsPlugin;
 use score::ScoreResource;
+use session::SessionPlugin;
 use shaders::ShadersPlugin;
 use time::TimePlugin;
 use ui::UIPlugin;
@@ -43,13 +67,22 @@ fn main() {
         .add_startup_system(setup.system())
         .add_plugins(DefaultPlugins)
         .add_system(exit_on_esc_system.system())
+        .add_plugin(ChartPlugin)
         .add_plugin(ArrowsPlugin)
+        .add_plugin(LifePlugin)
+        .add_plugin(ModifiersPlugin)
         .add_plugin(UIPlugin)
+        .add_plugin(PopupsPlugin)
         .add_plugin(AudioPlugin)
         .add_plugin(ShadersPlugin)
         .add_plugin(MenuPlugin)
         .add_plugin(TimePlugin)
         .add_plugin(MapMakerPlugin)
+        .add_plugin(EditorPlugin)
+        .add_plugin(PlaytestPlugin)
+        .add_plugin(PracticePlugin)
+        .add_plugin(ResultsPlugin)
+        .add_plugin(SessionPlugin)
         .run();
 }
 
@@ -1,11 +1,12 @@
 use crate::{
+    arrows::ArrowMaterialResource,
+    audio::{stop_song, SongLength, SongPlayer},
     consts::*,
-    time::ControlledTime,
-    types::{
-        ArrowTimeToml,
-        Directions::{self, *},
-        Speed,
-    },
+    history::{EditCommand, History},
+    lanes::{Lane, Lanes},
+    playtest::start_playtest,
+    time::{ControlledTime, PlaybackRate},
+    types::{ArrowTimeToml, DifficultyToml, SongConfig, SongConfigToml, Speed, DEFAULT_DIFFICULTY},
 };
 use bevy::{
     app::AppExit,
@@ -23,7 +24,6 @@ impl Plugin for MapMakerPlugin {
     fn build(&self, app: &mut AppBuilder) {
         app.init_resource::<Presses>()
             .init_resource::<MapMakerAudio>()
-            .on_state_enter(APP_STATE_STAGE, AppState::MakeMap, start_song.system())
             .on_state_enter(
                 APP_STATE_STAGE,
                 AppState::MakeMap,
@@ -34,15 +34,53 @@ impl Plugin for MapMakerPlugin {
                 AppState::MakeMap,
                 toggle_map_maker_arrows.system(),
             )
+            .on_state_update(
+                APP_STATE_STAGE,
+                AppState::MakeMap,
+                change_map_maker_lanes.system(),
+            )
             .on_state_update(
                 APP_STATE_STAGE,
                 AppState::MakeMap,
                 save_key_presses.system(),
             )
+            .on_state_update(
+                APP_STATE_STAGE,
+                AppState::MakeMap,
+                undo_key_presses.system(),
+            )
             .on_state_update(
                 APP_STATE_STAGE,
                 AppState::MakeMap,
                 save_to_file_on_exit.system(),
+            )
+            .on_state_update(APP_STATE_STAGE, AppState::MakeMap, start_song.system())
+            .on_state_update(
+                APP_STATE_STAGE,
+                AppState::MakeMap,
+                playtest_from_map_maker.system(),
+            )
+            .on_state_enter(
+                APP_STATE_STAGE,
+                AppState::MakeMap,
+                setup_map_maker_ui.system(),
+            )
+            .on_state_update(
+                APP_STATE_STAGE,
+                AppState::MakeMap,
+                control_playback.system(),
+            )
+            .on_state_update(APP_STATE_STAGE, AppState::MakeMap, scrub_song.system())
+            .on_state_update(
+                APP_STATE_STAGE,
+                AppState::MakeMap,
+                update_map_maker_ui.system(),
+            )
+            .on_state_exit(APP_STATE_STAGE, AppState::MakeMap, stop_song.system())
+            .on_state_exit(
+                APP_STATE_STAGE,
+                AppState::MakeMap,
+                despawn_map_maker_arrows.system(),
             );
     }
 }
@@ -50,27 +88,63 @@ impl Plugin for MapMakerPlugin {
 /// Keeps track of when keys are pressed.
 #[derive(Serialize, Debug, Default)]
 struct Presses {
+    /// How many lanes are being recorded.
+    lanes: Lanes,
     arrows: Vec<ArrowTimeToml>,
+    /// Lets stray key presses be undone.
+    #[serde(skip)]
+    history: History,
 }
 
-/// Saves key presses to [`Presses`].
+/// Key that gets held down to place mines instead of arrows.
+const MINE_KEY: KeyCode = KeyCode::M;
+
+/// Saves key presses to [`Presses`]. Presses while the mine key is held down
+/// turn into mines.
 fn save_key_presses(
     time: Res<ControlledTime>,
     keyboard_input: Res<Input<KeyCode>>,
     mut presses: ResMut<Presses>,
 ) {
-    const DIRECTIONS: [Directions; 4] = [Up, Down, Left, Right];
-    for direction in DIRECTIONS.iter() {
-        if direction.key_just_pressed(&keyboard_input) {
-            presses.arrows.push(ArrowTimeToml {
+    let presses = &mut *presses;
+
+    for lane in presses.lanes.iter() {
+        if presses.lanes.key_just_pressed(lane, &keyboard_input) {
+            let arrow = ArrowTimeToml {
                 click_time: time.seconds_since_startup(),
-                speed: Speed::Slow,
-                direction: *direction,
-            });
+                speed: Speed::SLOW,
+                lane,
+                hold: None,
+                mine: keyboard_input.pressed(MINE_KEY),
+            };
+
+            presses
+                .history
+                .apply(EditCommand::Add(vec![arrow]), &mut presses.arrows);
         }
     }
 }
 
+/// Undoes and redoes key presses with Ctrl+Z and Ctrl+Y.
+fn undo_key_presses(keyboard_input: Res<Input<KeyCode>>, mut presses: ResMut<Presses>) {
+    let presses = &mut *presses;
+
+    let ctrl =
+        keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl);
+    let shift = keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift);
+    if !ctrl {
+        return;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Z) && !shift {
+        presses.history.undo(&mut presses.arrows);
+    } else if keyboard_input.just_pressed(KeyCode::Y)
+        || (keyboard_input.just_pressed(KeyCode::Z) && shift)
+    {
+        presses.history.redo(&mut presses.arrows);
+    }
+}
+
 /// Saves our [`Presses`] to a file.
 fn save_to_file_on_exit(
     mut event_reader: Local<EventReader<AppExit>>,
@@ -87,58 +161,426 @@ fn save_to_file_on_exit(
 }
 
 /// An arrow that appears on the map maker screen when the user presses a key.
-struct MapMakerArrow(Directions);
+struct MapMakerArrow(Lane);
 
 /// Sets up each map maker arrow.
 fn setup_map_maker_arrows(
     commands: &mut Commands,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-    asset_server: ResMut<AssetServer>,
+    presses: Res<Presses>,
+    materials: Res<ArrowMaterialResource>,
 ) {
-    let border_handle = materials.add(asset_server.load("images/arrow_border.png").into());
-
-    const DIRECTIONS_AND_Y_VALS: [(Directions, f32); 4] =
-        [(Up, 150.0), (Down, 50.0), (Left, -50.0), (Right, -150.0)];
+    spawn_map_maker_arrows(commands, presses.lanes, &materials);
+}
 
-    for (direction, y) in DIRECTIONS_AND_Y_VALS.iter() {
-        let mut transform = Transform::from_translation(Vec3::new(0.0, *y, 1.0));
-        transform.rotate(Quat::from_rotation_z(direction.rotation()));
+/// Spawns a map maker arrow for each lane.
+fn spawn_map_maker_arrows(
+    commands: &mut Commands,
+    lanes: Lanes,
+    materials: &ArrowMaterialResource,
+) {
+    for lane in lanes.iter() {
+        let mut transform = Transform::from_translation(Vec3::new(0.0, lanes.y(lane), 1.0));
+        transform.rotate(Quat::from_rotation_z(lanes.rotation(lane)));
+        transform.scale = Vec3::splat(lanes.scale());
 
         commands
             .spawn(SpriteBundle {
-                material: border_handle.clone(),
+                material: materials.border_texture.clone(),
                 sprite: Sprite::new(Vec2::new(140.0, 140.0)),
                 transform,
                 ..Default::default()
             })
-            .with(MapMakerArrow(*direction));
+            .with(MapMakerArrow(lane));
+    }
+}
+
+/// Switches to the next lane count when F2 is pressed. Only works before
+/// anything has been recorded, since the recorded arrows wouldn't fit.
+fn change_map_maker_lanes(
+    commands: &mut Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut presses: ResMut<Presses>,
+    materials: Res<ArrowMaterialResource>,
+    query: Query<Entity, With<MapMakerArrow>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::F2) {
+        return;
+    }
+    if !presses.arrows.is_empty() {
+        info!("Can't change the lane count after recording arrows");
+        return;
+    }
+
+    presses.lanes = presses.lanes.next();
+    // Undone presses might not fit anymore.
+    presses.history = History::default();
+
+    for entity in query.iter() {
+        commands.despawn(entity);
+    }
+    spawn_map_maker_arrows(commands, presses.lanes, &materials);
+}
+
+/// Removes the map maker arrows and UI when leaving the map maker.
+fn despawn_map_maker_arrows(
+    commands: &mut Commands,
+    arrows: Query<Entity, With<MapMakerArrow>>,
+    ui: Query<Entity, With<MapMakerUI>>,
+) {
+    for entity in arrows.iter() {
+        commands.despawn(entity);
+    }
+    for entity in ui.iter() {
+        commands.despawn_recursive(entity);
     }
 }
 
 /// Toggles map maker arrow visibility according to if corresponding key is
-/// being pressed.
+/// being pressed. They show up as mines while the mine key is held down.
 fn toggle_map_maker_arrows(
-    mut query: Query<(&mut Visible, &MapMakerArrow)>,
+    mut query: Query<(&mut Visible, &mut Handle<ColorMaterial>, &MapMakerArrow)>,
     keyboard_input: Res<Input<KeyCode>>,
+    presses: Res<Presses>,
+    materials: Res<ArrowMaterialResource>,
 ) {
-    for (mut visible, arrow) in query.iter_mut() {
-        visible.is_visible = arrow.0.key_pressed(&keyboard_input);
+    for (mut visible, mut material, arrow) in query.iter_mut() {
+        visible.is_visible = presses.lanes.key_pressed(arrow.0, &keyboard_input);
+        *material = if keyboard_input.pressed(MINE_KEY) {
+            materials.mine_texture.clone()
+        } else {
+            materials.border_texture.clone()
+        };
     }
 }
 
 /// Just plays a single song by default. Hardcoded. The song select screen will
 /// probably be built later.
-struct MapMakerAudio(Handle<AudioSource>);
+struct MapMakerAudio {
+    handle: Handle<AudioSource>,
+    length: SongLength,
+}
 
 impl FromResources for MapMakerAudio {
     fn from_resources(resources: &Resources) -> Self {
         let asset_server = resources.get::<AssetServer>().unwrap();
-        let audio = asset_server.load("Electronic Fantasy.mp3");
-        Self(audio)
+        let handle = asset_server.load("songs/Electronic Fantasy.mp3");
+        Self {
+            handle,
+            length: SongLength::default(),
+        }
     }
 }
 
-/// Start playing some music!
-fn start_song(audio: Res<Audio>, map_maker_audio: Res<MapMakerAudio>) {
-    audio.play(map_maker_audio.0.clone());
+/// Start playing some music as soon as it's loaded! Picks up from wherever the
+/// map maker's time is at.
+fn start_song(
+    mut player: ResMut<SongPlayer>,
+    time: Res<ControlledTime>,
+    mut map_maker_audio: ResMut<MapMakerAudio>,
+    audio_sources: Res<Assets<AudioSource>>,
+    rate: Res<PlaybackRate>,
+) {
+    if player.is_playing() {
+        return;
+    }
+
+    if let Some(source) = audio_sources.get(&map_maker_audio.handle) {
+        map_maker_audio.length.measure(source);
+        player.set_rate(rate.0);
+        player.play(source, time.seconds_since_startup());
+    }
+}
+
+/// Seconds to jump by when seeking with the keyboard.
+const SEEK_STEP: f64 = 5.0;
+
+/// Jumps to some point in the song, keeping the map maker's time and the
+/// song's audio lined up.
+fn seek(
+    time: &mut ControlledTime,
+    player: &mut SongPlayer,
+    map_maker_audio: &MapMakerAudio,
+    source: &AudioSource,
+    position: f64,
+) {
+    let position = match map_maker_audio.length.get() {
+        Some(length) => position.min(length),
+        None => position,
+    }
+    .max(0.0);
+
+    time.set_seconds_since_startup(position);
+    player.seek(source, position);
+}
+
+/// Plays, pauses and seeks through the song with the keyboard.
+fn control_playback(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut time: ResMut<ControlledTime>,
+    mut player: ResMut<SongPlayer>,
+    map_maker_audio: Res<MapMakerAudio>,
+    audio_sources: Res<Assets<AudioSource>>,
+    mut rate: ResMut<PlaybackRate>,
+) {
+    let source = match audio_sources.get(&map_maker_audio.handle) {
+        Some(source) => source,
+        None => return,
+    };
+
+    // Changing the playback rate restarts the song where it's at.
+    let old_rate = rate.0;
+    if keyboard_input.just_pressed(KeyCode::Minus) {
+        rate.slower();
+    }
+    if keyboard_input.just_pressed(KeyCode::Equals) {
+        rate.faster();
+    }
+    if rate.0 != old_rate {
+        time.set_rate(rate.0);
+        player.set_rate(rate.0);
+
+        let position = time.seconds_since_startup();
+        seek(&mut time, &mut player, &map_maker_audio, source, position);
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Space) {
+        if time.is_paused() {
+            time.resume();
+            player.resume();
+        } else {
+            time.pause();
+            player.pause();
+        }
+    }
+
+    let now = time.seconds_since_startup();
+    let position = if keyboard_input.just_pressed(KeyCode::LBracket) {
+        Some(now - SEEK_STEP)
+    } else if keyboard_input.just_pressed(KeyCode::RBracket) {
+        Some(now + SEEK_STEP)
+    } else if keyboard_input.just_pressed(KeyCode::Home) {
+        Some(0.0)
+    } else {
+        None
+    };
+
+    if let Some(position) = position {
+        seek(&mut time, &mut player, &map_maker_audio, source, position);
+    }
+}
+
+/// A marker for all of the map maker's UI.
+struct MapMakerUI;
+
+/// Shows the current time in the map maker.
+struct MapMakerText;
+
+/// The bar that can be clicked or dragged on to jump around the song.
+struct ScrubBar;
+
+/// The part of the scrub bar that fills up as the song plays.
+struct ScrubBarFill;
+
+/// Sets up the map maker's text and scrub bar.
+fn setup_map_maker_ui(
+    commands: &mut Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+) {
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+
+    commands
+        // Time text node
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.0),
+                    top: Val::Px(10.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            material: materials.add(Color::NONE.into()),
+            ..Default::default()
+        })
+        .with(MapMakerUI)
+        .with_children(|parent| {
+            parent
+                .spawn(TextBundle {
+                    text: Text {
+                        value: String::new(),
+                        font,
+                        style: TextStyle {
+                            font_size: 20.0,
+                            color: Color::rgb(0.9, 0.9, 0.9),
+                            ..Default::default()
+                        },
+                    },
+                    ..Default::default()
+                })
+                .with(MapMakerText);
+        })
+        // Scrub bar along the bottom of the screen
+        .spawn(ButtonBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(0.0),
+                    bottom: Val::Px(0.0),
+                    ..Default::default()
+                },
+                size: Size::new(Val::Percent(100.0), Val::Px(20.0)),
+                ..Default::default()
+            },
+            material: materials.add(Color::rgb(0.15, 0.15, 0.15).into()),
+            ..Default::default()
+        })
+        .with(ScrubBar)
+        .with(MapMakerUI)
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Percent(0.0), Val::Percent(100.0)),
+                        ..Default::default()
+                    },
+                    material: materials.add(Color::rgb(0.35, 0.75, 0.35).into()),
+                    ..Default::default()
+                })
+                .with(ScrubBarFill);
+        });
+}
+
+/// Whether the scrub bar is being dragged, and whether the song was paused
+/// before it was.
+#[derive(Default)]
+struct Scrubbing {
+    active: bool,
+    was_paused: bool,
+}
+
+/// Jumps around the song while the scrub bar is being clicked or dragged.
+/// Time and audio stay paused while dragging, and pick back up when the mouse
+/// is released if the song was playing before.
+fn scrub_song(
+    mut scrubbing: Local<Scrubbing>,
+    windows: Res<Windows>,
+    query: Query<&Interaction, With<ScrubBar>>,
+    mut time: ResMut<ControlledTime>,
+    mut player: ResMut<SongPlayer>,
+    map_maker_audio: Res<MapMakerAudio>,
+    audio_sources: Res<Assets<AudioSource>>,
+) {
+    let (source, length) = match (
+        audio_sources.get(&map_maker_audio.handle),
+        map_maker_audio.length.get(),
+    ) {
+        (Some(source), Some(length)) => (source, length),
+        _ => return,
+    };
+
+    let clicked = query
+        .iter()
+        .any(|interaction| *interaction == Interaction::Clicked);
+
+    if clicked {
+        let fraction = windows.get_primary().and_then(|window| {
+            window
+                .cursor_position()
+                .map(|pos| (pos.x / window.width()).clamp(0.0, 1.0) as f64)
+        });
+
+        if let Some(fraction) = fraction {
+            if !scrubbing.active {
+                *scrubbing = Scrubbing {
+                    active: true,
+                    was_paused: time.is_paused(),
+                };
+                time.pause();
+                player.pause();
+            }
+            time.set_seconds_since_startup(fraction * length);
+        }
+    } else if scrubbing.active {
+        scrubbing.active = false;
+        let position = time.seconds_since_startup();
+        seek(&mut time, &mut player, &map_maker_audio, source, position);
+        if !scrubbing.was_paused {
+            time.resume();
+            player.resume();
+        }
+    }
+}
+
+/// Keeps the map maker's text and scrub bar up to date.
+fn update_map_maker_ui(
+    time: Res<ControlledTime>,
+    rate: Res<PlaybackRate>,
+    map_maker_audio: Res<MapMakerAudio>,
+    presses: Res<Presses>,
+    mut text_query: Query<&mut Text, With<MapMakerText>>,
+    mut fill_query: Query<&mut Style, With<ScrubBarFill>>,
+) {
+    let secs = time.seconds_since_startup();
+    let length = map_maker_audio.length.get();
+
+    for mut text in text_query.iter_mut() {
+        text.value = format!(
+            "Time: {:.2} / {}  |  Rate: {}x{}  |  Lanes: {}\n\
+             Space: play/pause  [ ]: seek {}s  Home: start  Scrub bar: jump  -/=: rate\n\
+             Hold M: place mines  Ctrl+Z/Y: undo/redo  F2: lanes  F5: playtest from here (Tab to return)",
+            secs,
+            length.map_or("?".to_string(), |length| format!("{:.2}", length)),
+            rate.0,
+            if time.is_paused() { "  (paused)" } else { "" },
+            presses.lanes.label(),
+            SEEK_STEP,
+        );
+    }
+
+    if let Some(length) = length {
+        for mut style in fill_query.iter_mut() {
+            style.size.width = Val::Percent((secs / length * 100.0).min(100.0) as f32);
+        }
+    }
+}
+
+/// Plays what's been recorded so far from the current point in the song when
+/// F5 is pressed.
+fn playtest_from_map_maker(
+    commands: &mut Commands,
+    time: Res<ControlledTime>,
+    presses: Res<Presses>,
+    keyboard_input: Res<Input<KeyCode>>,
+    asset_server: Res<AssetServer>,
+    mut state: ResMut<State<AppState>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::F5) {
+        return;
+    }
+
+    let chart = SongConfigToml {
+        name: "Map maker".to_string(),
+        filename: "Electronic Fantasy.mp3".to_string(),
+        difficulties: vec![DifficultyToml {
+            name: DEFAULT_DIFFICULTY.to_string(),
+            level: 0,
+            lanes: presses.lanes,
+            arrows: presses.arrows.clone(),
+            timing: None,
+        }],
+        ..Default::default()
+    };
+
+    // The song is straight in `assets/songs`, so the recording is treated as
+    // if it were saved there too
+    let song_config = SongConfig::new(&chart, &chart.difficulties[0], "map.toml", &asset_server);
+    start_playtest(
+        commands,
+        song_config,
+        time.seconds_since_startup(),
+        AppState::MakeMap,
+        &mut state,
+    );
 }
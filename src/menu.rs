@@ -1,6 +1,41 @@
-use crate::{consts::*, types::load_config};
-use bevy::prelude::*;
-use std::{fs::read_dir, iter};
+use crate::{
+    audio::{stop_song, SongPlayer},
+    chart::ChartHandle,
+    consts::*,
+    editor::EditorState,
+    import::{is_chart_file, load_chart},
+    lanes::Lanes,
+    life::LifeGauge,
+    modifiers::{LaneModifier, VisibilityModifier},
+    playfield::{Orientation, ScrollSpeed},
+    playtest::Playtest,
+    popups::TimingDisplay,
+    score::{HighScore, HighScores},
+    session::SessionExport,
+    settings::{Settings, SettingsMut},
+    time::{PlaybackRate, SongStart},
+    types::{SongConfig, SongConfigToml},
+};
+use bevy::{
+    asset::LoadState, ecs::SystemParam, input::mouse::MouseWheel, prelude::*,
+    window::ReceivedCharacter,
+};
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fs::read_dir,
+    path::{Path, PathBuf},
+};
+
+/// How many songs fit in the song list at once.
+const VISIBLE_SONGS: usize = 7;
+
+/// How long song previews last if their chart doesn't say, in seconds.
+const PREVIEW_LENGTH: f64 = 15.0;
+
+/// Where song previews start if their chart doesn't say, as a fraction of the
+/// song's length.
+const PREVIEW_START: f64 = 0.4;
 
 /// A plugin for handling all the game's menus.
 pub struct MenuPlugin;
@@ -8,6 +43,9 @@ pub struct MenuPlugin;
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut AppBuilder) {
         app.init_resource::<ButtonMaterials>()
+            .init_resource::<PendingSong>()
+            .init_resource::<SongSelect>()
+            .init_resource::<Preview>()
             .on_state_enter(APP_STATE_STAGE, AppState::Menu, setup_menu.system())
             .on_state_update(
                 APP_STATE_STAGE,
@@ -19,7 +57,18 @@ impl Plugin for MenuPlugin {
                 AppState::Menu,
                 button_press_system.system(),
             )
-            .on_state_exit(APP_STATE_STAGE, AppState::Menu, despawn_menu.system());
+            .on_state_update(
+                APP_STATE_STAGE,
+                AppState::Menu,
+                settings_button_system.system(),
+            )
+            .on_state_update(APP_STATE_STAGE, AppState::Menu, song_select_keys.system())
+            .on_state_update(APP_STATE_STAGE, AppState::Menu, update_song_list.system())
+            .on_state_update(APP_STATE_STAGE, AppState::Menu, update_song_info.system())
+            .on_state_update(APP_STATE_STAGE, AppState::Menu, play_preview.system())
+            .on_state_update(APP_STATE_STAGE, AppState::Menu, start_pending_song.system())
+            .on_state_exit(APP_STATE_STAGE, AppState::Menu, despawn_menu.system())
+            .on_state_exit(APP_STATE_STAGE, AppState::Menu, stop_song.system());
     }
 }
 
@@ -29,6 +78,8 @@ struct ButtonMaterials {
     normal: Handle<ColorMaterial>,
     hovered: Handle<ColorMaterial>,
     pressed: Handle<ColorMaterial>,
+    /// For the highlighted song in the song list.
+    highlighted: Handle<ColorMaterial>,
     font: Handle<Font>,
 }
 
@@ -42,46 +93,518 @@ impl FromResources for ButtonMaterials {
             normal: materials.add(Color::rgb(0.15, 0.15, 0.15).into()),
             hovered: materials.add(Color::rgb(0.25, 0.25, 0.25).into()),
             pressed: materials.add(Color::rgb(0.35, 0.75, 0.35).into()),
+            highlighted: materials.add(Color::rgb(0.2, 0.3, 0.55).into()),
             font: asset_server.load("fonts/FiraSans-Bold.ttf"),
         }
     }
 }
 
+/// A song that's been picked, and is waiting on its chart to load.
+#[derive(Default)]
+struct PendingSong(Option<(String, Handle<SongConfig>)>);
+
+/// Ways the song list can be sorted.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum SortOrder {
+    #[default]
+    Title,
+    Artist,
+    Bpm,
+    /// By the level of each song's picked difficulty.
+    Difficulty,
+    /// Best scores first.
+    BestScore,
+}
+
+impl SortOrder {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Title => "Title",
+            Self::Artist => "Artist",
+            Self::Bpm => "BPM",
+            Self::Difficulty => "Difficulty",
+            Self::BestScore => "Best score",
+        }
+    }
+
+    /// The sort order after this one, going back around to the first.
+    fn next(&self) -> Self {
+        match self {
+            Self::Title => Self::Artist,
+            Self::Artist => Self::Bpm,
+            Self::Bpm => Self::Difficulty,
+            Self::Difficulty => Self::BestScore,
+            Self::BestScore => Self::Title,
+        }
+    }
+}
+
+/// A song in song select.
+struct SongEntry {
+    /// Path to the chart, relative to `assets/songs`.
+    file: String,
+    /// The pack the song's in, if it's in one.
+    pack: Option<String>,
+    chart: SongConfigToml,
+    /// Fastest tempo in the song, for sorting.
+    bpm: f64,
+    details: String,
+}
+
+impl SongEntry {
+    fn new(file: String, chart: SongConfigToml) -> Self {
+        let bpm = chart.timing.as_ref().map_or(0.0, |timing| {
+            timing
+                .bpms
+                .iter()
+                .map(|change| change.bpm)
+                .fold(0.0, f64::max)
+        });
+        let pack = song_pack(&file);
+        let details = match &pack {
+            Some(pack) => format!("[{}] {}", pack, song_details(&chart)),
+            None => song_details(&chart),
+        };
+
+        Self {
+            file,
+            pack,
+            chart,
+            bpm,
+            details,
+        }
+    }
+
+    /// The song's name, or its file name if the chart doesn't have one.
+    fn title(&self) -> String {
+        if self.chart.name.is_empty() {
+            song_label(&self.file)
+        } else {
+            self.chart.name.clone()
+        }
+    }
+
+    /// Where a file that sits next to the chart is, relative to `assets`.
+    fn asset_path(&self, file: &str) -> PathBuf {
+        Path::new("songs")
+            .join(&self.file)
+            .parent()
+            .map(|dir| dir.join(file))
+            .unwrap_or_default()
+    }
+
+    /// The image to show next to the song. Falls back to the background if
+    /// there's no jacket.
+    fn jacket(&self) -> Option<PathBuf> {
+        self.chart
+            .jacket
+            .as_ref()
+            .or(self.chart.background_image.as_ref())
+            .map(|image| self.asset_path(image))
+    }
+
+    /// Where the song's preview starts, and how long it lasts, in seconds.
+    fn preview(&self) -> (f64, f64) {
+        (
+            self.chart
+                .preview_start
+                .unwrap_or_else(|| self.chart.length() * PREVIEW_START),
+            self.chart.preview_length.unwrap_or(PREVIEW_LENGTH),
+        )
+    }
+
+    /// Returns true if any of the song's text has some lowercase search text
+    /// in it.
+    fn matches(&self, search: &str) -> bool {
+        let chart = &self.chart;
+
+        [
+            Some(&self.file),
+            self.pack.as_ref(),
+            Some(&chart.name),
+            chart.artist.as_ref(),
+            chart.charter.as_ref(),
+            chart.genre.as_ref(),
+        ]
+        .iter()
+        .flatten()
+        .any(|text| text.to_lowercase().contains(search))
+    }
+}
+
+/// Everything about song select. Sticks around between visits to the menu, so
+/// the same song stays highlighted.
+#[derive(Default)]
+struct SongSelect {
+    songs: Vec<SongEntry>,
+    /// Every pack that songs are in, sorted.
+    packs: Vec<String>,
+    /// Only songs in this pack get shown, if one's been picked.
+    pack: Option<String>,
+    /// Which difficulty has been picked for each song, by file name.
+    difficulties: HashMap<String, usize>,
+    sort: SortOrder,
+    search: String,
+    /// Indices into `songs` of the ones that match the search, in order.
+    shown: Vec<usize>,
+    /// Which of the shown songs is highlighted.
+    highlighted: usize,
+    /// How many shown songs are scrolled past the top of the list.
+    scroll: usize,
+    /// Set when the menu's just been entered, so that the Enter press that
+    /// left the results screen doesn't start a song straight away.
+    entering: bool,
+}
+
+impl SongSelect {
+    /// Reads every song in `assets/songs` again. Any old high scores for them
+    /// get moved over to their charts.
+    fn refresh(&mut self, high_scores: &mut HighScores) {
+        let highlighted = self.highlighted().map(|song| song.file.clone());
+
+        self.songs = get_songs()
+            .into_iter()
+            .filter_map(|file| match load_chart(&file) {
+                Ok(chart) => Some(SongEntry::new(file, chart)),
+                Err(err) => {
+                    warn!("Couldn't read {}: {}", file, err);
+                    None
+                }
+            })
+            .collect();
+
+        let mut migrated = false;
+        for song in &self.songs {
+            migrated |= high_scores.migrate(&song.file, &song.chart.name);
+        }
+        if migrated {
+            high_scores.save();
+        }
+
+        self.packs = self
+            .songs
+            .iter()
+            .filter_map(|song| song.pack.clone())
+            .collect();
+        self.packs.sort();
+        self.packs.dedup();
+        if !self
+            .packs
+            .iter()
+            .any(|pack| Some(pack) == self.pack.as_ref())
+        {
+            self.pack = None;
+        }
+
+        self.show(highlighted, high_scores);
+    }
+
+    /// Switches to only showing the next pack's songs, and then back to
+    /// showing every song after the last pack.
+    fn next_pack(&mut self, high_scores: &HighScores) {
+        let next = match &self.pack {
+            Some(pack) => self
+                .packs
+                .iter()
+                .position(|other| other == pack)
+                .map(|i| i + 1),
+            None => Some(0),
+        };
+        self.pack = next.and_then(|i| self.packs.get(i)).cloned();
+
+        self.update_shown(high_scores);
+    }
+
+    /// Searches and sorts the songs again, keeping the same one highlighted if
+    /// it's still there.
+    fn update_shown(&mut self, high_scores: &HighScores) {
+        let highlighted = self.highlighted().map(|song| song.file.clone());
+        self.show(highlighted, high_scores);
+    }
+
+    fn show(&mut self, highlighted: Option<String>, high_scores: &HighScores) {
+        let search = self.search.to_lowercase();
+
+        let mut shown = (0..self.songs.len())
+            .filter(|&i| self.pack.is_none() || self.songs[i].pack == self.pack)
+            .filter(|&i| self.songs[i].matches(&search))
+            .collect::<Vec<_>>();
+        shown.sort_by(|&a, &b| self.compare(&self.songs[a], &self.songs[b], high_scores));
+
+        self.shown = shown;
+        self.highlighted = highlighted
+            .and_then(|file| self.shown.iter().position(|&i| self.songs[i].file == file))
+            .unwrap_or(0);
+        self.move_highlight(0);
+    }
+
+    /// Which way round two songs go in the current sort order. Songs are kept
+    /// together with the rest of their pack, and ties are sorted by title.
+    fn compare(&self, a: &SongEntry, b: &SongEntry, high_scores: &HighScores) -> Ordering {
+        let pack = a.pack.cmp(&b.pack);
+        let order = match self.sort {
+            SortOrder::Title => Ordering::Equal,
+            // Songs without an artist go last.
+            SortOrder::Artist => {
+                let artist = |song: &SongEntry| {
+                    let artist = song
+                        .chart
+                        .artist
+                        .as_ref()
+                        .map(|artist| artist.to_lowercase());
+                    (artist.is_none(), artist)
+                };
+                artist(a).cmp(&artist(b))
+            }
+            SortOrder::Bpm => a.bpm.partial_cmp(&b.bpm).unwrap_or(Ordering::Equal),
+            SortOrder::Difficulty => self.level(a).cmp(&self.level(b)),
+            SortOrder::BestScore => {
+                let score = |song: &SongEntry| self.best(song, high_scores).map(|best| best.score);
+                score(b).cmp(&score(a))
+            }
+        };
+
+        pack.then(order)
+            .then_with(|| a.title().to_lowercase().cmp(&b.title().to_lowercase()))
+    }
+
+    /// The highlighted song, if any songs are shown.
+    fn highlighted(&self) -> Option<&SongEntry> {
+        self.shown.get(self.highlighted).map(|&i| &self.songs[i])
+    }
+
+    /// The song shown in one of the song list's slots.
+    fn in_slot(&self, slot: usize) -> Option<&SongEntry> {
+        self.shown.get(self.scroll + slot).map(|&i| &self.songs[i])
+    }
+
+    /// Index of the difficulty that's been picked for a song.
+    fn difficulty(&self, song: &SongEntry) -> usize {
+        self.difficulties
+            .get(&song.file)
+            .copied()
+            .filter(|&difficulty| difficulty < song.chart.difficulties.len())
+            .unwrap_or(0)
+    }
+
+    /// Level of the difficulty that's been picked for a song.
+    fn level(&self, song: &SongEntry) -> u32 {
+        song.chart.difficulties[self.difficulty(song)].level
+    }
+
+    /// The best run on the difficulty that's been picked for a song.
+    fn best<'a>(&self, song: &SongEntry, high_scores: &'a HighScores) -> Option<&'a HighScore> {
+        let difficulty = &song.chart.difficulties[self.difficulty(song)];
+        high_scores.get(&song.file, &difficulty.name)
+    }
+
+    /// Moves the highlight up or down the list, scrolling to keep it in view.
+    fn move_highlight(&mut self, by: isize) {
+        let last = self.shown.len().saturating_sub(1);
+        self.highlighted = (self.highlighted as isize + by).max(0).min(last as isize) as usize;
+
+        if self.highlighted < self.scroll {
+            self.scroll = self.highlighted;
+        } else if self.highlighted >= self.scroll + VISIBLE_SONGS {
+            self.scroll = self.highlighted + 1 - VISIBLE_SONGS;
+        }
+        self.scroll = self
+            .scroll
+            .min(self.shown.len().saturating_sub(VISIBLE_SONGS));
+    }
+
+    /// Picks the next or previous difficulty of the highlighted song.
+    fn change_difficulty(&mut self, by: isize, high_scores: &HighScores) {
+        let (file, count, difficulty) = match self.highlighted() {
+            Some(song) => (
+                song.file.clone(),
+                song.chart.difficulties.len() as isize,
+                self.difficulty(song) as isize,
+            ),
+            None => return,
+        };
+
+        let difficulty = (difficulty + by).rem_euclid(count) as usize;
+        self.difficulties.insert(file, difficulty);
+
+        // Sorting by difficulty or score depends on which one's picked.
+        self.update_shown(high_scores);
+    }
+
+    /// Starts loading the picked difficulty of the highlighted song.
+    /// `start_pending_song` takes over from there.
+    fn play(&self, asset_server: &AssetServer, pending: &mut PendingSong) {
+        if let Some(song) = self.highlighted() {
+            let handle =
+                asset_server.load(&*format!("songs/{}#{}", song.file, self.difficulty(song)));
+            pending.0 = Some((song.file.clone(), handle));
+        }
+    }
+}
+
+/// The song being previewed in song select.
+#[derive(Default)]
+struct Preview {
+    /// Which song is being previewed, and its audio.
+    song: Option<(String, Handle<AudioSource>)>,
+    /// When the preview last started playing, in seconds since startup.
+    started: Option<f64>,
+}
+
 /// A marker struct for the Menu's UI.
 struct MenuUI;
 
+/// Text that shows what's being searched for, or about the highlighted song.
+enum InfoText {
+    Search,
+    Song,
+}
+
+/// The highlighted song's jacket, and the image it's showing.
+struct Jacket(Option<PathBuf>);
+
 /// All the menu buttons
 enum MenuButton {
     MakeMap,
-    PlaySong(String),
+    /// One of the rows of the song list. Clicking a song highlights it, and
+    /// clicking it again plays it.
+    Song(usize),
+    /// Picks which difficulty of the highlighted song gets played.
+    Difficulty,
+    Sort,
+    /// Picks which pack's songs get shown.
+    Pack,
+    /// Opens the highlighted song in the editor.
+    EditChart,
+    PlaybackRate(f64),
+    /// Picks which way arrows travel.
+    Orientation(Orientation),
+    ScrollSpeed(ScrollSpeed),
+    LifeGauge(LifeGauge),
+    NoFail(bool),
+    LaneModifier(LaneModifier),
+    VisibilityModifier(VisibilityModifier),
+    /// How far from their targets arrows fade, for hidden and sudden.
+    FadeDistance(f32),
+    Practice(bool),
+    /// Which files finished runs get written to.
+    SessionExport(SessionExport),
+    /// What gets shown about how early or late hits were.
+    TimingDisplay(TimingDisplay),
 }
 
 impl MenuButton {
-    fn name(&self) -> String {
+    fn name(&self, select: &SongSelect) -> String {
         match self {
             Self::MakeMap => "Make Map".to_string(),
-            Self::PlaySong(song) => format!("Play song: {}", song),
+            Self::Song(slot) => select
+                .in_slot(*slot)
+                .map_or_else(String::new, |song| song.title()),
+            Self::Difficulty => match select.highlighted() {
+                Some(song) => {
+                    let difficulty = &song.chart.difficulties[select.difficulty(song)];
+                    if difficulty.lanes == Lanes::default() {
+                        format!("{} ({})", difficulty.name, difficulty.level)
+                    } else {
+                        format!(
+                            "{} ({}, {})",
+                            difficulty.name,
+                            difficulty.level,
+                            difficulty.lanes.label()
+                        )
+                    }
+                }
+                None => "-".to_string(),
+            },
+            Self::Sort => format!("Sort: {}", select.sort.name()),
+            Self::Pack => format!("Pack: {}", select.pack.as_deref().unwrap_or("All")),
+            Self::EditChart => "Edit chart".to_string(),
+            Self::PlaybackRate(rate) => format!("Playback rate: {}x", rate),
+            Self::Orientation(orientation) => format!("Scroll: {}", orientation.name()),
+            Self::ScrollSpeed(scroll) => format!("Scroll speed: {}", scroll.name()),
+            Self::LifeGauge(gauge) => format!("Life: {}", gauge.name()),
+            Self::NoFail(no_fail) => format!("No fail: {}", if *no_fail { "on" } else { "off" }),
+            Self::LaneModifier(modifier) => format!("Lanes: {}", modifier.name()),
+            Self::VisibilityModifier(modifier) => format!("Visibility: {}", modifier.name()),
+            Self::FadeDistance(distance) => format!("Fade at: {}px", distance),
+            Self::Practice(practice) => {
+                format!("Practice: {}", if *practice { "on" } else { "off" })
+            }
+            Self::SessionExport(export) => format!("Save runs: {}", export.name()),
+            Self::TimingDisplay(display) => format!("Early/late: {}", display.name()),
+        }
+    }
+
+    /// Settings buttons get a column of their own, and are a bit smaller.
+    fn is_setting(&self) -> bool {
+        matches!(
+            self,
+            Self::PlaybackRate(_)
+                | Self::Orientation(_)
+                | Self::ScrollSpeed(_)
+                | Self::LifeGauge(_)
+                | Self::NoFail(_)
+                | Self::LaneModifier(_)
+                | Self::VisibilityModifier(_)
+                | Self::FadeDistance(_)
+                | Self::Practice(_)
+                | Self::SessionExport(_)
+                | Self::TimingDisplay(_)
+        )
+    }
+
+    fn size(&self) -> Size<Val> {
+        match self {
+            Self::Song(_) => Size::new(Val::Px(320.0), Val::Px(60.0)),
+            _ if self.is_setting() => Size::new(Val::Px(190.0), Val::Px(30.0)),
+            _ => Size::new(Val::Px(200.0), Val::Px(36.0)),
+        }
+    }
+
+    fn font_size(&self) -> f32 {
+        if self.is_setting() {
+            16.0
+        } else {
+            20.0
         }
     }
 }
 
-/// Create the menu UI.
-fn setup_menu(commands: &mut Commands, button_materials: Res<ButtonMaterials>) {
-    // A list of menu buttons:
-    let buttons = get_songs()
-        .into_iter()
-        .map(|name| MenuButton::PlaySong(name))
-        .chain(iter::once(MenuButton::MakeMap))
-        .collect::<Vec<_>>();
+/// Create the menu UI: the song list on the left, and everything else on the
+/// right.
+fn setup_menu(
+    commands: &mut Commands,
+    button_materials: Res<ButtonMaterials>,
+    settings: Settings,
+    mut high_scores: ResMut<HighScores>,
+    mut select: ResMut<SongSelect>,
+    mut preview: ResMut<Preview>,
+) {
+    select.refresh(&mut high_scores);
+    select.entering = true;
+    // The song player gets stopped when leaving the menu.
+    *preview = Preview::default();
+
+    let column = |width| NodeBundle {
+        style: Style {
+            size: Size::new(Val::Px(width), Val::Auto),
+            // Column children get laid out from the bottom up
+            flex_direction: FlexDirection::ColumnReverse,
+            align_items: AlignItems::Center,
+            ..Default::default()
+        },
+        material: button_materials.none.clone(),
+        ..Default::default()
+    };
 
     commands
         .spawn(NodeBundle {
             style: Style {
                 size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
                 display: Display::Flex,
-                flex_direction: FlexDirection::Column,
-                align_items: AlignItems::FlexStart,
-                justify_content: JustifyContent::FlexStart,
+                flex_direction: FlexDirection::Row,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::SpaceEvenly,
                 ..Default::default()
             },
             material: button_materials.none.clone(),
@@ -89,40 +612,123 @@ fn setup_menu(commands: &mut Commands, button_materials: Res<ButtonMaterials>) {
         })
         .with(MenuUI)
         .with_children(|parent| {
-            // Add all of the buttons as children.
-            for button in buttons {
-                // Spawn a new button:
+            parent.spawn(column(340.0)).with_children(|parent| {
+                spawn_text(parent, InfoText::Search, &button_materials);
+                for slot in 0..VISIBLE_SONGS {
+                    spawn_button(parent, MenuButton::Song(slot), &select, &button_materials);
+                }
+            });
+
+            parent.spawn(column(220.0)).with_children(|parent| {
                 parent
-                    .spawn(ButtonBundle {
+                    .spawn(ImageBundle {
                         style: Style {
-                            size: Size::new(Val::Px(350.0), Val::Px(65.0)),
-                            margin: Rect::all(Val::Auto),
-                            justify_content: JustifyContent::Center,
-                            align_items: AlignItems::Center,
+                            size: Size::new(Val::Px(160.0), Val::Px(160.0)),
+                            margin: Rect::all(Val::Px(4.0)),
                             ..Default::default()
                         },
-                        material: button_materials.normal.clone(),
+                        material: button_materials.none.clone(),
                         ..Default::default()
                     })
-                    .with_children(|parent| {
-                        parent.spawn(TextBundle {
-                            text: Text {
-                                value: button.name(),
-                                font: button_materials.font.clone(),
-                                style: TextStyle {
-                                    font_size: 20.0,
-                                    color: Color::rgb(0.9, 0.9, 0.9),
-                                    ..Default::default()
-                                },
-                            },
-                            ..Default::default()
-                        });
-                    })
-                    .with(button);
-            }
+                    .with(Jacket(None));
+                spawn_text(parent, InfoText::Song, &button_materials);
+
+                for button in [
+                    MenuButton::Difficulty,
+                    MenuButton::Sort,
+                    MenuButton::Pack,
+                    MenuButton::EditChart,
+                    MenuButton::MakeMap,
+                ] {
+                    spawn_button(parent, button, &select, &button_materials);
+                }
+            });
+
+            parent.spawn(column(200.0)).with_children(|parent| {
+                for button in [
+                    MenuButton::PlaybackRate(settings.rate.0),
+                    MenuButton::Orientation(*settings.orientation),
+                    MenuButton::ScrollSpeed(*settings.scroll),
+                    MenuButton::LifeGauge(*settings.gauge),
+                    MenuButton::NoFail(settings.no_fail.0),
+                    MenuButton::LaneModifier(settings.modifiers.lanes),
+                    MenuButton::VisibilityModifier(settings.modifiers.visibility),
+                    MenuButton::FadeDistance(settings.modifiers.fade_distance),
+                    MenuButton::Practice(settings.practice.0),
+                    MenuButton::SessionExport(*settings.export),
+                    MenuButton::TimingDisplay(*settings.timing_display),
+                ] {
+                    spawn_button(parent, button, &select, &button_materials);
+                }
+            });
         });
 }
 
+/// Spawns a menu button with its name as its text. Songs get a second, smaller
+/// line of text for their details.
+fn spawn_button(
+    parent: &mut ChildBuilder,
+    button: MenuButton,
+    select: &SongSelect,
+    button_materials: &ButtonMaterials,
+) {
+    let text = |value, font_size| TextBundle {
+        text: Text {
+            value,
+            font: button_materials.font.clone(),
+            style: TextStyle {
+                font_size,
+                color: Color::rgb(0.9, 0.9, 0.9),
+                ..Default::default()
+            },
+        },
+        ..Default::default()
+    };
+
+    parent
+        .spawn(ButtonBundle {
+            style: Style {
+                size: button.size(),
+                margin: Rect::all(Val::Px(4.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: button_materials.normal.clone(),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            parent.spawn(text(button.name(select), button.font_size()));
+            if let MenuButton::Song(_) = button {
+                parent.spawn(text(String::new(), 14.0));
+            }
+        })
+        .with(button);
+}
+
+/// Spawns some text that gets filled in by `update_song_info`.
+fn spawn_text(parent: &mut ChildBuilder, info: InfoText, button_materials: &ButtonMaterials) {
+    parent
+        .spawn(TextBundle {
+            style: Style {
+                margin: Rect::all(Val::Px(8.0)),
+                ..Default::default()
+            },
+            text: Text {
+                value: String::new(),
+                font: button_materials.font.clone(),
+                style: TextStyle {
+                    font_size: 20.0,
+                    color: Color::rgb(0.9, 0.9, 0.9),
+                    ..Default::default()
+                },
+            },
+            ..Default::default()
+        })
+        .with(info);
+}
+
 /// Remove the menu.
 fn despawn_menu(commands: &mut Commands, query: Query<(Entity, &MenuUI)>) {
     for (entity, _) in query.iter() {
@@ -130,15 +736,20 @@ fn despawn_menu(commands: &mut Commands, query: Query<(Entity, &MenuUI)>) {
     }
 }
 
-/// Handles changing menu button backgrounds whenever their states change.
+/// Buttons whose interaction has changed since the last frame.
+type ChangedButtons = (Mutated<Interaction>, With<Button>);
+
+/// Handles changing menu button backgrounds whenever their states change. The
+/// song list's buttons are handled by `update_song_list`.
 fn button_color_system(
     button_materials: Res<ButtonMaterials>,
-    mut query: Query<
-        (&Interaction, &mut Handle<ColorMaterial>),
-        (Mutated<Interaction>, With<Button>),
-    >,
+    mut query: Query<(&Interaction, &MenuButton, &mut Handle<ColorMaterial>), ChangedButtons>,
 ) {
-    for (interaction, mut material) in query.iter_mut() {
+    for (interaction, button, mut material) in query.iter_mut() {
+        if let MenuButton::Song(_) = button {
+            continue;
+        }
+
         match *interaction {
             Interaction::Clicked => {
                 *material = button_materials.pressed.clone();
@@ -159,49 +770,625 @@ fn button_color_system(
 fn button_press_system(
     commands: &mut Commands,
     asset_server: Res<AssetServer>,
-    query: Query<(&Interaction, &MenuButton), (Mutated<Interaction>, With<Button>)>,
+    high_scores: Res<HighScores>,
+    query: Query<(&Interaction, &MenuButton), ChangedButtons>,
+    mut select: ResMut<SongSelect>,
+    mut pending: ResMut<PendingSong>,
     mut state: ResMut<State<AppState>>,
 ) {
     for (interaction, button) in query.iter() {
         if *interaction == Interaction::Clicked {
             match button {
                 // If the map maker button was clicked, change the state.
-                MenuButton::MakeMap => state
-                    .set_next(AppState::MakeMap)
-                    .expect("Couldn't switch state to MakeMap."),
+                MenuButton::MakeMap => {
+                    commands.insert_resource(SongStart::default());
 
-                // If a play song button was clicked, load the config, insert it
-                // as a resource, and change state.
-                MenuButton::PlaySong(song) => {
-                    let config = load_config(&format!("{}.toml", song), &asset_server);
+                    state
+                        .set_next(AppState::MakeMap)
+                        .expect("Couldn't switch state to MakeMap.")
+                }
 
-                    commands.insert_resource(config);
+                // Clicking a song highlights it, or plays it if it already
+                // was.
+                MenuButton::Song(slot) => {
+                    let index = select.scroll + slot;
+                    if index == select.highlighted {
+                        select.play(&asset_server, &mut pending);
+                    } else if index < select.shown.len() {
+                        select.highlighted = index;
+                    }
+                }
 
-                    state
-                        .set_next(AppState::Game)
-                        .expect("Couldn't switch state to Game")
+                MenuButton::Difficulty => select.change_difficulty(1, &high_scores),
+
+                MenuButton::Pack => select.next_pack(&high_scores),
+
+                MenuButton::Sort => {
+                    select.sort = select.sort.next();
+                    select.update_shown(&high_scores);
                 }
+
+                // If the edit chart button was clicked, open the highlighted
+                // song's chart in the editor.
+                MenuButton::EditChart => match select.highlighted() {
+                    // Only our own charts can be edited.
+                    Some(song) if song.file.ends_with(".toml") => {
+                        match EditorState::open(&song.file) {
+                            Ok(editor) => {
+                                commands.insert_resource(editor);
+
+                                state
+                                    .set_next(AppState::EditChart)
+                                    .expect("Couldn't switch state to EditChart")
+                            }
+                            Err(err) => error!("Couldn't open {}: {}", song.file, err),
+                        }
+                    }
+                    Some(song) => info!("{} isn't a TOML chart, so it can't be edited", song.file),
+                    None => {}
+                },
+
+                // Handled by `settings_button_system`.
+                MenuButton::PlaybackRate(_)
+                | MenuButton::Orientation(_)
+                | MenuButton::ScrollSpeed(_)
+                | MenuButton::LifeGauge(_)
+                | MenuButton::NoFail(_)
+                | MenuButton::LaneModifier(_)
+                | MenuButton::VisibilityModifier(_)
+                | MenuButton::FadeDistance(_)
+                | MenuButton::Practice(_)
+                | MenuButton::SessionExport(_)
+                | MenuButton::TimingDisplay(_) => {}
+            }
+        }
+    }
+}
+
+/// Typing and scrolling done in song select.
+#[derive(SystemParam)]
+pub struct SongSelectInput<'a> {
+    char_reader: Local<'a, EventReader<ReceivedCharacter>>,
+    char_events: Res<'a, Events<ReceivedCharacter>>,
+    wheel_reader: Local<'a, EventReader<MouseWheel>>,
+    wheel_events: Res<'a, Events<MouseWheel>>,
+}
+
+impl<'a> SongSelectInput<'a> {
+    /// Characters typed since the last frame.
+    fn typed(&mut self) -> String {
+        self.char_reader
+            .iter(&self.char_events)
+            .map(|event| event.char)
+            // Arrow keys and such come through as control or private use
+            // characters on some platforms.
+            .filter(|c| c.is_alphanumeric() || c.is_ascii_punctuation() || *c == ' ')
+            .collect()
+    }
+
+    /// How many songs to move down the list by for scrolling since the last
+    /// frame, or up for negative numbers.
+    fn scrolled(&mut self) -> isize {
+        self.wheel_reader
+            .iter(&self.wheel_events)
+            .filter(|event| event.y != 0.0)
+            .map(|event| if event.y > 0.0 { -1 } else { 1 })
+            .sum()
+    }
+}
+
+/// Lets song select be used from the keyboard and mouse wheel. Up and down
+/// move through the list, left and right pick a difficulty, Enter plays the
+/// highlighted song and Tab changes how the list is sorted. Typing searches.
+fn song_select_keys(
+    mut input: SongSelectInput,
+    keyboard_input: Res<Input<KeyCode>>,
+    asset_server: Res<AssetServer>,
+    high_scores: Res<HighScores>,
+    mut select: ResMut<SongSelect>,
+    mut pending: ResMut<PendingSong>,
+) {
+    let typed = input.typed();
+    let scrolled = input.scrolled();
+
+    if select.entering {
+        select.entering = false;
+        return;
+    }
+
+    if !typed.is_empty() || keyboard_input.just_pressed(KeyCode::Back) {
+        select.search += &typed;
+        if keyboard_input.just_pressed(KeyCode::Back) {
+            select.search.pop();
+        }
+        select.update_shown(&high_scores);
+    }
+
+    let mut by = scrolled;
+    for (key, key_by) in [
+        (KeyCode::Up, -1),
+        (KeyCode::Down, 1),
+        (KeyCode::PageUp, -(VISIBLE_SONGS as isize)),
+        (KeyCode::PageDown, VISIBLE_SONGS as isize),
+    ]
+    .iter()
+    {
+        if keyboard_input.just_pressed(*key) {
+            by += key_by;
+        }
+    }
+    if by != 0 {
+        select.move_highlight(by);
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Left) {
+        select.change_difficulty(-1, &high_scores);
+    }
+    if keyboard_input.just_pressed(KeyCode::Right) {
+        select.change_difficulty(1, &high_scores);
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Tab) {
+        select.sort = select.sort.next();
+        select.update_shown(&high_scores);
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Return) {
+        select.play(&asset_server, &mut pending);
+    }
+}
+
+/// Changes some text, leaving it alone if it's already right so that it doesn't
+/// get laid out again.
+fn set_text(mut text: Mut<Text>, value: String) {
+    if text.value != value {
+        text.value = value;
+    }
+}
+
+/// Keeps the song list, and the buttons that depend on the highlighted song,
+/// up to date.
+fn update_song_list(
+    select: Res<SongSelect>,
+    button_materials: Res<ButtonMaterials>,
+    mut buttons: Query<(
+        &MenuButton,
+        &Interaction,
+        &mut Handle<ColorMaterial>,
+        &Children,
+    )>,
+    mut texts: Query<&mut Text>,
+) {
+    for (button, interaction, mut material, children) in buttons.iter_mut() {
+        if let Ok(text) = texts.get_mut(children[0]) {
+            set_text(text, button.name(&select));
+        }
+
+        let slot = match button {
+            MenuButton::Song(slot) => *slot,
+            _ => continue,
+        };
+        let song = select.in_slot(slot);
+
+        if let Some(text) = children.get(1).and_then(|child| texts.get_mut(*child).ok()) {
+            set_text(
+                text,
+                song.map_or_else(String::new, |song| song.details.clone()),
+            );
+        }
+
+        let new_material = if song.is_none() {
+            &button_materials.none
+        } else if select.scroll + slot == select.highlighted {
+            &button_materials.highlighted
+        } else if *interaction == Interaction::Hovered {
+            &button_materials.hovered
+        } else {
+            &button_materials.normal
+        };
+        if *material != *new_material {
+            *material = new_material.clone();
+        }
+    }
+}
+
+/// Shows what's being searched for, and the highlighted song's jacket and best
+/// score.
+fn update_song_info(
+    select: Res<SongSelect>,
+    high_scores: Res<HighScores>,
+    button_materials: Res<ButtonMaterials>,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut texts: Query<(&InfoText, &mut Text)>,
+    mut jackets: Query<(&mut Jacket, &mut Handle<ColorMaterial>)>,
+) {
+    let song = select.highlighted();
+
+    for (info, text) in texts.iter_mut() {
+        let value = match info {
+            InfoText::Search if select.search.is_empty() => {
+                "Type to search, Tab to sort".to_string()
+            }
+            InfoText::Search => format!("Search: {}", select.search),
+            InfoText::Song => match song.map(|song| select.best(song, &high_scores)) {
+                Some(Some(best)) => format!(
+                    "Best: {}{}",
+                    best.score,
+                    if best.is_slowed() { " (slowed)" } else { "" }
+                ),
+                Some(None) => "No best yet".to_string(),
+                None => "No songs found".to_string(),
+            },
+        };
+        set_text(text, value);
+    }
+
+    // Jackets only get loaded when the highlighted song changes.
+    let image = song.and_then(SongEntry::jacket);
+    for (mut jacket, mut material) in jackets.iter_mut() {
+        if jacket.0 == image {
+            continue;
+        }
+
+        *material = match &image {
+            Some(path) => {
+                let texture: Handle<Texture> = asset_server.load(path.as_path());
+                materials.add(texture.into())
+            }
+            None => button_materials.none.clone(),
+        };
+        jacket.0 = image.clone();
+    }
+}
+
+/// Plays the preview of the highlighted song, over and over.
+fn play_preview(
+    select: Res<SongSelect>,
+    asset_server: Res<AssetServer>,
+    audio_sources: Res<Assets<AudioSource>>,
+    time: Res<Time>,
+    rate: Res<PlaybackRate>,
+    mut preview: ResMut<Preview>,
+    mut player: ResMut<SongPlayer>,
+) {
+    let song = match select.highlighted() {
+        Some(song) => song,
+        None => {
+            if preview.song.take().is_some() {
+                player.stop();
+            }
+            return;
+        }
+    };
+
+    if preview.song.as_ref().map(|(file, _)| file) != Some(&song.file) {
+        player.stop();
+        let audio = asset_server.load(song.asset_path(&song.chart.filename).as_path());
+        preview.song = Some((song.file.clone(), audio));
+        preview.started = None;
+    }
+
+    // Previews play at the picked rate, so they take longer when slowed down.
+    let (start, length) = song.preview();
+    let now = time.seconds_since_startup();
+    if let Some(started) = preview.started {
+        if now - started < length / rate.0 {
+            return;
+        }
+    }
+
+    let audio = preview.song.as_ref().map(|(_, audio)| audio.clone());
+    if let Some(source) = audio.and_then(|audio| audio_sources.get(&audio)) {
+        player.set_rate(rate.0);
+        player.play(source, start);
+        preview.started = Some(now);
+    }
+}
+
+/// Starts the picked song once its chart has loaded, inserting the chart as a
+/// resource.
+fn start_pending_song(
+    commands: &mut Commands,
+    asset_server: Res<AssetServer>,
+    charts: Res<Assets<SongConfig>>,
+    mut pending: ResMut<PendingSong>,
+    mut state: ResMut<State<AppState>>,
+) {
+    let (song, handle) = match pending.0.take() {
+        Some(pending) => pending,
+        None => return,
+    };
+
+    if let Some(chart) = charts.get(&handle) {
+        commands.insert_resource(chart.clone());
+        commands.insert_resource(ChartHandle(Some(handle)));
+        commands.insert_resource(SongStart::default());
+        commands.insert_resource(Playtest::default());
+
+        state
+            .set_next(AppState::Game)
+            .expect("Couldn't switch state to Game");
+    } else if asset_server.get_load_state(&handle) == LoadState::Failed {
+        // The asset server logs why
+        error!("Couldn't load {}", song);
+    } else {
+        pending.0 = Some((song, handle));
+    }
+}
+
+/// Changes settings like the playback rate or life gauge when their buttons
+/// are clicked.
+fn settings_button_system(
+    mut query: Query<(&Interaction, &mut MenuButton), Mutated<Interaction>>,
+    mut settings: SettingsMut,
+) {
+    for (interaction, mut button) in query.iter_mut() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+
+        match &mut *button {
+            MenuButton::PlaybackRate(button_rate) => {
+                settings.rate.cycle();
+                *button_rate = settings.rate.0;
+            }
+            MenuButton::Orientation(button_orientation) => {
+                *settings.orientation = settings.orientation.next();
+                *button_orientation = *settings.orientation;
+            }
+            MenuButton::ScrollSpeed(button_scroll) => {
+                *settings.scroll = settings.scroll.next();
+                *button_scroll = *settings.scroll;
+            }
+            MenuButton::LifeGauge(button_gauge) => {
+                *settings.gauge = settings.gauge.next();
+                *button_gauge = *settings.gauge;
             }
+            MenuButton::NoFail(button_no_fail) => {
+                settings.no_fail.0 = !settings.no_fail.0;
+                *button_no_fail = settings.no_fail.0;
+            }
+            MenuButton::LaneModifier(button_modifier) => {
+                settings.modifiers.lanes = settings.modifiers.lanes.next();
+                *button_modifier = settings.modifiers.lanes;
+            }
+            MenuButton::VisibilityModifier(button_modifier) => {
+                settings.modifiers.visibility = settings.modifiers.visibility.next();
+                *button_modifier = settings.modifiers.visibility;
+            }
+            MenuButton::FadeDistance(button_distance) => {
+                settings.modifiers.next_fade_distance();
+                *button_distance = settings.modifiers.fade_distance;
+            }
+            MenuButton::Practice(button_practice) => {
+                settings.practice.0 = !settings.practice.0;
+                *button_practice = settings.practice.0;
+            }
+            MenuButton::SessionExport(button_export) => {
+                *settings.export = settings.export.next();
+                *button_export = *settings.export;
+            }
+            MenuButton::TimingDisplay(button_display) => {
+                *settings.timing_display = settings.timing_display.next();
+                *button_display = *settings.timing_display;
+            }
+            _ => {}
         }
     }
 }
 
-/// Gets all available songs.
+/// Gets all available songs, as paths relative to `assets/songs`. Songs can
+/// have a folder of their own, and those folders can be grouped into packs as
+/// deep as you like. Includes StepMania simfiles and osu! beatmaps as well as
+/// our own charts.
 pub fn get_songs() -> Vec<String> {
-    read_dir("assets/songs")
-        .unwrap()
-        .map(|dir_entry| dir_entry.unwrap().path())
-        .filter(|path| {
-            path.as_path()
-                .extension()
-                .map_or(false, |ext| ext == "toml")
-        })
-        .map(|path| {
-            path.as_path()
-                .file_stem()
-                .unwrap()
-                .to_string_lossy()
-                .to_string()
-        })
-        .collect()
+    let mut songs = Vec::new();
+    find_songs(Path::new("assets/songs"), "", &mut songs);
+    songs
+}
+
+/// Adds every chart in a directory and the directories inside it to `songs`.
+/// `prefix` is where the directory is, relative to `assets/songs`. Anything
+/// that can't be read gets skipped.
+fn find_songs(dir: &Path, prefix: &str, songs: &mut Vec<String>) {
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            warn!("Couldn't read {}: {}", dir.display(), err);
+            return;
+        }
+    };
+
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                warn!("Couldn't read an entry in {}: {}", dir.display(), err);
+                continue;
+            }
+        };
+
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        let file = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+
+        // Symlinks aren't followed, so they can't loop back around.
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => find_songs(&path, &file, songs),
+            Ok(_) if is_chart_file(&path) => songs.push(file),
+            Ok(_) => {}
+            Err(err) => warn!("Couldn't read {}: {}", path.display(), err),
+        }
+    }
+}
+
+/// The pack a song's in: whichever folders are above the song's own folder.
+/// Charts that aren't in a folder of their own aren't in a pack.
+fn song_pack(file: &str) -> Option<String> {
+    let parts = file.split('/').collect::<Vec<_>>();
+
+    // The last part is the chart, and the one before that is the song's own
+    // folder.
+    if parts.len() > 2 {
+        Some(parts[..parts.len() - 2].join("/"))
+    } else {
+        None
+    }
+}
+
+/// How a song file gets shown in the menu, for charts without a name.
+/// Imported charts show what format they're from.
+fn song_label(song: &str) -> String {
+    let path = Path::new(song);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") | None => stem.to_string(),
+        Some(ext) => format!("{} ({})", stem, ext),
+    }
+}
+
+/// A line about a song for song select, with whatever its chart knows about
+/// it.
+fn song_details(chart: &SongConfigToml) -> String {
+    let length = chart.length().round() as u64;
+    let details = vec![
+        chart.artist.clone(),
+        chart.genre.clone(),
+        chart.bpm_label().map(|bpm| format!("{} BPM", bpm)),
+        Some(format!("{}:{:02}", length / 60, length % 60)),
+        chart
+            .charter
+            .as_ref()
+            .map(|charter| format!("by {}", charter)),
+    ];
+
+    details
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" - ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lanes::Lanes,
+        types::{DifficultyToml, DEFAULT_DIFFICULTY},
+    };
+
+    fn song(file: &str, name: &str, artist: &str) -> SongEntry {
+        let chart = SongConfigToml {
+            name: name.to_string(),
+            artist: Some(artist.to_string()),
+            difficulties: vec![DifficultyToml {
+                name: DEFAULT_DIFFICULTY.to_string(),
+                level: 1,
+                lanes: Lanes::default(),
+                arrows: Vec::new(),
+                timing: None,
+            }],
+            ..Default::default()
+        };
+        SongEntry::new(file.to_string(), chart)
+    }
+
+    fn select() -> SongSelect {
+        SongSelect {
+            songs: vec![
+                song("alpha.toml", "Alpha", "Zed"),
+                song("beta.sm", "Beta", "Abe"),
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn shown(select: &SongSelect) -> Vec<&str> {
+        select
+            .shown
+            .iter()
+            .map(|&i| select.songs[i].chart.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn search_looks_at_files_and_metadata() {
+        let high_scores = HighScores::default();
+        let mut select = select();
+
+        select.search = "ZED".to_string();
+        select.update_shown(&high_scores);
+        assert_eq!(shown(&select), ["Alpha"]);
+
+        select.search = ".sm".to_string();
+        select.update_shown(&high_scores);
+        assert_eq!(shown(&select), ["Beta"]);
+
+        select.search = "nothing".to_string();
+        select.update_shown(&high_scores);
+        assert!(select.highlighted().is_none());
+    }
+
+    #[test]
+    fn sorting_keeps_the_highlight() {
+        let mut high_scores = HighScores::default();
+        let mut select = select();
+
+        select.update_shown(&high_scores);
+        assert_eq!(shown(&select), ["Alpha", "Beta"]);
+        select.move_highlight(1);
+
+        select.sort = SortOrder::Artist;
+        select.update_shown(&high_scores);
+        assert_eq!(shown(&select), ["Beta", "Alpha"]);
+        assert_eq!(select.highlighted().unwrap().chart.name, "Beta");
+
+        // Songs that haven't been played go after the ones that have.
+        let run = HighScore {
+            score: 100,
+            corrects: 1,
+            fails: 0,
+            rate: 1.0,
+            failed: false,
+            modifiers: Default::default(),
+        };
+        high_scores.submit("alpha.toml", DEFAULT_DIFFICULTY, run);
+        select.sort = SortOrder::BestScore;
+        select.update_shown(&high_scores);
+        assert_eq!(shown(&select), ["Alpha", "Beta"]);
+        assert_eq!(select.highlighted().unwrap().chart.name, "Beta");
+    }
+
+    #[test]
+    fn packs_are_folders_above_the_song_folder() {
+        assert_eq!(song_pack("song.toml"), None);
+        assert_eq!(song_pack("Song/song.sm"), None);
+        assert_eq!(song_pack("Pack/Song/song.sm").as_deref(), Some("Pack"));
+        assert_eq!(
+            song_pack("Big/Pack/Song/song.osu").as_deref(),
+            Some("Big/Pack")
+        );
+
+        let high_scores = HighScores::default();
+        let mut select = select();
+        select
+            .songs
+            .push(song("Pack/Gamma/gamma.sm", "Gamma", "Someone"));
+        select.packs = vec!["Pack".to_string()];
+        select.update_shown(&high_scores);
+        // Songs that aren't in a pack go first.
+        assert_eq!(shown(&select), ["Alpha", "Beta", "Gamma"]);
+
+        select.next_pack(&high_scores);
+        assert_eq!(shown(&select), ["Gamma"]);
+        select.next_pack(&high_scores);
+        assert_eq!(select.pack, None);
+        assert_eq!(shown(&select).len(), 3);
+    }
 }
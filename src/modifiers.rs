@@ -0,0 +1,390 @@
+use crate::{consts::*, lanes::Lane, types::SongConfig};
+use bevy::prelude::*;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde_derive::{Deserialize, Serialize};
+
+/// Lets players change up how charts are played.
+pub struct ModifiersPlugin;
+
+impl Plugin for ModifiersPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<Modifiers>().on_state_enter(
+            APP_STATE_STAGE,
+            AppState::Game,
+            apply_modifiers.system(),
+        );
+    }
+}
+
+/// Moves a chart's arrows into different lanes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum LaneModifier {
+    #[default]
+    Off,
+    /// Flips the chart, so left and right swap, and so do up and down.
+    Mirror,
+    /// Swaps the lanes around, the same way for the whole chart.
+    Shuffle,
+    /// Puts each arrow in a lane of its own picking.
+    Random,
+}
+
+impl LaneModifier {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Off => "Off",
+            Self::Mirror => "Mirror",
+            Self::Shuffle => "Shuffle",
+            Self::Random => "Random",
+        }
+    }
+
+    /// The modifier after this one, going back to the first after the last.
+    pub fn next(&self) -> Self {
+        match self {
+            Self::Off => Self::Mirror,
+            Self::Mirror => Self::Shuffle,
+            Self::Shuffle => Self::Random,
+            Self::Random => Self::Off,
+        }
+    }
+
+    /// Whether the modifier depends on the seed.
+    pub fn is_random(&self) -> bool {
+        matches!(self, Self::Shuffle | Self::Random)
+    }
+}
+
+/// Hides arrows for part or all of the way to their targets.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum VisibilityModifier {
+    #[default]
+    Off,
+    /// Arrows fade out as they get close to their targets.
+    Hidden,
+    /// Arrows only fade in once they're close to their targets.
+    Sudden,
+    /// Arrows can't be seen at all.
+    Stealth,
+}
+
+impl VisibilityModifier {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Off => "Off",
+            Self::Hidden => "Hidden",
+            Self::Sudden => "Sudden",
+            Self::Stealth => "Stealth",
+        }
+    }
+
+    /// The modifier after this one, going back to the first after the last.
+    pub fn next(&self) -> Self {
+        match self {
+            Self::Off => Self::Hidden,
+            Self::Hidden => Self::Sudden,
+            Self::Sudden => Self::Stealth,
+            Self::Stealth => Self::Off,
+        }
+    }
+}
+
+/// Fade distances that can be picked from, in pixels from the target.
+const FADE_DISTANCES: [f32; 5] = [100.0, 150.0, 200.0, 300.0, 400.0];
+
+/// How long the path is that arrows fade in or out over, in pixels.
+const FADE_LENGTH: f32 = 60.0;
+
+/// Random arrows won't go in a lane that had an arrow less than this long
+/// ago, in seconds, so they can't end up in jacks too fast to hit.
+const MIN_JACK_GAP: f64 = 0.125;
+
+/// Modifiers picked by the player. Saved along with scores, so runs can be
+/// told apart and shuffled charts can be laid out the same way again.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Modifiers {
+    pub lanes: LaneModifier,
+    /// What shuffle and random lay arrows out with. A new one gets picked for
+    /// each game.
+    pub seed: u32,
+    pub visibility: VisibilityModifier,
+    /// How far from their targets arrows get hidden by hidden, or shown by
+    /// sudden, in pixels.
+    pub fade_distance: f32,
+}
+
+impl Default for Modifiers {
+    fn default() -> Self {
+        Self {
+            lanes: LaneModifier::default(),
+            seed: 0,
+            visibility: VisibilityModifier::default(),
+            fade_distance: 200.0,
+        }
+    }
+}
+
+impl Modifiers {
+    /// Short description of the modifiers, like "Shuffle (seed 1234), Hidden
+    /// (200px)". Empty if none are on.
+    pub fn label(&self) -> String {
+        let lanes = match self.lanes {
+            LaneModifier::Off => None,
+            lanes if lanes.is_random() => Some(format!("{} (seed {})", lanes.name(), self.seed)),
+            lanes => Some(lanes.name().to_string()),
+        };
+        let visibility = match self.visibility {
+            VisibilityModifier::Off => None,
+            VisibilityModifier::Stealth => Some(self.visibility.name().to_string()),
+            visibility => Some(format!("{} ({}px)", visibility.name(), self.fade_distance)),
+        };
+
+        lanes
+            .into_iter()
+            .chain(visibility)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Switches to the next fade distance, going back to the shortest after
+    /// the longest.
+    pub fn next_fade_distance(&mut self) {
+        self.fade_distance = FADE_DISTANCES
+            .iter()
+            .copied()
+            .find(|distance| *distance > self.fade_distance)
+            .unwrap_or(FADE_DISTANCES[0]);
+    }
+
+    /// How see-through an arrow should be this far away from its target, from
+    /// 0 for invisible to 1 for fully shown. Arrows past their target have a
+    /// negative distance.
+    pub fn alpha(&self, distance: f32) -> f32 {
+        let fade = ((distance - self.fade_distance) / FADE_LENGTH).clamp(0.0, 1.0);
+        match self.visibility {
+            VisibilityModifier::Off => 1.0,
+            VisibilityModifier::Hidden => fade,
+            VisibilityModifier::Sudden => 1.0 - fade,
+            VisibilityModifier::Stealth => 0.0,
+        }
+    }
+
+    /// Moves a chart's arrows around. The same modifiers and seed always move
+    /// them the same way.
+    pub fn apply(&self, config: &mut SongConfig) {
+        let lanes = config.lanes;
+        let count = lanes.count();
+        let mut rng = StdRng::seed_from_u64(self.seed as u64);
+
+        match self.lanes {
+            LaneModifier::Off => {}
+            LaneModifier::Mirror => {
+                for arrow in config.arrows.iter_mut() {
+                    let column = count - 1 - lanes.column_for_lane(arrow.lane);
+                    arrow.lane = lanes.lane_for_column(column);
+                }
+            }
+            LaneModifier::Shuffle => {
+                let mut shuffled = lanes.iter().collect::<Vec<_>>();
+                shuffled.shuffle(&mut rng);
+                for arrow in config.arrows.iter_mut() {
+                    arrow.lane = shuffled[arrow.lane];
+                }
+            }
+            LaneModifier::Random => {
+                // Arrows might be sorted by spawn time, so go through them in
+                // the order they get hit.
+                let mut order = (0..config.arrows.len()).collect::<Vec<_>>();
+                order.sort_by(|a, b| {
+                    let (a, b) = (&config.arrows[*a], &config.arrows[*b]);
+                    a.click_time.partial_cmp(&b.click_time).unwrap()
+                });
+
+                // When each lane is free again, after its last arrow or hold.
+                let mut free_at = vec![f64::NEG_INFINITY; count];
+                for i in order {
+                    let arrow = &mut config.arrows[i];
+                    let time = arrow.click_time;
+
+                    let free = lanes
+                        .iter()
+                        .filter(|lane| free_at[*lane] + MIN_JACK_GAP <= time)
+                        .collect::<Vec<Lane>>();
+                    // Busy charts might not leave any lane free, so fall back
+                    // on the one that's been free the longest.
+                    let lane = match free.choose(&mut rng) {
+                        Some(lane) => *lane,
+                        None => lanes
+                            .iter()
+                            .min_by(|a, b| free_at[*a].partial_cmp(&free_at[*b]).unwrap())
+                            .unwrap(),
+                    };
+
+                    arrow.lane = lane;
+                    free_at[lane] = time + arrow.hold.unwrap_or(0.0);
+                }
+            }
+        }
+    }
+}
+
+/// Picks a new seed and moves the chart's arrows around at the start of a
+/// game.
+fn apply_modifiers(mut song_config: ResMut<SongConfig>, mut modifiers: ResMut<Modifiers>) {
+    modifiers.seed = rand::random();
+    modifiers.apply(&mut song_config);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lanes::Lanes,
+        types::{ArrowTimeToml, DifficultyToml, Directions, SongConfigToml, Speed},
+    };
+
+    /// A busy 4K chart, with jumps and a few holds thrown in.
+    fn chart() -> SongConfig {
+        let arrows = (0..200)
+            .flat_map(|i| {
+                let arrow = |lane, hold| ArrowTimeToml {
+                    click_time: i as f64 * 0.1,
+                    speed: Speed::SLOW,
+                    lane,
+                    hold,
+                    mine: false,
+                };
+
+                let mut arrows = vec![arrow(i % 4, None)];
+                if i % 7 == 0 {
+                    arrows.push(arrow((i + 2) % 4, None));
+                }
+                if i % 11 == 0 {
+                    arrows[0].hold = Some(0.35);
+                }
+                arrows
+            })
+            .collect();
+
+        SongConfig::with_audio(
+            &SongConfigToml::default(),
+            &DifficultyToml {
+                name: "Test".to_string(),
+                level: 0,
+                lanes: Lanes::default(),
+                arrows,
+                timing: None,
+            },
+            Default::default(),
+        )
+    }
+
+    fn modified(lanes: LaneModifier, seed: u32) -> SongConfig {
+        let mut config = chart();
+        Modifiers {
+            lanes,
+            seed,
+            ..Default::default()
+        }
+        .apply(&mut config);
+        config
+    }
+
+    fn lanes(config: &SongConfig) -> Vec<Lane> {
+        config.arrows.iter().map(|arrow| arrow.lane).collect()
+    }
+
+    #[test]
+    fn random_avoids_jacks() {
+        for seed in 0..20 {
+            let mut config = modified(LaneModifier::Random, seed);
+            config
+                .arrows
+                .sort_by(|a, b| a.click_time.partial_cmp(&b.click_time).unwrap());
+
+            let mut free_at = [f64::NEG_INFINITY; 4];
+            for arrow in &config.arrows {
+                let time = arrow.click_time;
+                let any_free = free_at.iter().any(|free_at| free_at + MIN_JACK_GAP <= time);
+                if any_free {
+                    assert!(
+                        free_at[arrow.lane] + MIN_JACK_GAP <= time,
+                        "seed {} put an arrow at {}s in lane {} too soon after the last one",
+                        seed,
+                        time,
+                        arrow.lane
+                    );
+                }
+                free_at[arrow.lane] = time + arrow.hold.unwrap_or(0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn same_seed_same_lanes() {
+        for modifier in [LaneModifier::Shuffle, LaneModifier::Random].iter() {
+            assert_eq!(
+                lanes(&modified(*modifier, 1234)),
+                lanes(&modified(*modifier, 1234))
+            );
+            assert_ne!(
+                lanes(&modified(*modifier, 1234)),
+                lanes(&modified(*modifier, 4321))
+            );
+        }
+    }
+
+    #[test]
+    fn mirror_flips_directions() {
+        let before = chart();
+        let after = modified(LaneModifier::Mirror, 0);
+        let lanes = before.lanes;
+
+        for (before, after) in before.arrows.iter().zip(&after.arrows) {
+            let flipped = match lanes.direction(before.lane) {
+                Directions::Left => Directions::Right,
+                Directions::Right => Directions::Left,
+                Directions::Up => Directions::Down,
+                Directions::Down => Directions::Up,
+                direction => direction,
+            };
+            assert_eq!(lanes.direction(after.lane), flipped);
+        }
+    }
+
+    #[test]
+    fn hidden_and_sudden_fade_at_the_fade_distance() {
+        let modifiers = |visibility| Modifiers {
+            visibility,
+            fade_distance: 200.0,
+            ..Default::default()
+        };
+        let hidden = modifiers(VisibilityModifier::Hidden);
+        let sudden = modifiers(VisibilityModifier::Sudden);
+
+        // Far away from the target
+        assert_eq!(hidden.alpha(500.0), 1.0);
+        assert_eq!(sudden.alpha(500.0), 0.0);
+        // Partway through fading
+        assert_eq!(hidden.alpha(200.0 + FADE_LENGTH / 2.0), 0.5);
+        assert_eq!(sudden.alpha(200.0 + FADE_LENGTH / 2.0), 0.5);
+        // Close to and past the target
+        assert_eq!(hidden.alpha(100.0), 0.0);
+        assert_eq!(sudden.alpha(-50.0), 1.0);
+
+        assert_eq!(modifiers(VisibilityModifier::Off).alpha(0.0), 1.0);
+        assert_eq!(modifiers(VisibilityModifier::Stealth).alpha(500.0), 0.0);
+    }
+
+    #[test]
+    fn fade_distances_wrap_around() {
+        let mut modifiers = Modifiers::default();
+        let mut seen = vec![];
+        for _ in 0..FADE_DISTANCES.len() {
+            modifiers.next_fade_distance();
+            seen.push(modifiers.fade_distance);
+        }
+        assert_eq!(seen, [300.0, 400.0, 100.0, 150.0, 200.0]);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::types::Speed;
+use bevy::prelude::*;
+
+/// Which way arrows travel towards their targets. Arrows keep pointing the way
+/// their lane says in every orientation, so they always match their keys.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Orientation {
+    #[default]
+    LeftToRight,
+    RightToLeft,
+    /// Arrows rise up to targets at the top of the screen.
+    Upscroll,
+    /// Arrows drop down to targets at the bottom of the screen.
+    Downscroll,
+}
+
+impl Orientation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::LeftToRight => "Left to right",
+            Self::RightToLeft => "Right to left",
+            Self::Upscroll => "Upscroll",
+            Self::Downscroll => "Downscroll",
+        }
+    }
+
+    /// The orientation after this one, going back to the first after the last.
+    pub fn next(&self) -> Self {
+        match self {
+            Self::LeftToRight => Self::RightToLeft,
+            Self::RightToLeft => Self::Upscroll,
+            Self::Upscroll => Self::Downscroll,
+            Self::Downscroll => Self::LeftToRight,
+        }
+    }
+
+    /// Works out where something goes on screen. `along` is how far it is
+    /// along the path from `SPAWN_POSITION` to `TARGET_POSITION`, and
+    /// `across` is where it is across the lanes, like `Lanes::y`.
+    pub fn place(&self, along: f32, across: f32) -> Vec2 {
+        match self {
+            Self::LeftToRight => Vec2::new(along, across),
+            Self::RightToLeft => Vec2::new(-along, across),
+            // The top lane ends up on the left
+            Self::Upscroll => Vec2::new(-across, along),
+            Self::Downscroll => Vec2::new(-across, -along),
+        }
+    }
+
+    /// Like [`place`](Self::place), with a z coordinate.
+    pub fn translation(&self, along: f32, across: f32, z: f32) -> Vec3 {
+        self.place(along, across).extend(z)
+    }
+
+    /// Size of a sprite that's `along` long in the direction arrows travel,
+    /// and `across` wide.
+    pub fn size(&self, along: f32, across: f32) -> Vec2 {
+        match self {
+            Self::LeftToRight | Self::RightToLeft => Vec2::new(along, across),
+            Self::Upscroll | Self::Downscroll => Vec2::new(across, along),
+        }
+    }
+
+    /// Which way arrows spin as they fall off past their targets. Layouts that
+    /// are mirror images of left to right spin the other way.
+    pub fn spin(&self) -> f32 {
+        match self {
+            Self::LeftToRight | Self::Upscroll => 1.0,
+            Self::RightToLeft | Self::Downscroll => -1.0,
+        }
+    }
+}
+
+/// How fast arrows scroll towards their targets, picked by the player. Faster
+/// arrows show up later and are spread further apart, which can make busy
+/// charts easier to read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScrollSpeed {
+    /// Multiplies the speeds the chart gives its arrows.
+    Multiplier(f32),
+    /// Every arrow moves at this many pixels a second, whatever the chart
+    /// says.
+    Constant(f32),
+}
+
+impl Default for ScrollSpeed {
+    fn default() -> Self {
+        Self::Multiplier(1.0)
+    }
+}
+
+impl ScrollSpeed {
+    /// Multipliers that can be picked from.
+    const MULTIPLIERS: [f32; 8] = [0.5, 0.75, 1.0, 1.25, 1.5, 2.0, 3.0, 4.0];
+    /// Constant speeds that can be picked from.
+    const CONSTANTS: [f32; 5] = [200.0, 300.0, 400.0, 600.0, 800.0];
+
+    /// How fast an arrow with some speed in the chart actually moves.
+    pub fn speed(&self, speed: Speed) -> f32 {
+        match self {
+            Self::Multiplier(multiplier) => speed.value() * multiplier,
+            Self::Constant(pixels) => *pixels,
+        }
+    }
+
+    /// Short name for the scroll speed, like "1.5x" or "C400".
+    pub fn name(&self) -> String {
+        match self {
+            Self::Multiplier(multiplier) => format!("{}x", multiplier),
+            Self::Constant(speed) => format!("C{}", speed),
+        }
+    }
+
+    /// Switches to the next fastest multiplier, then goes through the constant
+    /// speeds, then back to the slowest multiplier.
+    pub fn next(&self) -> Self {
+        match self {
+            Self::Multiplier(multiplier) => Self::MULTIPLIERS
+                .iter()
+                .find(|next| *next > multiplier)
+                .map_or(Self::Constant(Self::CONSTANTS[0]), |next| {
+                    Self::Multiplier(*next)
+                }),
+            Self::Constant(speed) => Self::CONSTANTS
+                .iter()
+                .find(|next| *next > speed)
+                .map_or(Self::Multiplier(Self::MULTIPLIERS[0]), |next| {
+                    Self::Constant(*next)
+                }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIENTATIONS: [Orientation; 4] = [
+        Orientation::LeftToRight,
+        Orientation::RightToLeft,
+        Orientation::Upscroll,
+        Orientation::Downscroll,
+    ];
+
+    #[test]
+    fn next_goes_through_every_orientation() {
+        let mut orientation = Orientation::default();
+        for expected in ORIENTATIONS.iter().cycle().skip(1).take(4) {
+            orientation = orientation.next();
+            assert_eq!(orientation, *expected);
+        }
+    }
+
+    #[test]
+    fn orientations_only_turn_the_playfield() {
+        for orientation in &ORIENTATIONS {
+            let place = |along, across| orientation.place(along, across);
+
+            // Distances along and across the playfield stay the same.
+            assert_eq!(place(300.0, 0.0).distance(place(-300.0, 0.0)), 600.0);
+            assert_eq!(place(0.0, 150.0).distance(place(0.0, -50.0)), 200.0);
+            // Sprites get turned the same way as where they're placed.
+            let size = orientation.size(20.0, 10.0);
+            assert_eq!(place(20.0, 10.0).abs(), size);
+        }
+    }
+
+    #[test]
+    fn scroll_speeds_go_through_multipliers_then_constants() {
+        let mut scroll = ScrollSpeed::Multiplier(4.0);
+        scroll = scroll.next();
+        assert_eq!(scroll, ScrollSpeed::Constant(200.0));
+        assert_eq!(scroll.speed(Speed::SLOW), scroll.speed(Speed::FAST));
+
+        scroll = ScrollSpeed::Constant(800.0).next();
+        assert_eq!(scroll, ScrollSpeed::Multiplier(0.5));
+        assert_eq!(scroll.speed(Speed::SLOW), Speed::SLOW.value() / 2.0);
+        assert_eq!(scroll.name(), "0.5x");
+    }
+}
@@ -0,0 +1,64 @@
+use crate::{chart::ChartHandle, consts::*, time::SongStart, types::SongConfig};
+use bevy::prelude::*;
+
+/// Lets charts be played straight out of the chart editor or the map maker,
+/// starting partway through the song.
+pub struct PlaytestPlugin;
+
+impl Plugin for PlaytestPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<Playtest>().on_state_update(
+            APP_STATE_STAGE,
+            AppState::Game,
+            return_from_playtest.system(),
+        );
+    }
+}
+
+/// Keeps track of where the current game was started from.
+#[derive(Debug, Default)]
+pub struct Playtest {
+    /// State to go back to when the playtest ends. `None` for normal games.
+    pub from: Option<AppState>,
+}
+
+/// Starts playing an in-memory chart from some point in the song, in seconds.
+/// Any arrows before that point get skipped.
+pub fn start_playtest(
+    commands: &mut Commands,
+    mut song_config: SongConfig,
+    start: f64,
+    from: AppState,
+    state: &mut State<AppState>,
+) {
+    song_config.arrows.retain(|arrow| arrow.click_time >= start);
+
+    commands.insert_resource(song_config);
+    commands.insert_resource(ChartHandle(None));
+    commands.insert_resource(SongStart {
+        time: start,
+        lead_in: PLAYTEST_LEAD_IN,
+    });
+    commands.insert_resource(Playtest { from: Some(from) });
+
+    state
+        .set_next(AppState::Game)
+        .expect("Couldn't switch state to Game");
+}
+
+/// Goes back to wherever the playtest was started from when Tab is pressed.
+fn return_from_playtest(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut playtest: ResMut<Playtest>,
+    mut state: ResMut<State<AppState>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::Tab) {
+        return;
+    }
+
+    if let Some(from) = playtest.from.take() {
+        state
+            .set_next(from)
+            .expect("Couldn't switch state back from playtest");
+    }
+}
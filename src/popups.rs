@@ -0,0 +1,357 @@
+use crate::{
+    arrows::{CorrectArrowEvent, MissArrowEvent},
+    consts::*,
+    lanes::Lane,
+    playfield::Orientation,
+    types::SongConfig,
+};
+use bevy::{ecs::SystemParam, prelude::*};
+
+/// Pops up how good each hit was next to its target, like "Perfect" or
+/// "Miss", and optionally whether it was early or late.
+pub struct PopupsPlugin;
+
+impl Plugin for PopupsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<TimingDisplay>()
+            .init_resource::<PopupMaterials>()
+            .on_state_update(APP_STATE_STAGE, AppState::Game, spawn_popups.system())
+            .on_state_update(APP_STATE_STAGE, AppState::Game, fade_popups.system())
+            .on_state_exit(APP_STATE_STAGE, AppState::Game, despawn_popups.system());
+    }
+}
+
+/// How long popups stay on screen, in seconds.
+const POPUP_LIFETIME: f64 = 0.6;
+
+/// How long popups take to shrink down to their normal size, in seconds.
+const POP_LENGTH: f64 = 0.08;
+
+/// How much bigger popups start out than they end up.
+const POP_SCALE: f32 = 1.4;
+
+/// Size of the box popups get centered in, in pixels.
+const POPUP_WIDTH: f32 = 160.0;
+const POPUP_HEIGHT: f32 = 60.0;
+
+/// How far before their target popups show up, in pixels along the lane.
+const POPUP_DISTANCE: f32 = 90.0;
+
+/// Font sizes for the judgement and for the early/late line under it.
+const JUDGEMENT_FONT_SIZE: f32 = 26.0;
+const TIMING_FONT_SIZE: f32 = 16.0;
+
+/// What gets shown under judgements about how early or late hits were, picked
+/// by the player.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum TimingDisplay {
+    Off,
+    /// "FAST" for early hits and "SLOW" for late ones.
+    #[default]
+    FastSlow,
+    /// As well as how far off the hit was, like "FAST -23ms".
+    Milliseconds,
+}
+
+impl TimingDisplay {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Off => "Off",
+            Self::FastSlow => "Fast/slow",
+            Self::Milliseconds => "Fast/slow + ms",
+        }
+    }
+
+    /// The option after this one, going back to the first after the last.
+    pub fn next(&self) -> Self {
+        match self {
+            Self::Off => Self::FastSlow,
+            Self::FastSlow => Self::Milliseconds,
+            Self::Milliseconds => Self::Off,
+        }
+    }
+
+    /// The line shown under a hit's judgement, if any. Perfect hits are close
+    /// enough that they don't get called early or late.
+    fn label(&self, grade: Grade, offset: f64) -> Option<String> {
+        if grade == Grade::Perfect {
+            return match self {
+                Self::Milliseconds => Some(format!("{:+.0}ms", offset * 1000.0)),
+                _ => None,
+            };
+        }
+
+        let fast_slow = if offset < 0.0 { "FAST" } else { "SLOW" };
+        match self {
+            Self::Off => None,
+            Self::FastSlow => Some(fast_slow.to_string()),
+            Self::Milliseconds => Some(format!("{} {:+.0}ms", fast_slow, offset * 1000.0)),
+        }
+    }
+}
+
+/// How good a hit was, going by how early or late it was.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Grade {
+    Perfect,
+    Great,
+    Good,
+    Bad,
+    Miss,
+}
+
+impl Grade {
+    fn from_offset(offset: f64) -> Self {
+        match offset.abs() {
+            offset if offset <= PERFECT_WINDOW => Self::Perfect,
+            offset if offset <= GREAT_WINDOW => Self::Great,
+            offset if offset <= GOOD_WINDOW => Self::Good,
+            _ => Self::Bad,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Perfect => "Perfect",
+            Self::Great => "Great",
+            Self::Good => "Good",
+            Self::Bad => "Bad",
+            Self::Miss => "Miss",
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            Self::Perfect => Color::rgb(0.4, 0.9, 1.0),
+            Self::Great => Color::rgb(0.4, 1.0, 0.5),
+            Self::Good => Color::rgb(1.0, 0.9, 0.4),
+            Self::Bad => Color::rgb(1.0, 0.6, 0.3),
+            Self::Miss => Color::rgb(1.0, 0.3, 0.3),
+        }
+    }
+}
+
+/// What popups get drawn with.
+struct PopupMaterials {
+    none: Handle<ColorMaterial>,
+    font: Handle<Font>,
+}
+
+impl FromResources for PopupMaterials {
+    fn from_resources(resources: &Resources) -> Self {
+        let mut materials = resources.get_mut::<Assets<ColorMaterial>>().unwrap();
+        let asset_server = resources.get::<AssetServer>().unwrap();
+
+        Self {
+            none: materials.add(Color::NONE.into()),
+            font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+        }
+    }
+}
+
+/// A judgement popup, along with when it showed up, in seconds since startup.
+struct Popup {
+    lane: Lane,
+    spawned: f64,
+}
+
+/// Text in a popup, and how big it normally is.
+struct PopupText {
+    font_size: f32,
+}
+
+/// Hits and misses that haven't had a popup yet.
+#[derive(SystemParam)]
+pub struct NewJudgements<'a> {
+    correct_reader: Local<'a, EventReader<CorrectArrowEvent>>,
+    correct_events: Res<'a, Events<CorrectArrowEvent>>,
+    miss_reader: Local<'a, EventReader<MissArrowEvent>>,
+    miss_events: Res<'a, Events<MissArrowEvent>>,
+}
+
+impl<'a> NewJudgements<'a> {
+    /// The lane, grade and timing line of each new hit and miss.
+    fn read(&mut self, display: &TimingDisplay) -> Vec<(Lane, Grade, Option<String>)> {
+        let hits = self.correct_reader.iter(&self.correct_events).map(|event| {
+            let grade = Grade::from_offset(event.offset);
+            (event.lane, grade, display.label(grade, event.offset))
+        });
+        let misses = self
+            .miss_reader
+            .iter(&self.miss_events)
+            .map(|event| (event.lane, Grade::Miss, None));
+        hits.chain(misses).collect()
+    }
+}
+
+/// Where popups go on screen.
+#[derive(SystemParam)]
+pub struct PopupLayout<'a> {
+    song_config: Res<'a, SongConfig>,
+    orientation: Res<'a, Orientation>,
+    windows: Res<'a, Windows>,
+}
+
+impl<'a> PopupLayout<'a> {
+    /// Where the popup box for a lane goes. UI is placed from the bottom left
+    /// corner, while the playfield is centered on the window.
+    fn position(&self, lane: Lane) -> Rect<Val> {
+        let (width, height) = self
+            .windows
+            .get_primary()
+            .map_or((800.0, 600.0), |window| (window.width(), window.height()));
+        let center = self.orientation.place(
+            TARGET_POSITION - POPUP_DISTANCE,
+            self.song_config.lanes.y(lane),
+        );
+
+        Rect {
+            left: Val::Px(center.x + width / 2.0 - POPUP_WIDTH / 2.0),
+            bottom: Val::Px(center.y + height / 2.0 - POPUP_HEIGHT / 2.0),
+            ..Default::default()
+        }
+    }
+}
+
+/// Shows a popup for every hit and miss. A new popup in a lane replaces the
+/// one that was there.
+fn spawn_popups(
+    commands: &mut Commands,
+    mut judgements: NewJudgements,
+    time: Res<Time>,
+    display: Res<TimingDisplay>,
+    layout: PopupLayout,
+    materials: Res<PopupMaterials>,
+    popups: Query<(Entity, &Popup)>,
+) {
+    for (lane, grade, timing) in judgements.read(&display) {
+        for (entity, popup) in popups.iter() {
+            if popup.lane == lane {
+                commands.despawn_recursive(entity);
+            }
+        }
+
+        let text = |value: String, font_size: f32, color| TextBundle {
+            text: Text {
+                value,
+                font: materials.font.clone(),
+                style: TextStyle {
+                    font_size: font_size * POP_SCALE,
+                    color,
+                    ..Default::default()
+                },
+            },
+            ..Default::default()
+        };
+
+        commands
+            .spawn(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: layout.position(lane),
+                    size: Size::new(Val::Px(POPUP_WIDTH), Val::Px(POPUP_HEIGHT)),
+                    // Column children get laid out from the bottom up
+                    flex_direction: FlexDirection::ColumnReverse,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..Default::default()
+                },
+                material: materials.none.clone(),
+                ..Default::default()
+            })
+            .with(Popup {
+                lane,
+                spawned: time.seconds_since_startup(),
+            })
+            .with_children(|parent| {
+                parent
+                    .spawn(text(
+                        grade.name().to_string(),
+                        JUDGEMENT_FONT_SIZE,
+                        grade.color(),
+                    ))
+                    .with(PopupText {
+                        font_size: JUDGEMENT_FONT_SIZE,
+                    });
+                if let Some(timing) = timing {
+                    parent
+                        .spawn(text(timing, TIMING_FONT_SIZE, Color::rgb(0.9, 0.9, 0.9)))
+                        .with(PopupText {
+                            font_size: TIMING_FONT_SIZE,
+                        });
+                }
+            });
+    }
+}
+
+/// Shrinks popups down to size as they appear, fades them out, and removes
+/// them once they're done.
+fn fade_popups(
+    commands: &mut Commands,
+    time: Res<Time>,
+    popups: Query<(Entity, &Popup, &Children)>,
+    mut texts: Query<(&mut Text, &PopupText)>,
+) {
+    for (entity, popup, children) in popups.iter() {
+        let age = time.seconds_since_startup() - popup.spawned;
+        if age >= POPUP_LIFETIME {
+            commands.despawn_recursive(entity);
+            continue;
+        }
+
+        let pop = 1.0 + (POP_SCALE - 1.0) * (1.0 - (age / POP_LENGTH).min(1.0) as f32);
+        // Stays solid for the first half, then fades out
+        let alpha = (2.0 - 2.0 * age / POPUP_LIFETIME).min(1.0) as f32;
+
+        for child in children.iter() {
+            if let Ok((mut text, popup_text)) = texts.get_mut(*child) {
+                text.style.font_size = popup_text.font_size * pop;
+                text.style.color.set_a(alpha);
+            }
+        }
+    }
+}
+
+/// Removes any popups left over when leaving a game.
+fn despawn_popups(commands: &mut Commands, query: Query<Entity, With<Popup>>) {
+    for entity in query.iter() {
+        commands.despawn_recursive(entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grade_boundaries() {
+        assert_eq!(Grade::from_offset(0.0), Grade::Perfect);
+        assert_eq!(Grade::from_offset(-PERFECT_WINDOW), Grade::Perfect);
+        assert_eq!(Grade::from_offset(PERFECT_WINDOW + 0.001), Grade::Great);
+        assert_eq!(Grade::from_offset(GREAT_WINDOW), Grade::Great);
+        assert_eq!(Grade::from_offset(-GREAT_WINDOW - 0.001), Grade::Good);
+        assert_eq!(Grade::from_offset(GOOD_WINDOW), Grade::Good);
+        assert_eq!(Grade::from_offset(GOOD_WINDOW + 0.001), Grade::Bad);
+        assert_eq!(Grade::from_offset(-HIT_WINDOW), Grade::Bad);
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(TimingDisplay::Off.label(Grade::Good, -0.05), None);
+        assert_eq!(
+            TimingDisplay::FastSlow.label(Grade::Good, -0.05),
+            Some("FAST".to_string())
+        );
+        assert_eq!(
+            TimingDisplay::Milliseconds.label(Grade::Great, 0.023),
+            Some("SLOW +23ms".to_string())
+        );
+
+        // Perfect hits are never early or late
+        assert_eq!(TimingDisplay::FastSlow.label(Grade::Perfect, 0.005), None);
+        assert_eq!(
+            TimingDisplay::Milliseconds.label(Grade::Perfect, -0.004),
+            Some("-4ms".to_string())
+        );
+    }
+}
@@ -0,0 +1,368 @@
+use crate::{
+    arrows::{Arrow, HoldTail},
+    audio::SongPlayer,
+    chart::PlayingChart,
+    consts::*,
+    life::{Life, LifeGauge},
+    playtest::Playtest,
+    score::ScoreResource,
+    time::ControlledTime,
+    timing::Timing,
+    types::SongConfig,
+};
+use bevy::prelude::*;
+
+/// Lets a hard part of a song be played over and over, without having to play
+/// the whole song each time.
+pub struct PracticePlugin;
+
+impl Plugin for PracticePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<PracticeMode>()
+            .init_resource::<Practice>()
+            .on_state_enter(APP_STATE_STAGE, AppState::Game, reset_practice.system())
+            .on_state_enter(APP_STATE_STAGE, AppState::Game, setup_practice_ui.system())
+            .on_state_update(APP_STATE_STAGE, AppState::Game, set_loop_points.system())
+            .on_state_update(
+                APP_STATE_STAGE,
+                AppState::Game,
+                restart_loop.system().chain(record_loop.system()),
+            )
+            .on_state_update(APP_STATE_STAGE, AppState::Game, stop_practice.system())
+            .on_state_update(
+                APP_STATE_STAGE,
+                AppState::Game,
+                update_practice_text.system(),
+            )
+            .on_state_exit(
+                APP_STATE_STAGE,
+                AppState::Game,
+                despawn_practice_ui.system(),
+            );
+    }
+}
+
+/// Keeps going for a bit after the loop end before going back around, so that
+/// arrows right at the end can still be hit, in seconds.
+const LOOP_END_DELAY: f64 = 0.5;
+
+/// How many of the last loops get their accuracy shown while practicing.
+const SHOWN_LOOPS: usize = 5;
+
+/// Plays songs in practice mode, picked in the menu. Practice runs don't count
+/// towards high scores.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PracticeMode(pub bool);
+
+/// The loop being practiced in the current game, and how the player's been
+/// doing on it.
+#[derive(Debug, Default)]
+pub struct Practice {
+    /// Whether the current game is a practice run. Playtests never are.
+    pub active: bool,
+    /// Where the loop starts and ends, in seconds into the song.
+    pub start: Option<f64>,
+    pub end: Option<f64>,
+    /// Accuracy of each loop that's been played all the way through, from 0
+    /// to 1.
+    pub loops: Vec<f64>,
+    /// Set once the player's done practicing, to end the song.
+    pub stopped: bool,
+    /// Hits and misses when the current loop started. Playing up to the loop
+    /// the first time around doesn't count as a loop.
+    loop_start: Option<(usize, usize)>,
+}
+
+impl Practice {
+    /// The start and end of the loop, if both have been set the right way
+    /// around.
+    pub fn bounds(&self) -> Option<(f64, f64)> {
+        match (self.start, self.end) {
+            (Some(start), Some(end)) if end > start => Some((start, end)),
+            _ => None,
+        }
+    }
+
+    /// How many of the arrows in the current loop were hit, from 0 to 1. `None`
+    /// until an arrow's been hit or missed.
+    fn loop_accuracy(&self, score: &ScoreResource) -> Option<f64> {
+        let (start_hits, start_misses) = self.loop_start?;
+        let hits = score.corrects() - start_hits;
+        let misses = score.fails() - start_misses;
+        if hits + misses == 0 {
+            None
+        } else {
+            Some(hits as f64 / (hits + misses) as f64)
+        }
+    }
+
+    /// Describes the loop and how the last few went.
+    fn status(&self, score: &ScoreResource) -> String {
+        let mut status = match (self.bounds(), self.start, self.end) {
+            (Some((start, end)), _, _) => format!("Loop: {:.2}s - {:.2}s", start, end),
+            (None, Some(_), Some(_)) => "Loop end has to be after its start".to_string(),
+            (None, Some(start), None) => format!("Loop from {:.2}s, press ] to set its end", start),
+            (None, None, Some(end)) => format!("Loop to {:.2}s, press [ to set its start", end),
+            (None, None, None) => "No loop set".to_string(),
+        };
+
+        if let Some(accuracy) = self.loop_accuracy(score) {
+            status += &format!("  |  This loop: {:.1}%", accuracy * 100.0);
+        }
+        if !self.loops.is_empty() {
+            let last = self.loops[self.loops.len().saturating_sub(SHOWN_LOOPS)..]
+                .iter()
+                .map(|accuracy| format!("{:.1}%", accuracy * 100.0))
+                .collect::<Vec<_>>();
+            status += &format!("  |  Last: {}", last.join(", "));
+        }
+
+        status
+    }
+}
+
+/// Rounds a time to the nearest beat, if the chart has a tempo.
+fn snap_to_beat(secs: f64, timing: Option<&Timing>) -> f64 {
+    match timing {
+        Some(timing) => timing.beat_to_time(timing.time_to_beat(secs).round()),
+        None => secs,
+    }
+}
+
+/// Clears the last game's loop. Only games started from the menu can be
+/// practice runs.
+fn reset_practice(
+    mut practice: ResMut<Practice>,
+    mode: Res<PracticeMode>,
+    playtest: Res<Playtest>,
+) {
+    *practice = Practice {
+        active: mode.0 && playtest.from.is_none(),
+        ..Default::default()
+    };
+}
+
+/// Sets the loop start and end to the current beat with [ and ], or to the
+/// exact time while holding Shift. Backspace clears the loop.
+fn set_loop_points(
+    keyboard_input: Res<Input<KeyCode>>,
+    time: Res<ControlledTime>,
+    song_config: Res<SongConfig>,
+    mut practice: ResMut<Practice>,
+) {
+    if !practice.active {
+        return;
+    }
+
+    let secs = time.seconds_since_startup() - SONG_START_DELAY;
+    let exact = keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift);
+    let at = if exact {
+        secs
+    } else {
+        snap_to_beat(secs, song_config.timing.as_ref())
+    }
+    .max(0.0);
+
+    if keyboard_input.just_pressed(KeyCode::LBracket) {
+        practice.start = Some(at);
+        info!("Loop start set to {:.2}s", at);
+    }
+    if keyboard_input.just_pressed(KeyCode::RBracket) {
+        practice.end = Some(at);
+        info!("Loop end set to {:.2}s", at);
+    }
+    if keyboard_input.just_pressed(KeyCode::Back) {
+        practice.start = None;
+        practice.end = None;
+        practice.loops.clear();
+        practice.loop_start = None;
+    }
+}
+
+/// Goes back to the start of the loop once its end is reached. The arrows in
+/// the loop get put back, and the song picks up again from there. Returns true
+/// if it went back.
+fn restart_loop(
+    commands: &mut Commands,
+    mut time: ResMut<ControlledTime>,
+    mut player: ResMut<SongPlayer>,
+    practice: Res<Practice>,
+    mut chart: PlayingChart,
+    arrows: Query<Entity, With<Arrow>>,
+    tails: Query<Entity, With<HoldTail>>,
+) -> bool {
+    if !practice.active {
+        return false;
+    }
+    let (start, end) = match practice.bounds() {
+        Some(bounds) => bounds,
+        None => return false,
+    };
+    let secs = time.seconds_since_startup() - SONG_START_DELAY;
+    if secs < end + LOOP_END_DELAY {
+        return false;
+    }
+
+    // The modifiers still have the same seed, so the arrows end up in the same
+    // lanes as last time around.
+    if !chart.reset() {
+        return false;
+    }
+    chart
+        .song_config
+        .arrows
+        .retain(|arrow| arrow.click_time >= start && arrow.click_time <= end);
+
+    for entity in arrows.iter().chain(tails.iter()) {
+        commands.despawn(entity);
+    }
+
+    time.set_seconds_since_startup(SONG_START_DELAY + start - PRACTICE_LEAD_IN);
+    // `start_song` starts it up again from the new time.
+    player.stop();
+    true
+}
+
+/// Records how the loop went each time it's played through, and fills the
+/// player's life back up for the next time around.
+fn record_loop(
+    In(restarted): In<bool>,
+    mut practice: ResMut<Practice>,
+    score: Res<ScoreResource>,
+    mut life: ResMut<Life>,
+    gauge: Res<LifeGauge>,
+) {
+    if !restarted {
+        return;
+    }
+
+    if let Some(accuracy) = practice.loop_accuracy(&score) {
+        practice.loops.push(accuracy);
+    }
+    practice.loop_start = Some((score.corrects(), score.fails()));
+    *life = Life::new(gauge.curve());
+}
+
+/// Ends a practice run when End is pressed.
+fn stop_practice(keyboard_input: Res<Input<KeyCode>>, mut practice: ResMut<Practice>) {
+    if practice.active && keyboard_input.just_pressed(KeyCode::End) {
+        practice.stopped = true;
+    }
+}
+
+/// A marker for the practice mode UI.
+struct PracticeUI;
+
+/// Shows the loop and how it's going.
+struct PracticeText;
+
+fn setup_practice_ui(
+    commands: &mut Commands,
+    practice: Res<Practice>,
+    asset_server: Res<AssetServer>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+) {
+    if !practice.active {
+        return;
+    }
+
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    let text = |value: &str, font_size| TextBundle {
+        text: Text {
+            value: value.to_string(),
+            font: font.clone(),
+            style: TextStyle {
+                font_size,
+                color: Color::rgb(0.9, 0.9, 0.9),
+                ..Default::default()
+            },
+        },
+        ..Default::default()
+    };
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.0),
+                    top: Val::Px(60.0),
+                    ..Default::default()
+                },
+                // Column children get laid out from the bottom up
+                flex_direction: FlexDirection::ColumnReverse,
+                ..Default::default()
+            },
+            material: color_materials.add(Color::NONE.into()),
+            ..Default::default()
+        })
+        .with(PracticeUI)
+        .with_children(|parent| {
+            parent.spawn(text("", 20.0)).with(PracticeText);
+            parent.spawn(text(
+                "[ / ]: loop start / end (Shift: exact time)  |  Backspace: clear  |  End: finish",
+                14.0,
+            ));
+        });
+}
+
+fn update_practice_text(
+    practice: Res<Practice>,
+    score: Res<ScoreResource>,
+    mut query: Query<&mut Text, With<PracticeText>>,
+) {
+    for mut text in query.iter_mut() {
+        text.value = practice.status(&score);
+    }
+}
+
+/// Removes the practice mode UI when leaving a game.
+fn despawn_practice_ui(commands: &mut Commands, query: Query<Entity, With<PracticeUI>>) {
+    for entity in query.iter() {
+        commands.despawn_recursive(entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::score::Judgement;
+
+    #[test]
+    fn loops_need_both_ends_the_right_way_around() {
+        let practice = |start, end| Practice {
+            start,
+            end,
+            ..Default::default()
+        };
+        assert_eq!(practice(Some(1.0), Some(2.0)).bounds(), Some((1.0, 2.0)));
+        assert_eq!(practice(Some(2.0), Some(1.0)).bounds(), None);
+        assert_eq!(practice(Some(1.0), None).bounds(), None);
+        assert_eq!(practice(None, Some(2.0)).bounds(), None);
+    }
+
+    #[test]
+    fn loop_points_snap_to_beats() {
+        let timing = Timing::constant(120.0, 0.1);
+        assert!((snap_to_beat(1.3, Some(&timing)) - 1.1).abs() < 1e-9);
+        assert!((snap_to_beat(1.4, Some(&timing)) - 1.6).abs() < 1e-9);
+        // Charts without a tempo don't get snapped
+        assert_eq!(snap_to_beat(1.3, None), 1.3);
+    }
+
+    #[test]
+    fn loop_accuracy_only_counts_the_current_loop() {
+        let mut score = ScoreResource::default();
+        score.increase_fails(0.0, 0, Judgement::Miss);
+        let mut practice = Practice::default();
+        assert_eq!(practice.loop_accuracy(&score), None);
+
+        practice.loop_start = Some((score.corrects(), score.fails()));
+        assert_eq!(practice.loop_accuracy(&score), None);
+        score.increase_correct(0.0, 0, 0.0);
+        score.increase_correct(0.0, 0, 0.0);
+        score.increase_correct(0.0, 0, 0.0);
+        score.increase_fails(0.0, 0, Judgement::Miss);
+        assert_eq!(practice.loop_accuracy(&score), Some(0.75));
+    }
+}
@@ -0,0 +1,376 @@
+use crate::{
+    consts::*,
+    life::{Life, NoFail},
+    modifiers::Modifiers,
+    playtest::Playtest,
+    practice::Practice,
+    score::{HighScore, HighScores, ScoreResource},
+    settings::Settings,
+    time::{ControlledTime, PlaybackRate},
+    types::SongConfig,
+};
+use bevy::{ecs::SystemParam, prelude::*};
+
+/// Ends songs once all their arrows are done or the player's life runs out,
+/// and shows how the run went.
+pub struct ResultsPlugin;
+
+impl Plugin for ResultsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(HighScores::load())
+            .init_resource::<RunResult>()
+            .on_state_update(APP_STATE_STAGE, AppState::Game, finish_song.system())
+            .on_state_enter(APP_STATE_STAGE, AppState::Results, setup_results.system())
+            .on_state_update(APP_STATE_STAGE, AppState::Results, leave_results.system())
+            .on_state_exit(APP_STATE_STAGE, AppState::Results, despawn_results.system());
+    }
+}
+
+/// How the last run went.
+#[derive(Debug, Default)]
+pub struct RunResult {
+    pub song: String,
+    /// File the chart was loaded from, relative to `assets/songs`. Playtests
+    /// don't have one.
+    pub file: Option<String>,
+    pub difficulty: String,
+    pub score: ScoreResource,
+    pub rate: PlaybackRate,
+    pub modifiers: Modifiers,
+    /// Playtests don't count towards high scores.
+    pub playtest: bool,
+    /// Neither do practice runs.
+    pub practice: bool,
+    /// Accuracy of each loop played in a practice run, from 0 to 1.
+    pub loops: Vec<f64>,
+    pub new_best: bool,
+    /// When life ran out, in seconds into the song, if it did.
+    pub failed_at: Option<f64>,
+    /// Whether the song kept going after life ran out.
+    pub no_fail: bool,
+}
+
+/// How the game being played is going.
+#[derive(SystemParam)]
+pub struct Run<'a> {
+    score: Res<'a, ScoreResource>,
+    life: Res<'a, Life>,
+    playtest: Res<'a, Playtest>,
+    practice: Res<'a, Practice>,
+}
+
+/// Switches to the results screen once every arrow has been dealt with, or
+/// straight away if the player runs out of life without no fail on or stops
+/// practicing. Records the run as a high score if it's the best one yet and
+/// wasn't failed. Practice runs can't fail.
+fn finish_song(
+    time: Res<ControlledTime>,
+    song_config: Res<SongConfig>,
+    run: Run,
+    settings: Settings,
+    mut high_scores: ResMut<HighScores>,
+    mut result: ResMut<RunResult>,
+    mut state: ResMut<State<AppState>>,
+) {
+    let Run {
+        score,
+        life,
+        playtest,
+        practice,
+    } = run;
+    let Settings {
+        rate,
+        no_fail,
+        modifiers,
+        ..
+    } = settings;
+    let secs = time.seconds_since_startup() - SONG_START_DELAY;
+    let no_fail = NoFail(no_fail.0 || practice.active);
+    let failed = life.has_failed(no_fail);
+    if secs < song_config.end_time + SONG_END_DELAY && !failed && !practice.stopped {
+        return;
+    }
+
+    let playtest = playtest.from.is_some();
+    let new_best = !playtest
+        && !practice.active
+        && !failed
+        && song_config.file.as_ref().is_some_and(|file| {
+            high_scores.submit(
+                file,
+                &song_config.difficulty,
+                HighScore {
+                    score: score.score(),
+                    corrects: score.corrects(),
+                    fails: score.fails(),
+                    rate: rate.0,
+                    failed: life.failed_at().is_some(),
+                    modifiers: *modifiers,
+                },
+            )
+        });
+
+    if new_best {
+        high_scores.save();
+    }
+
+    *result = RunResult {
+        song: song_config.name.clone(),
+        file: song_config.file.clone(),
+        difficulty: song_config.difficulty.clone(),
+        score: score.clone(),
+        rate: *rate,
+        modifiers: *modifiers,
+        playtest,
+        practice: practice.active,
+        loops: practice.loops.clone(),
+        new_best,
+        failed_at: life.failed_at(),
+        no_fail: no_fail.0,
+    };
+
+    state
+        .set_next(AppState::Results)
+        .expect("Couldn't switch state to Results");
+}
+
+/// A marker for the results screen's UI.
+struct ResultsUI;
+
+/// How many bars the timing histogram is split into. Odd, so that the middle
+/// one is centered on hits that were right on time.
+const HISTOGRAM_BUCKETS: usize = 21;
+
+/// Size of the timing histogram, in pixels.
+const HISTOGRAM_WIDTH: f32 = 420.0;
+const HISTOGRAM_HEIGHT: f32 = 70.0;
+
+/// Shows how the last run went.
+fn setup_results(
+    commands: &mut Commands,
+    result: Res<RunResult>,
+    high_scores: Res<HighScores>,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+
+    let mut lines = vec![
+        (result.song.clone(), 50.0),
+        (result.difficulty.clone(), 30.0),
+        (
+            format!(
+                "Score: {}  |  Hits: {}  |  Misses: {}",
+                result.score.score(),
+                result.score.corrects(),
+                result.score.fails(),
+            ),
+            30.0,
+        ),
+    ];
+
+    if result.score.mines_hit() > 0 {
+        lines.push((format!("Mines hit: {}", result.score.mines_hit()), 25.0));
+    }
+
+    if let Some(failed_at) = result.failed_at {
+        let line = if result.no_fail {
+            format!("Would have failed at {:.1}s (no fail)", failed_at)
+        } else {
+            format!("Failed at {:.1}s", failed_at)
+        };
+        lines.push((line, 30.0));
+    }
+
+    if result.rate.is_slowed() {
+        lines.push((format!("Slowed: played at {}x", result.rate.0), 25.0));
+    } else if (result.rate.0 - 1.0).abs() > f64::EPSILON {
+        lines.push((format!("Played at {}x", result.rate.0), 25.0));
+    }
+
+    let modifiers = result.modifiers.label();
+    if !modifiers.is_empty() {
+        lines.push((format!("Modifiers: {}", modifiers), 25.0));
+    }
+
+    if let (Some(mean), Some(std_dev)) = (result.score.mean_offset(), result.score.offset_std_dev())
+    {
+        let (early, late) = result.score.early_late();
+        lines.push((
+            format!(
+                "Timing: {:+.1}ms on average, give or take {:.1}ms  |  Early: {}  |  Late: {}",
+                mean * 1000.0,
+                std_dev * 1000.0,
+                early,
+                late
+            ),
+            20.0,
+        ));
+    }
+
+    if !result.loops.is_empty() {
+        let best = result.loops.iter().copied().fold(0.0, f64::max);
+        let last = result.loops[result.loops.len() - 1];
+        lines.push((
+            format!(
+                "Loops: {}  |  Best: {:.1}%  |  Last: {:.1}%",
+                result.loops.len(),
+                best * 100.0,
+                last * 100.0
+            ),
+            25.0,
+        ));
+    }
+
+    if result.playtest {
+        lines.push(("Playtest: not saved".to_string(), 25.0));
+    } else if result.practice {
+        lines.push(("Practice: not saved".to_string(), 25.0));
+    } else if result.failed_at.is_some() && !result.no_fail {
+        lines.push(("Failed runs aren't saved".to_string(), 25.0));
+    } else if result.new_best {
+        lines.push(("New best!".to_string(), 25.0));
+    } else if let Some(best) = result
+        .file
+        .as_ref()
+        .and_then(|file| high_scores.get(file, &result.difficulty))
+    {
+        lines.push((
+            format!(
+                "Best: {}{}{}",
+                best.score,
+                if best.is_slowed() {
+                    format!(" (slowed, {}x)", best.rate)
+                } else {
+                    String::new()
+                },
+                if best.failed {
+                    " (failed, no fail)"
+                } else {
+                    ""
+                }
+            ),
+            25.0,
+        ));
+    }
+
+    let histogram = result
+        .score
+        .mean_offset()
+        .map(|_| result.score.offset_histogram(HISTOGRAM_BUCKETS));
+    let background = materials.add(Color::rgba(0.2, 0.2, 0.2, 0.8).into());
+    let early = materials.add(Color::rgb(0.3, 0.5, 0.9).into());
+    let on_time = materials.add(Color::rgb(0.2, 0.8, 0.3).into());
+    let late = materials.add(Color::rgb(0.9, 0.6, 0.2).into());
+
+    let text = |value: String, font_size| TextBundle {
+        style: Style {
+            margin: Rect::all(Val::Px(8.0)),
+            ..Default::default()
+        },
+        text: Text {
+            value,
+            font: font.clone(),
+            style: TextStyle {
+                font_size,
+                color: Color::rgb(0.9, 0.9, 0.9),
+                ..Default::default()
+            },
+        },
+        ..Default::default()
+    };
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                display: Display::Flex,
+                // Column children get laid out from the bottom up
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+            },
+            material: materials.add(Color::NONE.into()),
+            ..Default::default()
+        })
+        .with(ResultsUI)
+        .with_children(|parent| {
+            for (value, font_size) in lines {
+                parent.spawn(text(value, font_size));
+            }
+
+            // How many hits landed how early or late, early on the left.
+            if let Some((counts, range)) = histogram {
+                let most = counts.iter().copied().max().unwrap_or(1).max(1);
+                parent
+                    .spawn(NodeBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(HISTOGRAM_WIDTH), Val::Px(HISTOGRAM_HEIGHT)),
+                            margin: Rect::all(Val::Px(8.0)),
+                            flex_direction: FlexDirection::Row,
+                            // Which is the bottom, so bars stand up from it
+                            align_items: AlignItems::FlexStart,
+                            ..Default::default()
+                        },
+                        material: background,
+                        ..Default::default()
+                    })
+                    .with_children(|parent| {
+                        let middle = counts.len() / 2;
+                        for (i, count) in counts.iter().enumerate() {
+                            let material = match i {
+                                i if i < middle => early.clone(),
+                                i if i > middle => late.clone(),
+                                _ => on_time.clone(),
+                            };
+                            parent.spawn(NodeBundle {
+                                style: Style {
+                                    size: Size::new(
+                                        Val::Percent(100.0 / counts.len() as f32),
+                                        Val::Percent(*count as f32 / most as f32 * 100.0),
+                                    ),
+                                    ..Default::default()
+                                },
+                                material,
+                                ..Default::default()
+                            });
+                        }
+                    });
+                parent.spawn(text(
+                    format!(
+                        "Early ({:.0}ms)  <  On time  >  Late ({:.0}ms)",
+                        range * 1000.0,
+                        range * 1000.0
+                    ),
+                    16.0,
+                ));
+            }
+
+            parent.spawn(text("Press Enter to continue".to_string(), 20.0));
+        });
+}
+
+/// Goes back to the menu, or wherever a playtest was started from, when Enter
+/// is pressed.
+fn leave_results(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut playtest: ResMut<Playtest>,
+    mut state: ResMut<State<AppState>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::Return) {
+        return;
+    }
+
+    let next = playtest.from.take().unwrap_or(AppState::Menu);
+    state
+        .set_next(next)
+        .expect("Couldn't switch state away from Results");
+}
+
+/// Removes the results screen.
+fn despawn_results(commands: &mut Commands, query: Query<Entity, With<ResultsUI>>) {
+    for entity in query.iter() {
+        commands.despawn_recursive(entity);
+    }
+}
@@ -1,29 +1,112 @@
-use crate::consts::*;
+use crate::{consts::*, lanes::Lane, modifiers::Modifiers, types::DEFAULT_DIFFICULTY};
+use bevy::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::HashMap, fs};
 
-#[derive(Default, Debug, Clone, Copy)]
+/// What happened to a note.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Judgement {
+    Hit,
+    Miss,
+    /// A hold that got hit, but let go of before it was done.
+    HoldDropped,
+    MineHit,
+    MineAvoided,
+}
+
+impl Judgement {
+    /// The name judgements get saved with, like "hold_dropped".
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Hit => "hit",
+            Self::Miss => "miss",
+            Self::HoldDropped => "hold_dropped",
+            Self::MineHit => "mine_hit",
+            Self::MineAvoided => "mine_avoided",
+        }
+    }
+}
+
+/// How a note went.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct NoteResult {
+    /// When the note should have been hit, in seconds into the song. Dropped
+    /// holds use when they were let go of instead.
+    pub time: f64,
+    pub lane: Lane,
+    pub judgement: Judgement,
+    /// How early (negative) or late (positive) a hit was, in seconds. Only
+    /// hits have one.
+    pub offset: Option<f64>,
+    /// Points given for the note. Mines that get hit take away points, but
+    /// still count as 0 here.
+    pub points: usize,
+}
+
+#[derive(Default, Debug, Clone)]
 pub struct ScoreResource {
     corrects: usize,
     fails: usize,
     score: usize,
+    mines_hit: usize,
+    /// Every note that's been dealt with, in the order they were.
+    notes: Vec<NoteResult>,
 }
 
 impl ScoreResource {
-    /// Increases number of corrects and adds to score
-    pub fn increase_correct(&mut self, distance: f32) -> usize {
+    /// Increases number of corrects and adds to score. `offset` is how early
+    /// or late the arrow was hit, in seconds, which is what points are given
+    /// for.
+    pub fn increase_correct(&mut self, time: f64, lane: Lane, offset: f64) -> usize {
         self.corrects += 1;
 
         // Get a value from 0 to 1 according to how close the press was
-        let score_multiplier = (THRESHOLD - distance.abs()) / THRESHOLD;
+        let score_multiplier = (HIT_WINDOW - offset.abs()) / HIT_WINDOW;
         // Give at least 10 points and max 100 points
-        let points = (score_multiplier * 100.0).min(100.0).max(10.0) as usize;
+        let points = (score_multiplier * 100.0).clamp(10.0, 100.0) as usize;
         self.score += points;
 
+        self.notes.push(NoteResult {
+            time,
+            lane,
+            judgement: Judgement::Hit,
+            offset: Some(offset),
+            points,
+        });
         points
     }
 
-    /// Increases the number of failures.
-    pub fn increase_fails(&mut self) {
+    /// Increases the number of failures, for a note that was missed or
+    /// dropped.
+    pub fn increase_fails(&mut self, time: f64, lane: Lane, judgement: Judgement) {
+        self.fails += 1;
+        self.push_note(time, lane, judgement);
+    }
+
+    /// Counts a mine that got left alone as a correct, without any points.
+    pub fn avoid_mine(&mut self, time: f64, lane: Lane) {
+        self.corrects += 1;
+        self.push_note(time, lane, Judgement::MineAvoided);
+    }
+
+    /// Counts a mine that got hit as a failure, and takes away some points.
+    pub fn hit_mine(&mut self, time: f64, lane: Lane) {
         self.fails += 1;
+        self.mines_hit += 1;
+        self.score = self.score.saturating_sub(MINE_PENALTY);
+        self.push_note(time, lane, Judgement::MineHit);
+    }
+
+    /// Records a note that didn't get any points.
+    fn push_note(&mut self, time: f64, lane: Lane, judgement: Judgement) {
+        self.notes.push(NoteResult {
+            time,
+            lane,
+            judgement,
+            offset: None,
+            points: 0,
+        });
     }
 
     /// Get the score
@@ -40,4 +123,309 @@ impl ScoreResource {
     pub fn fails(&self) -> usize {
         self.fails
     }
+
+    /// Get the number of mines that got hit
+    pub fn mines_hit(&self) -> usize {
+        self.mines_hit
+    }
+
+    /// Get every note that's been dealt with
+    pub fn notes(&self) -> &[NoteResult] {
+        &self.notes
+    }
+
+    /// How early or late each hit was, in seconds.
+    fn offsets(&self) -> impl Iterator<Item = f64> + '_ {
+        self.notes.iter().filter_map(|note| note.offset)
+    }
+
+    /// How early or late hits were on average, in seconds. Negative is early.
+    pub fn mean_offset(&self) -> Option<f64> {
+        let count = self.offsets().count();
+        if count == 0 {
+            return None;
+        }
+        Some(self.offsets().sum::<f64>() / count as f64)
+    }
+
+    /// How spread out the hits were around their mean offset, in seconds.
+    pub fn offset_std_dev(&self) -> Option<f64> {
+        let mean = self.mean_offset()?;
+        let variance = self
+            .offsets()
+            .map(|offset| (offset - mean).powi(2))
+            .sum::<f64>()
+            / self.offsets().count() as f64;
+        Some(variance.sqrt())
+    }
+
+    /// How many hits were early, and how many were late.
+    pub fn early_late(&self) -> (usize, usize) {
+        let early = self.offsets().filter(|offset| *offset < 0.0).count();
+        let late = self.offsets().filter(|offset| *offset > 0.0).count();
+        (early, late)
+    }
+
+    /// Sorts hits into evenly sized buckets by offset, from the earliest hit to
+    /// the latest, with 0 in the middle. Also returns how far either side of 0
+    /// the buckets go, in seconds.
+    pub fn offset_histogram(&self, buckets: usize) -> (Vec<usize>, f64) {
+        let mut counts = vec![0; buckets];
+        let range = self.offsets().map(f64::abs).fold(0.0, f64::max);
+
+        for offset in self.offsets() {
+            let bucket = if range > 0.0 {
+                ((offset + range) / (2.0 * range) * buckets as f64) as usize
+            } else {
+                buckets / 2
+            };
+            counts[bucket.min(buckets - 1)] += 1;
+        }
+
+        (counts, range)
+    }
+}
+
+/// Points lost for hitting a mine.
+const MINE_PENALTY: usize = 50;
+
+/// Resets the score. To be used when entering a game.
+pub fn reset_score(mut score: ResMut<ScoreResource>) {
+    *score = ScoreResource::default();
+}
+
+/// The best run on a song.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct HighScore {
+    pub score: usize,
+    pub corrects: usize,
+    pub fails: usize,
+    /// Playback rate the run was played at.
+    pub rate: f64,
+    /// Whether life ran out during the run. Only runs with no fail on get
+    /// saved after running out.
+    #[serde(default)]
+    pub failed: bool,
+    #[serde(default)]
+    pub modifiers: Modifiers,
+}
+
+impl HighScore {
+    /// Returns true if the run was played slower than normal.
+    pub fn is_slowed(&self) -> bool {
+        self.rate < 1.0
+    }
+}
+
+/// File that high scores get saved to.
+const HIGH_SCORES_FILE: &str = "scores.toml";
+
+/// The best runs on every chart, keyed by the chart's file (relative to
+/// `assets/songs`) and then difficulty name.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct HighScores {
+    #[serde(default)]
+    charts: HashMap<String, HashMap<String, HighScore>>,
+    /// Scores saved before charts could have more than one difficulty, keyed
+    /// by song name. They get moved into `charts` once a chart with that name
+    /// turns up, see [`HighScores::migrate`].
+    #[serde(default, rename = "scores", skip_serializing_if = "HashMap::is_empty")]
+    legacy: HashMap<String, HighScore>,
+}
+
+impl HighScores {
+    /// Loads high scores from disk, starting fresh if there aren't any yet.
+    pub fn load() -> Self {
+        fs::read_to_string(HIGH_SCORES_FILE)
+            .ok()
+            .and_then(|contents| match toml::from_str(&contents) {
+                Ok(scores) => Some(scores),
+                Err(err) => {
+                    warn!("Couldn't parse {}: {}", HIGH_SCORES_FILE, err);
+                    None
+                }
+            })
+            .unwrap_or_default()
+    }
+
+    /// Writes high scores to disk.
+    pub fn save(&self) {
+        let result = toml::to_string(self)
+            .map_err(|err| err.to_string())
+            .and_then(|text| fs::write(HIGH_SCORES_FILE, text).map_err(|err| err.to_string()));
+
+        if let Err(err) = result {
+            error!("Couldn't save high scores: {}", err);
+        }
+    }
+
+    /// Moves an old score for the song called `name` over to the chart in
+    /// `file`. It goes to the difficulty charts from back then get loaded
+    /// with, since they only had the one. Returns true if there was one.
+    pub fn migrate(&mut self, file: &str, name: &str) -> bool {
+        match self.legacy.remove(name) {
+            Some(run) => {
+                self.submit(file, DEFAULT_DIFFICULTY, run);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Gets the best run on one of a chart's difficulties.
+    pub fn get(&self, file: &str, difficulty: &str) -> Option<&HighScore> {
+        self.charts.get(file)?.get(difficulty)
+    }
+
+    /// Records a run, returning true if it's the new best on its chart and
+    /// difficulty. Slowed runs never take the place of ones at full speed,
+    /// however high they score.
+    pub fn submit(&mut self, file: &str, difficulty: &str, run: HighScore) -> bool {
+        let is_best = match self.get(file, difficulty) {
+            None => true,
+            Some(best) => match (run.is_slowed(), best.is_slowed()) {
+                (true, false) => false,
+                (false, true) => true,
+                _ => run.score > best.score,
+            },
+        };
+
+        if is_best {
+            self.charts
+                .entry(file.to_string())
+                .or_default()
+                .insert(difficulty.to_string(), run);
+        }
+
+        is_best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(score: usize, rate: f64) -> HighScore {
+        HighScore {
+            score,
+            corrects: 0,
+            fails: 0,
+            rate,
+            failed: false,
+            modifiers: Modifiers::default(),
+        }
+    }
+
+    /// A run with hits this many seconds late, or early if negative, and a
+    /// miss.
+    fn hits(offsets: &[f64]) -> ScoreResource {
+        let mut score = ScoreResource::default();
+        for (i, offset) in offsets.iter().enumerate() {
+            score.increase_correct(i as f64, i % 4, *offset);
+        }
+        score.increase_fails(10.0, 0, Judgement::Miss);
+        score
+    }
+
+    #[test]
+    fn points_by_offset() {
+        let mut score = ScoreResource::default();
+        assert_eq!(score.increase_correct(0.0, 0, 0.0), 100);
+        assert_eq!(score.increase_correct(1.0, 0, -0.05), 50);
+        assert_eq!(score.increase_correct(2.0, 0, 0.05), 50);
+        // Hits at the edge of the window still get something
+        assert_eq!(score.increase_correct(3.0, 0, HIT_WINDOW), 10);
+        assert_eq!(score.score(), 210);
+    }
+
+    #[test]
+    fn mines_take_points_away() {
+        let mut score = ScoreResource::default();
+        score.increase_correct(0.0, 0, 0.0);
+        score.avoid_mine(1.0, 1);
+        assert_eq!(
+            (score.score(), score.corrects(), score.fails()),
+            (100, 2, 0)
+        );
+
+        score.hit_mine(2.0, 2);
+        assert_eq!(score.score(), 100 - MINE_PENALTY);
+        assert_eq!((score.mines_hit(), score.fails()), (1, 1));
+        // Scores don't go below zero
+        score.hit_mine(3.0, 3);
+        score.hit_mine(4.0, 0);
+        assert_eq!(score.score(), 0);
+        assert_eq!(score.notes().last().unwrap().judgement, Judgement::MineHit);
+    }
+
+    #[test]
+    fn offset_stats_need_hits() {
+        let score = hits(&[]);
+        assert_eq!(score.mean_offset(), None);
+        assert_eq!(score.offset_std_dev(), None);
+        assert_eq!(score.early_late(), (0, 0));
+    }
+
+    #[test]
+    fn offset_stats() {
+        let score = hits(&[-0.02, 0.0, 0.01, 0.03, 0.03]);
+        assert!((score.mean_offset().unwrap() - 0.01).abs() < 1e-9);
+        // Deviations of -0.03, -0.01, 0, 0.02 and 0.02
+        let std_dev = (0.0018f64 / 5.0).sqrt();
+        assert!((score.offset_std_dev().unwrap() - std_dev).abs() < 1e-9);
+        // Hits right on time are neither
+        assert_eq!(score.early_late(), (1, 3));
+    }
+
+    #[test]
+    fn slowed_runs_never_beat_full_speed() {
+        let mut high_scores = HighScores::default();
+        assert!(high_scores.submit("song.toml", "Hard", run(500, 1.0)));
+        assert!(!high_scores.submit("song.toml", "Hard", run(900, 0.75)));
+        assert_eq!(high_scores.get("song.toml", "Hard").unwrap().score, 500);
+
+        assert!(!high_scores.submit("song.toml", "Hard", run(400, 1.0)));
+        assert!(high_scores.submit("song.toml", "Hard", run(600, 1.25)));
+        assert_eq!(high_scores.get("song.toml", "Hard").unwrap().score, 600);
+    }
+
+    #[test]
+    fn old_scores_get_migrated() {
+        let mut high_scores: HighScores =
+            toml::from_str("[scores.Song]\nscore = 700\ncorrects = 7\nfails = 1\nrate = 1.0\n")
+                .unwrap();
+        // Old scores stay saved until their chart turns up
+        assert!(toml::to_string(&high_scores).unwrap().contains("[scores"));
+
+        assert!(!high_scores.migrate("other.toml", "Other"));
+        assert!(high_scores.migrate("pack/song.toml", "Song"));
+        assert_eq!(
+            high_scores
+                .get("pack/song.toml", DEFAULT_DIFFICULTY)
+                .unwrap()
+                .score,
+            700
+        );
+        assert!(high_scores.get("Song", DEFAULT_DIFFICULTY).is_none());
+
+        let saved = toml::to_string(&high_scores).unwrap();
+        assert!(!saved.contains("[scores"));
+        let reloaded: HighScores = toml::from_str(&saved).unwrap();
+        assert_eq!(
+            reloaded
+                .get("pack/song.toml", DEFAULT_DIFFICULTY)
+                .unwrap()
+                .score,
+            700
+        );
+    }
+
+    #[test]
+    fn full_speed_runs_replace_slowed_ones() {
+        let mut high_scores = HighScores::default();
+        assert!(high_scores.submit("song.toml", "Hard", run(900, 0.5)));
+        assert!(high_scores.submit("song.toml", "Hard", run(950, 0.75)));
+        assert!(high_scores.submit("song.toml", "Hard", run(100, 1.0)));
+        assert_eq!(high_scores.get("song.toml", "Hard").unwrap().score, 100);
+    }
 }
@@ -0,0 +1,441 @@
+use crate::{
+    consts::*,
+    life::{Life, LifeGauge},
+    modifiers::Modifiers,
+    playfield::{Orientation, ScrollSpeed},
+    results::RunResult,
+    score::NoteResult,
+    time::ControlledTime,
+    types::SongConfig,
+};
+use bevy::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    fmt::Write,
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Writes every finished run out to a file, so it can be looked at outside the
+/// game.
+pub struct SessionPlugin;
+
+impl Plugin for SessionPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<SessionExport>()
+            .init_resource::<LifeHistory>()
+            .on_state_enter(APP_STATE_STAGE, AppState::Game, reset_life_history.system())
+            .on_state_update(APP_STATE_STAGE, AppState::Game, record_life.system())
+            .on_state_enter(APP_STATE_STAGE, AppState::Results, export_session.system());
+    }
+}
+
+/// Version of the session file format. Only goes up when a field gets removed
+/// or changes meaning, so tools reading sessions can rely on it. New fields
+/// can show up without it changing.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Folder that sessions get written to.
+const SESSIONS_DIR: &str = "sessions";
+
+/// How often life gets recorded, in seconds of song time.
+const LIFE_SAMPLE_INTERVAL: f64 = 0.25;
+
+/// Which files finished runs get written to, picked by the player.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SessionExport {
+    Off,
+    #[default]
+    Json,
+    /// A JSON file, and CSV files for the notes and life.
+    JsonAndCsv,
+}
+
+impl SessionExport {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Off => "Off",
+            Self::Json => "JSON",
+            Self::JsonAndCsv => "JSON + CSV",
+        }
+    }
+
+    /// The option after this one, going back to the first after the last.
+    pub fn next(&self) -> Self {
+        match self {
+            Self::Off => Self::Json,
+            Self::Json => Self::JsonAndCsv,
+            Self::JsonAndCsv => Self::Off,
+        }
+    }
+}
+
+/// One finished run. This is what gets written to session files, see
+/// [`SCHEMA_VERSION`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Session {
+    pub schema_version: u32,
+    /// When the run finished, in seconds since the Unix epoch.
+    pub finished_at: u64,
+    pub chart: ChartInfo,
+    pub settings: Settings,
+    pub modifiers: Modifiers,
+    pub summary: Summary,
+    /// Every note that got hit, missed or dropped, in the order they were.
+    pub notes: Vec<NoteResult>,
+    /// Life over the course of the run.
+    pub life: Vec<LifeSample>,
+}
+
+/// Which chart got played.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChartInfo {
+    /// Path to the chart, relative to `assets/songs`. Missing for charts
+    /// played straight out of the editor.
+    pub file: Option<String>,
+    pub song: String,
+    pub difficulty: String,
+    pub lanes: usize,
+}
+
+/// Settings the run was played with.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Settings {
+    /// How fast the song played, as a multiple of its normal speed.
+    pub rate: f64,
+    /// Like "1.5x" for a multiplier, or "C400" for a constant speed.
+    pub scroll_speed: String,
+    pub orientation: String,
+    pub life_gauge: String,
+    pub no_fail: bool,
+    pub practice: bool,
+    pub playtest: bool,
+}
+
+/// How the run went overall.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Summary {
+    pub score: usize,
+    pub hits: usize,
+    pub misses: usize,
+    pub mines_hit: usize,
+    /// When life ran out, in seconds into the song, if it did.
+    pub failed_at: Option<f64>,
+    /// Average and spread of how early (negative) or late (positive) hits
+    /// were, in seconds. Missing if nothing got hit.
+    pub mean_offset: Option<f64>,
+    pub offset_std_dev: Option<f64>,
+    /// Accuracy of each loop in a practice run, from 0 to 1.
+    pub loops: Vec<f64>,
+}
+
+/// The player's life at some point in the run.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct LifeSample {
+    /// Seconds into the song.
+    pub time: f64,
+    /// From 0 to 1.
+    pub life: f32,
+}
+
+impl Session {
+    /// Notes as CSV, one per row.
+    fn notes_csv(&self) -> String {
+        let mut csv = "time,lane,judgement,offset,points\n".to_string();
+        for note in &self.notes {
+            let offset = note
+                .offset
+                .map_or_else(String::new, |offset| offset.to_string());
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{}",
+                note.time,
+                note.lane,
+                note.judgement.name(),
+                offset,
+                note.points
+            );
+        }
+        csv
+    }
+
+    /// Life over time as CSV, one sample per row.
+    fn life_csv(&self) -> String {
+        let mut csv = "time,life\n".to_string();
+        for sample in &self.life {
+            let _ = writeln!(csv, "{},{}", sample.time, sample.life);
+        }
+        csv
+    }
+
+    /// Writes the session to the sessions folder, named after when it finished
+    /// and what was played. Returns the JSON file's path.
+    fn save(&self, export: SessionExport) -> Result<String, String> {
+        fs::create_dir_all(SESSIONS_DIR).map_err(|err| err.to_string())?;
+
+        let name = format!(
+            "{}-{}-{}",
+            self.finished_at,
+            file_safe(&self.chart.song),
+            file_safe(&self.chart.difficulty)
+        );
+        let base = Path::new(SESSIONS_DIR).join(name);
+
+        let json = serde_json::to_string_pretty(self).map_err(|err| err.to_string())?;
+        let json_path = base.with_extension("json");
+        fs::write(&json_path, json).map_err(|err| err.to_string())?;
+
+        if export == SessionExport::JsonAndCsv {
+            fs::write(base.with_extension("notes.csv"), self.notes_csv())
+                .map_err(|err| err.to_string())?;
+            fs::write(base.with_extension("life.csv"), self.life_csv())
+                .map_err(|err| err.to_string())?;
+        }
+
+        Ok(json_path.display().to_string())
+    }
+}
+
+/// Swaps out anything that doesn't belong in a file name.
+fn file_safe(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Life recorded over the current game.
+#[derive(Debug, Default)]
+struct LifeHistory(Vec<LifeSample>);
+
+fn reset_life_history(mut history: ResMut<LifeHistory>) {
+    history.0.clear();
+}
+
+/// Records the player's life every so often. Practice loops go back in time,
+/// so a new sample gets taken straight away when that happens.
+fn record_life(time: Res<ControlledTime>, life: Res<Life>, mut history: ResMut<LifeHistory>) {
+    let secs = time.seconds_since_startup() - SONG_START_DELAY;
+    if secs < 0.0 {
+        return;
+    }
+
+    let due = history
+        .0
+        .last()
+        .is_none_or(|last| secs >= last.time + LIFE_SAMPLE_INTERVAL || secs < last.time);
+    if due {
+        history.0.push(LifeSample {
+            time: secs,
+            life: life.value(),
+        });
+    }
+}
+
+/// Writes out the run that just finished.
+fn export_session(
+    export: Res<SessionExport>,
+    result: Res<RunResult>,
+    song_config: Res<SongConfig>,
+    scroll: Res<ScrollSpeed>,
+    orientation: Res<Orientation>,
+    gauge: Res<LifeGauge>,
+    history: Res<LifeHistory>,
+) {
+    if *export == SessionExport::Off {
+        return;
+    }
+
+    let session = Session {
+        schema_version: SCHEMA_VERSION,
+        finished_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs()),
+        chart: ChartInfo {
+            file: song_config.file.clone(),
+            song: result.song.clone(),
+            difficulty: result.difficulty.clone(),
+            lanes: song_config.lanes.count(),
+        },
+        settings: Settings {
+            rate: result.rate.0,
+            scroll_speed: scroll.name(),
+            orientation: orientation.name().to_string(),
+            life_gauge: gauge.name().to_string(),
+            no_fail: result.no_fail,
+            practice: result.practice,
+            playtest: result.playtest,
+        },
+        modifiers: result.modifiers,
+        summary: Summary {
+            score: result.score.score(),
+            hits: result.score.corrects(),
+            misses: result.score.fails(),
+            mines_hit: result.score.mines_hit(),
+            failed_at: result.failed_at,
+            mean_offset: result.score.mean_offset(),
+            offset_std_dev: result.score.offset_std_dev(),
+            loops: result.loops.clone(),
+        },
+        notes: result.score.notes().to_vec(),
+        life: history.0.clone(),
+    };
+
+    match session.save(*export) {
+        Ok(path) => info!("Saved session to {}", path),
+        Err(err) => error!("Couldn't save session: {}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::score::Judgement;
+    use serde_json::Value;
+
+    fn session() -> Session {
+        Session {
+            schema_version: SCHEMA_VERSION,
+            finished_at: 1_600_000_000,
+            chart: ChartInfo {
+                file: Some("pack/song.toml".to_string()),
+                song: "Song".to_string(),
+                difficulty: "Hard".to_string(),
+                lanes: 4,
+            },
+            settings: Settings {
+                rate: 1.0,
+                scroll_speed: "1.5x".to_string(),
+                orientation: "Left to right".to_string(),
+                life_gauge: "Normal".to_string(),
+                no_fail: false,
+                practice: false,
+                playtest: false,
+            },
+            modifiers: Modifiers::default(),
+            summary: Summary {
+                score: 90,
+                hits: 1,
+                misses: 1,
+                mines_hit: 0,
+                failed_at: None,
+                mean_offset: Some(-0.012),
+                offset_std_dev: Some(0.0),
+                loops: Vec::new(),
+            },
+            notes: vec![
+                NoteResult {
+                    time: 1.5,
+                    lane: 2,
+                    judgement: Judgement::Hit,
+                    offset: Some(-0.012),
+                    points: 90,
+                },
+                NoteResult {
+                    time: 2.0,
+                    lane: 0,
+                    judgement: Judgement::HoldDropped,
+                    offset: None,
+                    points: 0,
+                },
+            ],
+            life: vec![
+                LifeSample {
+                    time: 0.0,
+                    life: 0.5,
+                },
+                LifeSample {
+                    time: 0.25,
+                    life: 0.55,
+                },
+            ],
+        }
+    }
+
+    /// The names of a JSON object's fields, in alphabetical order.
+    fn keys(value: &Value) -> Vec<&str> {
+        let mut keys = value
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys
+    }
+
+    #[test]
+    fn json_fields() {
+        let json = serde_json::to_value(session()).unwrap();
+
+        assert_eq!(
+            keys(&json),
+            [
+                "chart",
+                "finished_at",
+                "life",
+                "modifiers",
+                "notes",
+                "schema_version",
+                "settings",
+                "summary"
+            ]
+        );
+        assert_eq!(json["schema_version"], SCHEMA_VERSION);
+        assert_eq!(
+            keys(&json["chart"]),
+            ["difficulty", "file", "lanes", "song"]
+        );
+        assert_eq!(
+            keys(&json["settings"]),
+            [
+                "life_gauge",
+                "no_fail",
+                "orientation",
+                "playtest",
+                "practice",
+                "rate",
+                "scroll_speed"
+            ]
+        );
+        assert_eq!(
+            keys(&json["summary"]),
+            [
+                "failed_at",
+                "hits",
+                "loops",
+                "mean_offset",
+                "mines_hit",
+                "misses",
+                "offset_std_dev",
+                "score"
+            ]
+        );
+        assert_eq!(
+            keys(&json["notes"][0]),
+            ["judgement", "lane", "offset", "points", "time"]
+        );
+        assert_eq!(json["notes"][1]["judgement"], "hold_dropped");
+        assert_eq!(keys(&json["life"][0]), ["life", "time"]);
+    }
+
+    #[test]
+    fn csv_rows() {
+        let session = session();
+
+        let notes = session.notes_csv();
+        let notes = notes.lines().collect::<Vec<_>>();
+        assert_eq!(
+            notes,
+            [
+                "time,lane,judgement,offset,points",
+                "1.5,2,hit,-0.012,90",
+                "2,0,hold_dropped,,0"
+            ]
+        );
+
+        let life = session.life_csv();
+        let life = life.lines().collect::<Vec<_>>();
+        assert_eq!(life, ["time,life", "0,0.5", "0.25,0.55"]);
+    }
+}
@@ -0,0 +1,39 @@
+use crate::{
+    life::{LifeGauge, NoFail},
+    modifiers::Modifiers,
+    playfield::{Orientation, ScrollSpeed},
+    popups::TimingDisplay,
+    practice::PracticeMode,
+    session::SessionExport,
+    time::PlaybackRate,
+};
+use bevy::{ecs::SystemParam, prelude::*};
+
+/// Every setting that can be changed in the menu, for systems that need more
+/// than one or two of them.
+#[derive(SystemParam)]
+pub struct Settings<'a> {
+    pub rate: Res<'a, PlaybackRate>,
+    pub orientation: Res<'a, Orientation>,
+    pub scroll: Res<'a, ScrollSpeed>,
+    pub gauge: Res<'a, LifeGauge>,
+    pub no_fail: Res<'a, NoFail>,
+    pub modifiers: Res<'a, Modifiers>,
+    pub practice: Res<'a, PracticeMode>,
+    pub export: Res<'a, SessionExport>,
+    pub timing_display: Res<'a, TimingDisplay>,
+}
+
+/// Same as [`Settings`], but for changing them.
+#[derive(SystemParam)]
+pub struct SettingsMut<'a> {
+    pub rate: ResMut<'a, PlaybackRate>,
+    pub orientation: ResMut<'a, Orientation>,
+    pub scroll: ResMut<'a, ScrollSpeed>,
+    pub gauge: ResMut<'a, LifeGauge>,
+    pub no_fail: ResMut<'a, NoFail>,
+    pub modifiers: ResMut<'a, Modifiers>,
+    pub practice: ResMut<'a, PracticeMode>,
+    pub export: ResMut<'a, SessionExport>,
+    pub timing_display: ResMut<'a, TimingDisplay>,
+}
@@ -1,3 +1,4 @@
+use crate::consts::*;
 use bevy::{
     prelude::*,
     reflect::TypeUuid,
@@ -28,6 +29,16 @@ impl Plugin for ShadersPlugin {
             .add_startup_system(setup_background.system())
             .add_system(update_background_size.system())
             .add_startup_system(setup_target_arrows.system())
+            .on_state_enter(
+                APP_STATE_STAGE,
+                AppState::Game,
+                spawn_target_sparkles.system(),
+            )
+            .on_state_exit(
+                APP_STATE_STAGE,
+                AppState::Game,
+                despawn_target_sparkles.system(),
+            )
             .add_system(correct_arrow_event_listener.system());
     }
 }
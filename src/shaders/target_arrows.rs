@@ -1,8 +1,6 @@
 use super::ShaderInputs;
 use crate::{
-    arrows::CorrectArrowEvent,
-    consts::*,
-    types::Directions::{self, *},
+    arrows::CorrectArrowEvent, consts::*, lanes::Lane, playfield::Orientation, types::SongConfig,
 };
 use bevy::{
     prelude::*,
@@ -16,12 +14,15 @@ use bevy::{
 };
 
 /// A component for the sparkles that appear around a target arrow when successfully
-/// hitting an arrow. Keeps track of the direction of the target.
+/// hitting an arrow. Keeps track of the lane of the target.
 #[derive(Debug)]
 pub struct TargetArrowSparkle {
-    direction: Directions,
+    lane: Lane,
 }
 
+/// The pipeline that the sparkles get drawn with.
+pub struct TargetArrowPipeline(Handle<PipelineDescriptor>);
+
 /// A struct to be passed to a shader as a parameter which keeps track of when
 /// how long ago the player last hit an arrow, and how many points it was worth.
 #[derive(RenderResources, TypeUuid)]
@@ -31,13 +32,12 @@ pub struct TimeSinceLastCorrect {
     points: f32,
 }
 
-/// Setup the fancy target arrows.
+/// Sets up the shader pipeline for the fancy target arrows.
 pub fn setup_target_arrows(
     commands: &mut Commands,
     mut pipelines: ResMut<Assets<PipelineDescriptor>>,
     mut shaders: ResMut<Assets<Shader>>,
     mut render_graph: ResMut<RenderGraph>,
-    window: Res<WindowDescriptor>,
 ) {
     // Create a new shader pipeline.
     let pipeline_handle = pipelines.add(PipelineDescriptor::default_config(ShaderStages {
@@ -50,6 +50,7 @@ pub fn setup_target_arrows(
             include_str!("target_arrows.frag"),
         ))),
     }));
+    commands.insert_resource(TargetArrowPipeline(pipeline_handle));
 
     // Add `TimeSinceLastCorrect` to the render graph.
     render_graph.add_system_node(
@@ -59,20 +60,34 @@ pub fn setup_target_arrows(
     render_graph
         .add_node_edge("last_time", base::node::MAIN_PASS)
         .unwrap();
+}
 
-    // Different z values so they don't overlap
-    const DIRECTIONS_AND_Z_VALUES: [(Directions, f32); 4] =
-        [(Up, 0.3), (Down, 0.4), (Left, 0.5), (Right, 0.6)];
+/// Spawns a fancy target arrow for each of the song's lanes.
+pub fn spawn_target_sparkles(
+    commands: &mut Commands,
+    pipeline: Res<TargetArrowPipeline>,
+    song_config: Res<SongConfig>,
+    orientation: Res<Orientation>,
+    windows: Res<Windows>,
+) {
+    let lanes = song_config.lanes;
+    let resolution = windows
+        .get_primary()
+        .map_or(Vec2::new(4.0 / 3.0, 1.0), |window| {
+            Vec2::new(window.width() / window.height(), 1.0)
+        });
 
-    for (direction, z) in DIRECTIONS_AND_Z_VALUES.iter() {
+    for lane in lanes.iter() {
+        // Different z values so they don't overlap
+        let z = 0.3 + 0.05 * lane as f32;
         let mut transform =
-            Transform::from_translation(Vec3::new(TARGET_POSITION, direction.y(), *z));
-        transform.scale = Vec3::new(300.0, 300.0, 1.0);
+            Transform::from_translation(orientation.translation(TARGET_POSITION, lanes.y(lane), z));
+        transform.scale = Vec3::new(300.0 * lanes.scale(), 300.0 * lanes.scale(), 1.0);
 
         commands
             .spawn(SpriteBundle {
                 render_pipelines: RenderPipelines::from_pipelines(vec![RenderPipeline::new(
-                    pipeline_handle.clone(),
+                    pipeline.0.clone(),
                 )]),
                 transform,
                 visible: Visible {
@@ -81,20 +96,28 @@ pub fn setup_target_arrows(
                 },
                 ..Default::default()
             })
-            .with(TargetArrowSparkle {
-                direction: *direction,
-            })
+            .with(TargetArrowSparkle { lane })
             .with(TimeSinceLastCorrect {
                 last_time: -10.0,
                 points: 0.0,
             })
             .with(ShaderInputs {
                 time: 0.0,
-                resolution: Vec2::new(window.width / window.height, 1.0),
+                resolution,
             });
     }
 }
 
+/// Removes the fancy target arrows when leaving a game.
+pub fn despawn_target_sparkles(
+    commands: &mut Commands,
+    query: Query<Entity, With<TargetArrowSparkle>>,
+) {
+    for entity in query.iter() {
+        commands.despawn(entity);
+    }
+}
+
 pub fn correct_arrow_event_listener(
     time: Res<Time>,
     mut correct_event_reader: Local<EventReader<CorrectArrowEvent>>,
@@ -103,7 +126,7 @@ pub fn correct_arrow_event_listener(
 ) {
     for event in correct_event_reader.iter(&correct_events) {
         for (arrow, mut last_correct) in query.iter_mut() {
-            if arrow.direction == event.direction {
+            if arrow.lane == event.lane {
                 last_correct.last_time = time.seconds_since_startup() as f32;
                 last_correct.points = event.points as f32 / 100.0;
             }
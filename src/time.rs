@@ -11,10 +11,16 @@ pub struct TimePlugin;
 impl Plugin for TimePlugin {
     fn build(&self, app: &mut AppBuilder) {
         app.init_resource::<ControlledTime>()
+            .init_resource::<SongStart>()
+            .init_resource::<PlaybackRate>()
             .on_state_update(APP_STATE_STAGE, AppState::Game, update_time.system())
             .on_state_update(APP_STATE_STAGE, AppState::MakeMap, update_time.system())
-            .on_state_enter(APP_STATE_STAGE, AppState::Game, reset_time.system())
-            .on_state_enter(APP_STATE_STAGE, AppState::MakeMap, reset_time.system());
+            .on_state_enter(APP_STATE_STAGE, AppState::Game, reset_game_time.system())
+            .on_state_enter(
+                APP_STATE_STAGE,
+                AppState::MakeMap,
+                reset_map_maker_time.system(),
+            );
     }
 }
 
@@ -26,14 +32,38 @@ pub struct ControlledTime {
     delta_seconds_f64: f64,
     delta_seconds: f32,
     seconds_since_startup: f64,
-    startup: Instant,
+    paused: bool,
+    rate: f64,
 }
 
 impl ControlledTime {
-    /// Reset tracked time to 0.
-    pub fn reset_time(&mut self) {
-        self.startup = Instant::now();
-        self.seconds_since_startup = 0.0;
+    /// Jump to some point in time, in seconds.
+    pub fn set_seconds_since_startup(&mut self, seconds: f64) {
+        self.last_update = Some(Instant::now());
+        self.delta = Duration::from_secs(0);
+        self.delta_seconds_f64 = 0.0;
+        self.delta_seconds = 0.0;
+        self.seconds_since_startup = seconds;
+    }
+
+    /// Stop time from moving forwards.
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Let time move forwards again.
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    /// Returns true if time isn't moving forwards.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Make time move faster or slower than real time.
+    pub fn set_rate(&mut self, rate: f64) {
+        self.rate = rate;
     }
 
     /// Update the tracked time to be the current time.
@@ -44,14 +74,17 @@ impl ControlledTime {
 
     /// Update the tracked time with some instant in time.
     pub fn update_with_instant(&mut self, instant: Instant) {
-        if let Some(last_update) = self.last_update {
-            self.delta = instant - last_update;
+        if self.paused {
+            self.delta = Duration::from_secs(0);
+            self.delta_seconds_f64 = 0.0;
+            self.delta_seconds = 0.0;
+        } else if let Some(last_update) = self.last_update {
+            self.delta = (instant - last_update).mul_f64(self.rate);
             self.delta_seconds_f64 = self.delta.as_secs_f64();
             self.delta_seconds = self.delta.as_secs_f32();
+            self.seconds_since_startup += self.delta_seconds_f64;
         }
 
-        let duration_since_startup = instant - self.startup;
-        self.seconds_since_startup = duration_since_startup.as_secs_f64();
         self.last_update = Some(instant);
     }
 
@@ -61,12 +94,6 @@ impl ControlledTime {
         self.delta_seconds
     }
 
-    /// The delta between the current and last tick as [`f64`] seconds
-    #[inline]
-    pub fn delta_seconds_f64(&self) -> f64 {
-        self.delta_seconds_f64
-    }
-
     /// The time since startup in seconds
     #[inline]
     pub fn seconds_since_startup(&self) -> f64 {
@@ -79,10 +106,11 @@ impl Default for ControlledTime {
         Self {
             delta: Duration::from_secs(0),
             last_update: None,
-            startup: Instant::now(),
             delta_seconds_f64: 0.0,
             delta_seconds: 0.0,
             seconds_since_startup: 0.0,
+            paused: false,
+            rate: 1.0,
         }
     }
 }
@@ -92,7 +120,122 @@ pub fn update_time(mut time: ResMut<ControlledTime>) {
     time.update();
 }
 
-/// Resets the current time. To be used when entering a game, or the map editor.
-pub fn reset_time(mut time: ResMut<ControlledTime>) {
-    time.reset_time();
+/// How fast songs play, as a multiple of their normal speed. Applies to both
+/// games and the map maker.
+#[derive(Debug, Clone, Copy)]
+pub struct PlaybackRate(pub f64);
+
+impl Default for PlaybackRate {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+impl PlaybackRate {
+    /// Rates that can be picked from.
+    const STEPS: [f64; 7] = [0.5, 0.75, 0.9, 1.0, 1.25, 1.5, 2.0];
+
+    /// Switches to the next slowest rate, if there is one.
+    pub fn slower(&mut self) {
+        if let Some(rate) = Self::STEPS.iter().rev().find(|rate| **rate < self.0) {
+            self.0 = *rate;
+        }
+    }
+
+    /// Switches to the next fastest rate, if there is one.
+    pub fn faster(&mut self) {
+        if let Some(rate) = Self::STEPS.iter().find(|rate| **rate > self.0) {
+            self.0 = *rate;
+        }
+    }
+
+    /// Returns true if songs play slower than normal.
+    pub fn is_slowed(&self) -> bool {
+        self.0 < 1.0
+    }
+
+    /// Switches to the next fastest rate, going back to the slowest one after
+    /// the fastest.
+    pub fn cycle(&mut self) {
+        if self.0 >= Self::STEPS[Self::STEPS.len() - 1] {
+            self.0 = Self::STEPS[0];
+        } else {
+            self.faster();
+        }
+    }
+}
+
+/// Where in the song to start from when entering a game or the map maker.
+#[derive(Debug, Clone, Copy)]
+pub struct SongStart {
+    /// Song time to start from, in seconds.
+    pub time: f64,
+    /// How long to wait in a game before reaching that time, in seconds.
+    pub lead_in: f64,
+}
+
+impl Default for SongStart {
+    fn default() -> Self {
+        Self {
+            time: 0.0,
+            lead_in: SONG_START_DELAY,
+        }
+    }
+}
+
+/// Resets the current time when entering a game, so that the song starts at
+/// the right place.
+pub fn reset_game_time(
+    mut time: ResMut<ControlledTime>,
+    start: Res<SongStart>,
+    rate: Res<PlaybackRate>,
+) {
+    time.resume();
+    time.set_rate(rate.0);
+    time.set_seconds_since_startup(SONG_START_DELAY + start.time - start.lead_in);
+}
+
+/// Resets the current time when entering the map maker.
+pub fn reset_map_maker_time(
+    mut time: ResMut<ControlledTime>,
+    start: Res<SongStart>,
+    rate: Res<PlaybackRate>,
+) {
+    time.resume();
+    time.set_rate(rate.0);
+    time.set_seconds_since_startup(start.time);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_carries_on_from_where_it_was_set() {
+        let mut time = ControlledTime::default();
+        time.set_seconds_since_startup(5.0);
+        assert_eq!(time.seconds_since_startup(), 5.0);
+        assert_eq!(time.delta_seconds(), 0.0);
+
+        time.update_with_instant(Instant::now() + Duration::from_secs(1));
+        let secs = time.seconds_since_startup();
+        assert!((6.0..6.1).contains(&secs), "{}", secs);
+    }
+
+    #[test]
+    fn paused_time_stands_still() {
+        let mut time = ControlledTime::default();
+        time.set_seconds_since_startup(2.0);
+        time.pause();
+        let later = Instant::now() + Duration::from_secs(1);
+        time.update_with_instant(later);
+        assert!(time.is_paused());
+        assert_eq!(time.seconds_since_startup(), 2.0);
+        assert_eq!(time.delta_seconds(), 0.0);
+
+        // Time spent paused doesn't get caught up on after resuming
+        time.resume();
+        time.update_with_instant(later + Duration::from_millis(500));
+        assert_eq!(time.seconds_since_startup(), 2.5);
+    }
 }
@@ -0,0 +1,220 @@
+use serde_derive::{Deserialize, Serialize};
+
+/// Tempo used for charts that don't say what theirs is.
+pub const DEFAULT_BPM: f64 = 120.0;
+
+/// A change in tempo, starting at some beat.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct BpmChange {
+    pub beat: f64,
+    pub bpm: f64,
+}
+
+/// A pause in the song's beat, starting at some beat and lasting for some
+/// amount of seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Stop {
+    pub beat: f64,
+    pub duration: f64,
+}
+
+/// A change in how fast every arrow scrolls, starting at some beat. Arrows
+/// move at `scroll` times their usual speed until the next change, so 2.0
+/// doubles their speed and 0.0 freezes them in place.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ScrollChange {
+    pub beat: f64,
+    pub scroll: f64,
+}
+
+/// Tempo information for a song. Lets us convert between beats and seconds.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Timing {
+    /// Time at which beat 0 happens, in seconds.
+    #[serde(default)]
+    pub offset: f64,
+    /// Tempo changes, sorted by beat. The first one is used for any beat before
+    /// it.
+    pub bpms: Vec<BpmChange>,
+    /// Stops, sorted by beat.
+    #[serde(default)]
+    pub stops: Vec<Stop>,
+    /// Scroll speed changes, sorted by beat. Arrows scroll at their usual
+    /// speed before the first one.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scrolls: Vec<ScrollChange>,
+}
+
+impl Timing {
+    /// Timing for a song that stays at the same tempo the whole way through.
+    pub fn constant(bpm: f64, offset: f64) -> Self {
+        Self {
+            offset,
+            bpms: vec![BpmChange { beat: 0.0, bpm }],
+            stops: Vec::new(),
+            scrolls: Vec::new(),
+        }
+    }
+
+    /// The tempo at the very start of the song.
+    pub fn initial_bpm(&self) -> f64 {
+        self.bpms.first().map_or(DEFAULT_BPM, |change| change.bpm)
+    }
+
+    /// The tempo at some beat.
+    pub fn bpm_at(&self, beat: f64) -> f64 {
+        self.bpms
+            .iter()
+            .skip(1)
+            .take_while(|change| change.beat <= beat)
+            .last()
+            .map_or(self.initial_bpm(), |change| change.bpm)
+    }
+
+    /// The same timing, but with every beat numbered `beats` higher. Beat 0
+    /// gets moved earlier to make up for it, so nothing changes time.
+    pub fn shifted(&self, beats: f64) -> Self {
+        let mut bpms = self
+            .bpms
+            .iter()
+            .map(|change| BpmChange {
+                beat: change.beat + beats,
+                ..*change
+            })
+            .collect::<Vec<_>>();
+        // The first tempo still covers everything before it.
+        if let Some(first) = bpms.first_mut() {
+            first.beat = 0.0;
+        }
+
+        Self {
+            offset: self.offset - beats * 60.0 / self.initial_bpm(),
+            bpms,
+            stops: self
+                .stops
+                .iter()
+                .map(|stop| Stop {
+                    beat: stop.beat + beats,
+                    ..*stop
+                })
+                .collect(),
+            scrolls: self
+                .scrolls
+                .iter()
+                .map(|change| ScrollChange {
+                    beat: change.beat + beats,
+                    ..*change
+                })
+                .collect(),
+        }
+    }
+
+    /// Converts a beat into a time in seconds.
+    pub fn beat_to_time(&self, beat: f64) -> f64 {
+        let mut time = self.offset;
+        let mut last_beat = 0.0;
+        let mut bpm = self.initial_bpm();
+
+        for change in self.bpms.iter().skip(1) {
+            if change.beat >= beat {
+                break;
+            }
+
+            time += (change.beat - last_beat) * 60.0 / bpm;
+            last_beat = change.beat;
+            bpm = change.bpm;
+        }
+
+        time += (beat - last_beat) * 60.0 / bpm;
+
+        // Notes on a stop get hit right as the stop starts.
+        time + self
+            .stops
+            .iter()
+            .filter(|stop| stop.beat < beat)
+            .map(|stop| stop.duration)
+            .sum::<f64>()
+    }
+
+    /// Converts a time in seconds into a beat.
+    pub fn time_to_beat(&self, time: f64) -> f64 {
+        let mut elapsed = self.offset;
+        let mut beat = 0.0;
+        let mut bpm = self.initial_bpm();
+
+        let mut bpms = self.bpms.iter().skip(1).peekable();
+        let mut stops = self.stops.iter().peekable();
+
+        loop {
+            let next_bpm = bpms.peek().map_or(f64::INFINITY, |change| change.beat);
+            let next_stop = stops.peek().map_or(f64::INFINITY, |stop| stop.beat);
+            let next_beat = next_bpm.min(next_stop);
+
+            if next_beat.is_infinite() {
+                return beat + (time - elapsed) * bpm / 60.0;
+            }
+
+            let time_at_next = elapsed + (next_beat - beat) * 60.0 / bpm;
+            if time < time_at_next {
+                return beat + (time - elapsed) * bpm / 60.0;
+            }
+
+            elapsed = time_at_next;
+            beat = next_beat;
+
+            // Tempo changes get applied before any stop on the same beat.
+            if next_bpm <= next_stop {
+                bpm = bpms.next().unwrap().bpm;
+            } else {
+                let stop = stops.next().unwrap();
+                if time < elapsed + stop.duration {
+                    return beat;
+                }
+                elapsed += stop.duration;
+            }
+        }
+    }
+}
+
+/// Where a song's scroll speed changes put arrows. Instead of going by time,
+/// arrows go by a scroll position that moves faster or slower than time when
+/// the scroll speed is changed, and is the same as time when it isn't.
+#[derive(Clone, Debug, Default)]
+pub struct ScrollTrack {
+    /// When each change happens in seconds, the position at that time, and
+    /// the scroll speed from then on.
+    changes: Vec<(f64, f64, f64)>,
+}
+
+impl ScrollTrack {
+    pub fn new(timing: Option<&Timing>) -> Self {
+        let timing = match timing {
+            Some(timing) => timing,
+            None => return Self::default(),
+        };
+
+        let mut changes = Vec::new();
+        for change in timing.scrolls.iter() {
+            let time = timing.beat_to_time(change.beat);
+            let position = Self::position_in(&changes, time);
+            changes.push((time, position, change.scroll.max(0.0)));
+        }
+
+        Self { changes }
+    }
+
+    /// The scroll position at some time in seconds.
+    pub fn position(&self, time: f64) -> f64 {
+        Self::position_in(&self.changes, time)
+    }
+
+    fn position_in(changes: &[(f64, f64, f64)], time: f64) -> f64 {
+        changes
+            .iter()
+            .take_while(|(start, _, _)| *start <= time)
+            .last()
+            .map_or(time, |(start, position, scroll)| {
+                position + (time - start) * scroll
+            })
+    }
+}
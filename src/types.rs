@@ -1,45 +1,29 @@
-use crate::consts::*;
-use bevy::{
-    input::{keyboard::KeyCode, Input},
-    prelude::*,
+use crate::{
+    consts::*,
+    lanes::{deserialize_lane, Lane, Lanes},
+    playfield::ScrollSpeed,
+    timing::{ScrollTrack, Timing},
 };
+use bevy::{prelude::*, reflect::TypeUuid};
 use core::f32::consts::PI;
 use serde_derive::{Deserialize, Serialize};
+use std::convert::TryFrom;
 use std::fs::File;
 use std::io::prelude::*;
+use std::path::Path;
 
-#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+/// Which way an arrow points.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Directions {
     Up,
     Down,
     Left,
     Right,
+    UpLeft,
+    UpRight,
 }
 
 impl Directions {
-    fn to_keycodes(&self) -> [KeyCode; 2] {
-        match self {
-            Self::Up => [KeyCode::Up, KeyCode::W],
-            Self::Down => [KeyCode::Down, KeyCode::S],
-            Self::Left => [KeyCode::Left, KeyCode::A],
-            Self::Right => [KeyCode::Right, KeyCode::D],
-        }
-    }
-
-    /// Checks if a key corresponding to this direction has *just* been pressed.
-    pub fn key_just_pressed(&self, input: &Input<KeyCode>) -> bool {
-        let keys = self.to_keycodes();
-
-        keys.iter().any(|code| input.just_pressed(*code))
-    }
-
-    /// Checks if a key corresponding to this direction is *currently* being pressed.
-    pub fn key_pressed(&self, input: &Input<KeyCode>) -> bool {
-        let keys = self.to_keycodes();
-
-        keys.iter().any(|code| input.pressed(*code))
-    }
-
     /// Returns the correct rotation for an arrow with this direction
     pub fn rotation(&self) -> f32 {
         match self {
@@ -47,28 +31,24 @@ impl Directions {
             Self::Down => -PI / 2.0,
             Self::Left => PI,
             Self::Right => 0.0,
-        }
-    }
-
-    /// Returns the correct y coordinate for an arrow with this direction
-    pub fn y(&self) -> f32 {
-        match self {
-            Self::Up => 150.0,
-            Self::Down => 50.0,
-            Self::Left => -50.0,
-            Self::Right => -150.0,
+            Self::UpLeft => PI * 3.0 / 4.0,
+            Self::UpRight => PI / 4.0,
         }
     }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
-pub enum Speed {
-    Slow,
-    Medium,
-    Fast,
-}
+/// How fast an arrow moves, as a multiple of [`BASE_SPEED`]. Gets written to
+/// charts as a number, but charts from before speeds could be any number used
+/// the names `Slow`, `Medium` and `Fast`, which still work.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "SpeedToml", into = "f64")]
+pub struct Speed(pub f64);
 
 impl Speed {
+    pub const SLOW: Self = Self(1.0);
+    pub const MEDIUM: Self = Self(1.2);
+    pub const FAST: Self = Self(1.5);
+
     /// Returns the actual speed at which arrows should move.
     pub fn value(&self) -> f32 {
         BASE_SPEED * self.multiplier()
@@ -76,86 +56,353 @@ impl Speed {
 
     /// Speed multiplier.
     pub fn multiplier(&self) -> f32 {
-        match self {
-            Self::Slow => 1.0,
-            Self::Medium => 1.2,
-            Self::Fast => 1.5,
+        self.0 as f32
+    }
+}
+
+/// A speed as it's written in a chart.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SpeedToml {
+    Multiplier(f64),
+    Name(String),
+}
+
+impl TryFrom<SpeedToml> for Speed {
+    type Error = String;
+
+    fn try_from(speed: SpeedToml) -> Result<Self, Self::Error> {
+        match speed {
+            SpeedToml::Multiplier(multiplier) if multiplier > 0.0 => Ok(Self(multiplier)),
+            SpeedToml::Multiplier(multiplier) => {
+                Err(format!("speeds have to be above 0, not {}", multiplier))
+            }
+            SpeedToml::Name(name) => match name.as_str() {
+                "Slow" => Ok(Self::SLOW),
+                "Medium" => Ok(Self::MEDIUM),
+                "Fast" => Ok(Self::FAST),
+                _ => Err(format!("unknown speed {}", name)),
+            },
         }
     }
 }
 
+impl From<Speed> for f64 {
+    fn from(speed: Speed) -> Self {
+        speed.0
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
-/// Keeps track of when each arrow should spawn, its speed, and its direction.
+/// Keeps track of when each arrow should be hit, its speed, and its lane.
 pub struct ArrowTime {
-    pub spawn_time: f64,
+    pub click_time: f64,
+    /// Where the song's scroll position is when the arrow should be hit. See
+    /// [`ScrollTrack`].
+    pub click_position: f64,
     pub speed: Speed,
-    pub direction: Directions,
+    pub lane: Lane,
+    /// How long the arrow has to be held down for, if it's a hold.
+    pub hold: Option<f64>,
+    /// Mines have to be left alone instead of hit.
+    pub mine: bool,
 }
 
 impl ArrowTime {
-    fn new(arrow: &ArrowTimeToml) -> Self {
-        let speed_value = arrow.speed.value();
+    fn new(arrow: &ArrowTimeToml, scroll: &ScrollTrack) -> Self {
         Self {
-            spawn_time: arrow.click_time - (DISTANCE / speed_value) as f64,
+            click_time: arrow.click_time,
+            click_position: scroll.position(arrow.click_time),
             speed: arrow.speed,
-            direction: arrow.direction,
+            lane: arrow.lane,
+            hold: arrow.hold,
+            mine: arrow.mine,
         }
     }
+
+    /// The scroll position the arrow needs to spawn at to reach its target in
+    /// time, which depends on how fast the player has arrows scroll.
+    pub fn spawn_position(&self, scroll: ScrollSpeed) -> f64 {
+        self.click_position - (DISTANCE / scroll.speed(self.speed)) as f64
+    }
 }
 
 /// Keeps track of a list of all the arrows in a song, including their speeds
-/// and their directions and when they should be clicked or spawned.
-#[derive(Debug)]
+/// and their lanes and when they should be clicked or spawned.
+///
+/// Chart files get loaded into these by the asset server, see
+/// [`ChartLoader`](crate::chart::ChartLoader).
+#[derive(Debug, Clone, TypeUuid)]
+#[uuid = "6f0f7e0a-3c1b-4d5e-9a86-2b7d0c4e91f3"]
 pub struct SongConfig {
+    /// The chart file this was loaded from, relative to `assets/songs`. Charts
+    /// played straight out of the editor don't have one.
+    pub file: Option<String>,
     pub name: String,
+    /// Name of the difficulty being played.
+    pub difficulty: String,
+    pub lanes: Lanes,
     pub song_audio: Handle<AudioSource>,
     pub arrows: Vec<ArrowTime>,
+    /// When the last arrow or hold ends, in seconds.
+    pub end_time: f64,
+    /// The song's scroll speed changes.
+    pub scroll: ScrollTrack,
+    /// The song's tempo, if the chart says what it is.
+    pub timing: Option<Timing>,
 }
 
 /// An `ArrowTime` that stores the click time instead of the spawn time. Used
 /// for TOML songfiles.
-#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
 pub struct ArrowTimeToml {
     pub click_time: f64,
     pub speed: Speed,
-    pub direction: Directions,
+    /// Charts from before there were lanes stored a direction here instead.
+    #[serde(alias = "direction", deserialize_with = "deserialize_lane")]
+    pub lane: Lane,
+    /// How long the arrow has to be held down for after being hit, in
+    /// seconds. Left out for normal arrows.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hold: Option<f64>,
+    /// Mines hurt when their key gets pressed as they pass the target, so
+    /// they have to be left alone. Mines can't be holds.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub mine: bool,
+}
+
+/// Difficulty name given to charts from before songs could have more than one.
+pub const DEFAULT_DIFFICULTY: &str = "Normal";
+
+/// One of a song's charts.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DifficultyToml {
+    /// Name of the difficulty, like "Easy" or "Expert".
+    pub name: String,
+    /// How hard the chart is. Higher is harder.
+    #[serde(default)]
+    pub level: u32,
+    #[serde(default)]
+    pub lanes: Lanes,
+    pub arrows: Vec<ArrowTimeToml>,
+    /// Tempo information for just this difficulty, if it doesn't follow the
+    /// song's. `.ssc` charts can have their own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timing: Option<Timing>,
 }
 
 /// A `SongConfig` that stores the click times instead of the spawn times. Used
-/// for TOML songfiles.
-#[derive(Deserialize, Debug)]
-struct SongConfigToml {
+/// for TOML songfiles. Holds every difficulty of a song.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct SongConfigToml {
     pub name: String,
     pub filename: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    /// Who made the chart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub charter: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub genre: Option<String>,
+    /// BPM to show in song select, like "150" or "120-240". Worked out from
+    /// the timing when left out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bpm_display: Option<String>,
+    /// Where the part of the song that gets previewed in song select starts,
+    /// in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview_start: Option<f64>,
+    /// How long the preview lasts, in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview_length: Option<f64>,
+    /// Image files, relative to the chart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub background_image: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jacket: Option<String>,
+    /// Arrows from charts made before there were difficulties. These get moved
+    /// into a difficulty of their own when the chart is parsed.
+    #[serde(default, skip_serializing)]
     pub arrows: Vec<ArrowTimeToml>,
+    /// How many lanes those arrows are in. The map maker still saves charts
+    /// like this.
+    #[serde(default, skip_serializing)]
+    pub lanes: Lanes,
+    #[serde(default)]
+    pub difficulties: Vec<DifficultyToml>,
+    /// Tempo information. Only used for lining things up to beats, so older
+    /// charts can leave it out.
+    pub timing: Option<Timing>,
+}
+
+impl SongConfigToml {
+    /// Parses a TOML chart.
+    pub fn from_toml(text: &str) -> Result<Self, toml::de::Error> {
+        let mut chart: Self = toml::from_str(text)?;
+
+        if !chart.arrows.is_empty() {
+            let arrows = std::mem::take(&mut chart.arrows);
+            chart.difficulties.insert(
+                0,
+                DifficultyToml {
+                    name: DEFAULT_DIFFICULTY.to_string(),
+                    level: 0,
+                    lanes: chart.lanes,
+                    arrows,
+                    timing: None,
+                },
+            );
+        }
+
+        for difficulty in chart.difficulties.iter() {
+            let lanes = difficulty.lanes.count();
+            if difficulty.arrows.iter().any(|arrow| arrow.lane >= lanes) {
+                return Err(serde::de::Error::custom(format!(
+                    "{} has arrows outside of its {} lanes",
+                    difficulty.name, lanes
+                )));
+            }
+            if difficulty
+                .arrows
+                .iter()
+                .any(|arrow| arrow.mine && arrow.hold.is_some())
+            {
+                return Err(serde::de::Error::custom(format!(
+                    "{} has mines that are holds",
+                    difficulty.name
+                )));
+            }
+        }
+
+        Ok(chart)
+    }
+
+    /// The BPM to show for this song. Songs that change tempo show the range
+    /// it goes between.
+    pub fn bpm_label(&self) -> Option<String> {
+        if let Some(bpm) = &self.bpm_display {
+            return Some(bpm.clone());
+        }
+
+        let bpms = &self.timing.as_ref()?.bpms;
+        let min = bpms
+            .iter()
+            .map(|change| change.bpm)
+            .fold(f64::INFINITY, f64::min);
+        let max = bpms.iter().map(|change| change.bpm).fold(0.0, f64::max);
+
+        if bpms.is_empty() {
+            None
+        } else if (max - min).abs() < 0.5 {
+            Some(format!("{:.0}", max))
+        } else {
+            Some(format!("{:.0}-{:.0}", min, max))
+        }
+    }
+
+    /// Tempo information for one of the song's difficulties: its own if it
+    /// has any, or else the song's.
+    pub fn timing_for<'a>(&'a self, difficulty: &'a DifficultyToml) -> Option<&'a Timing> {
+        difficulty.timing.as_ref().or(self.timing.as_ref())
+    }
+
+    /// How long the song is played for, in seconds. That's until the last
+    /// arrow or hold of its longest difficulty ends.
+    pub fn length(&self) -> f64 {
+        self.difficulties
+            .iter()
+            .flat_map(|difficulty| difficulty.arrows.iter())
+            .map(|arrow| arrow.click_time + arrow.hold.unwrap_or(0.0))
+            .fold(0.0, f64::max)
+    }
+
+    /// Reads a chart from a file in `assets/songs`.
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        // Open file and read contents
+        let mut file = File::open(format!("assets/songs/{}", path))?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+
+        // Parse using toml and Serde
+        Self::from_toml(&contents)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+    }
+
+    /// Writes this chart to a file in `assets/songs`.
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let text = toml::to_string(self)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+
+        let mut file = File::create(format!("assets/songs/{}", path))?;
+        file.write_all(text.as_bytes())
+    }
 }
 
-pub fn load_config(path: &str, asset_server: &AssetServer) -> SongConfig {
-    // Open file and read contents
-    let mut file = File::open(format!("assets/songs/{}", path)).expect("Couldn't open file");
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)
-        .expect("Couldn't read file into a UTF-8 string");
-
-    // Parse using toml and Serde
-    let parsed: SongConfigToml = toml::from_str(&contents).expect("Couldn't parse file as TOML");
-
-    // Process arrows
-    let mut arrows = parsed
-        .arrows
-        .iter()
-        .map(|arr| ArrowTime::new(arr))
-        .collect::<Vec<_>>();
-
-    // Sort arrows by spawn time
-    arrows.sort_unstable_by(|a, b| a.spawn_time.partial_cmp(&b.spawn_time).unwrap());
-
-    // Load song audio and get the handle
-    let song_audio = asset_server.load(&*format!("songs/{}", parsed.filename));
-
-    SongConfig {
-        name: parsed.name,
-        song_audio,
-        arrows,
+impl SongConfig {
+    /// Processes one of a chart's difficulties into something that can be
+    /// played. `file` is where the chart is, relative to `assets/songs`, and
+    /// its audio is loaded from the same folder.
+    pub fn new(
+        parsed: &SongConfigToml,
+        difficulty: &DifficultyToml,
+        file: &str,
+        asset_server: &AssetServer,
+    ) -> Self {
+        let audio_path = Path::new("songs")
+            .join(file)
+            .parent()
+            .map(|dir| dir.join(&parsed.filename))
+            .unwrap_or_default();
+        let song_audio = asset_server.load(audio_path);
+
+        Self::with_audio(parsed, difficulty, song_audio)
+    }
+
+    /// Processes one of a chart's difficulties into something that can be
+    /// played, with its audio already being loaded.
+    pub fn with_audio(
+        parsed: &SongConfigToml,
+        difficulty: &DifficultyToml,
+        song_audio: Handle<AudioSource>,
+    ) -> Self {
+        // Process arrows
+        let timing = parsed.timing_for(difficulty);
+        let scroll = ScrollTrack::new(timing);
+        let arrows = difficulty
+            .arrows
+            .iter()
+            .map(|arr| ArrowTime::new(arr, &scroll))
+            .collect::<Vec<_>>();
+
+        let end_time = difficulty
+            .arrows
+            .iter()
+            .map(|arrow| arrow.click_time + arrow.hold.unwrap_or(0.0))
+            .fold(0.0, f64::max);
+
+        let mut config = SongConfig {
+            file: None,
+            name: parsed.name.clone(),
+            difficulty: difficulty.name.clone(),
+            lanes: difficulty.lanes,
+            song_audio,
+            arrows,
+            end_time,
+            scroll,
+            timing: timing.cloned(),
+        };
+        config.sort_arrows(ScrollSpeed::default());
+        config
+    }
+
+    /// Sorts the arrows by when they spawn at some scroll speed. Arrows with
+    /// different speeds can swap places when the scroll speed changes, so
+    /// this needs doing before each game.
+    pub fn sort_arrows(&mut self, scroll: ScrollSpeed) {
+        self.arrows.sort_unstable_by(|a, b| {
+            a.spawn_position(scroll)
+                .partial_cmp(&b.spawn_position(scroll))
+                .unwrap()
+        });
     }
 }
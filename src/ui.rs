@@ -7,7 +7,8 @@ impl Plugin for UIPlugin {
     fn build(&self, app: &mut AppBuilder) {
         app.on_state_enter(APP_STATE_STAGE, AppState::Game, setup_ui.system())
             .on_state_update(APP_STATE_STAGE, AppState::Game, update_time_text.system())
-            .on_state_update(APP_STATE_STAGE, AppState::Game, update_score_text.system());
+            .on_state_update(APP_STATE_STAGE, AppState::Game, update_score_text.system())
+            .on_state_exit(APP_STATE_STAGE, AppState::Game, despawn_ui.system());
     }
 }
 
@@ -34,6 +35,7 @@ fn setup_ui(
             material: material.clone(),
             ..Default::default()
         })
+        .with(GameUI)
         .with_children(|parent| {
             parent
                 .spawn(TextBundle {
@@ -64,11 +66,12 @@ fn setup_ui(
             material,
             ..Default::default()
         })
+        .with(GameUI)
         .with_children(|parent| {
             parent
                 .spawn(TextBundle {
                     text: Text {
-                        value: format!("Score: 0  |  Corrects: 0  |  Fails: 0"),
+                        value: "Score: 0  |  Corrects: 0  |  Fails: 0".to_string(),
                         font,
                         style: TextStyle {
                             font_size: 40.0,
@@ -82,6 +85,16 @@ fn setup_ui(
         });
 }
 
+/// A marker for all of the in-game UI.
+struct GameUI;
+
+/// Removes the in-game UI when leaving a game.
+fn despawn_ui(commands: &mut Commands, query: Query<Entity, With<GameUI>>) {
+    for entity in query.iter() {
+        commands.despawn_recursive(entity);
+    }
+}
+
 struct TimeText;
 
 fn update_time_text(time: Res<ControlledTime>, mut query: Query<(&mut Text, &TimeText)>) {